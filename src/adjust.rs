@@ -0,0 +1,326 @@
+//! Split and dividend adjustment of price series.
+//!
+//! Providers can adjust bars themselves ([`Adjustment`] is passed along with
+//! each request), or raw bars can be stored once and adjusted locally from a
+//! corporate-actions file with [`Adjuster`]. Adjustment runs backwards from
+//! the latest action: bars before a split's ex-date have their prices divided
+//! and volume multiplied by the split ratio, and bars before a dividend's
+//! ex-date have their prices scaled by `1 - dividend / previous close`.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::Agg;
+use crate::compress::open_reader;
+
+/// Which corporate actions prices are adjusted for
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Adjustment {
+    /// Prices and volume as traded
+    Raw,
+    /// Adjusted for splits
+    #[default]
+    Splits,
+    /// Adjusted for splits and cash dividends
+    All,
+}
+
+/// What happened on an ex-date
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// New shares per old share, e.g. 4.0 for a 4-for-1 split or 0.1 for a
+    /// 1-for-10 reverse split
+    Split(f64),
+    /// Cash paid per share
+    Dividend(f64),
+}
+
+/// One row of a corporate-actions file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CorporateAction {
+    /// First day the shares trade with the action applied
+    pub ex_date: NaiveDate,
+    pub action: Action,
+}
+
+#[derive(Deserialize)]
+struct ActionRow {
+    #[serde(default)]
+    ticker: Option<String>,
+    date: NaiveDate,
+    action: String,
+    value: String,
+}
+
+/// Split ratio written as `4`, `4:1` or `1:10`
+fn parse_ratio(value: &str) -> Option<f64> {
+    let ratio = match value.split_once(':') {
+        Some((new, old)) => new.trim().parse::<f64>().ok()? / old.trim().parse::<f64>().ok()?,
+        None => value.trim().parse().ok()?,
+    };
+    (ratio.is_finite() && ratio > 0.0).then_some(ratio)
+}
+
+/// Read a CSV of corporate actions with a `date,action,value` header and an
+/// optional `ticker` column. `action` is `split` (value `4`, `4:1` or `1:10`)
+/// or `dividend` (cash per share); rows of other tickers than `ticker` are
+/// skipped when the column is present.
+pub fn read_actions(path: &str, ticker: &str) -> Result<Vec<CorporateAction>> {
+    if !Path::new(path).exists() {
+        return Err(anyhow!("Corporate actions file {} does not exist", path));
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(open_reader(path)?);
+    let mut actions = Vec::new();
+    for (i, row) in reader.deserialize::<ActionRow>().enumerate() {
+        // Line 1 is the header
+        let line = i + 2;
+        let row = row.with_context(|| format!("Invalid corporate action in {}:{}", path, line))?;
+        if row
+            .ticker
+            .as_deref()
+            .is_some_and(|t| !t.is_empty() && !t.eq_ignore_ascii_case(ticker))
+        {
+            continue;
+        }
+        let action = match row.action.to_ascii_lowercase().as_str() {
+            "split" => Action::Split(parse_ratio(&row.value).ok_or_else(|| {
+                anyhow!(
+                    "Invalid split ratio '{}' in {}:{}: expected e.g. 4, 4:1 or 1:10",
+                    row.value,
+                    path,
+                    line
+                )
+            })?),
+            "dividend" => Action::Dividend(
+                row.value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|d| d.is_finite() && *d >= 0.0)
+                    .ok_or_else(|| {
+                        anyhow!("Invalid dividend '{}' in {}:{}", row.value, path, line)
+                    })?,
+            ),
+            other => {
+                return Err(anyhow!(
+                    "Unknown action '{}' in {}:{}: expected split or dividend",
+                    other,
+                    path,
+                    line
+                ));
+            }
+        };
+        actions.push(CorporateAction {
+            ex_date: row.date,
+            action,
+        });
+    }
+    Ok(actions)
+}
+
+/// Applies corporate actions to raw bars
+#[derive(Debug, Clone)]
+pub struct Adjuster {
+    actions: Vec<CorporateAction>,
+    adjustment: Adjustment,
+    tz: Tz,
+}
+
+impl Adjuster {
+    /// Adjust for the `actions` that `adjustment` covers; bars belong before
+    /// an ex-date when their date in `tz` (the exchange time zone) is earlier
+    pub fn new(actions: Vec<CorporateAction>, adjustment: Adjustment, tz: Tz) -> Self {
+        let mut actions: Vec<_> = actions
+            .into_iter()
+            .filter(|a| match a.action {
+                Action::Split(_) => adjustment != Adjustment::Raw,
+                Action::Dividend(_) => adjustment == Adjustment::All,
+            })
+            .collect();
+        actions.sort_by_key(|a| a.ex_date);
+        Self {
+            actions,
+            adjustment,
+            tz,
+        }
+    }
+
+    fn date(&self, t: i64) -> Option<NaiveDate> {
+        self.tz
+            .timestamp_millis_opt(t)
+            .single()
+            .map(|dt| dt.date_naive())
+    }
+
+    /// Adjust `bars`, which must hold the raw prices of one ticker in
+    /// timestamp order
+    pub fn apply(&self, bars: &mut [Agg]) -> Result<()> {
+        if self.adjustment == Adjustment::Raw || self.actions.is_empty() {
+            return Ok(());
+        }
+        let dates = bars
+            .iter()
+            .map(|bar| {
+                self.date(bar.t)
+                    .ok_or_else(|| anyhow!("Invalid bar timestamp {}", bar.t))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Price and volume factor of each action, from the raw close before it
+        let mut factors = Vec::with_capacity(self.actions.len());
+        for action in &self.actions {
+            let before = dates.partition_point(|d| *d < action.ex_date);
+            factors.push(match action.action {
+                Action::Split(ratio) => (1.0 / ratio, ratio),
+                Action::Dividend(_) if before == 0 => (1.0, 1.0),
+                Action::Dividend(amount) => {
+                    let close = bars[before - 1].c;
+                    let factor = 1.0 - amount / close;
+                    if !(factor > 0.0 && factor <= 1.0) {
+                        return Err(anyhow!(
+                            "Dividend of {} on {} is not below the previous close of {}",
+                            amount,
+                            action.ex_date,
+                            close
+                        ));
+                    }
+                    (factor, 1.0)
+                }
+            });
+        }
+        // Walk back from the latest action, compounding the factors
+        let (mut price, mut volume) = (1.0, 1.0);
+        let mut next = self.actions.len();
+        for (bar, date) in bars.iter_mut().zip(&dates).rev() {
+            while next > 0 && self.actions[next - 1].ex_date > *date {
+                next -= 1;
+                price *= factors[next].0;
+                volume *= factors[next].1;
+            }
+            bar.o *= price;
+            bar.h *= price;
+            bar.l *= price;
+            bar.c *= price;
+            bar.vw = bar.vw.map(|vw| vw * price);
+            bar.v = bar.v.map(|v| v * volume);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86_400_000;
+    // 2024-06-03 00:00 UTC
+    const JUNE_3: i64 = 1717372800000;
+
+    fn bar(t: i64, c: f64) -> Agg {
+        Agg {
+            t,
+            o: c,
+            h: c,
+            l: c,
+            c,
+            v: Some(100.0),
+            vw: Some(c),
+            n: Some(1),
+        }
+    }
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 6, d).unwrap()
+    }
+
+    #[test]
+    fn test_split_and_dividend() {
+        // Closes of 100 and 102 before a 2:1 split on June 5, then a
+        // dividend of 1 on June 7, paid after a close of 50
+        let mut bars = vec![
+            bar(JUNE_3, 100.0),
+            bar(JUNE_3 + DAY, 102.0),
+            bar(JUNE_3 + 2 * DAY, 51.0),
+            bar(JUNE_3 + 3 * DAY, 50.0),
+            bar(JUNE_3 + 4 * DAY, 49.5),
+        ];
+        let actions = vec![
+            CorporateAction {
+                ex_date: date(7),
+                action: Action::Dividend(1.0),
+            },
+            CorporateAction {
+                ex_date: date(5),
+                action: Action::Split(2.0),
+            },
+        ];
+
+        let mut splits = bars.clone();
+        Adjuster::new(actions.clone(), Adjustment::Splits, Tz::UTC)
+            .apply(&mut splits)
+            .unwrap();
+        let closes: Vec<f64> = splits.iter().map(|b| b.c).collect();
+        assert_eq!(closes, vec![50.0, 51.0, 51.0, 50.0, 49.5]);
+        assert_eq!(splits[0].v, Some(200.0));
+        assert_eq!(splits[2].v, Some(100.0));
+
+        Adjuster::new(actions, Adjustment::All, Tz::UTC)
+            .apply(&mut bars)
+            .unwrap();
+        // 1 - 1/50 = 0.98 before June 7
+        let closes: Vec<f64> = bars.iter().map(|b| (b.c * 1e6).round() / 1e6).collect();
+        assert_eq!(closes, vec![49.0, 49.98, 49.98, 49.0, 49.5]);
+        assert_eq!(bars[1].vw.map(|vw| (vw * 1e6).round() / 1e6), Some(49.98));
+        assert_eq!(bars[4].v, Some(100.0));
+    }
+
+    #[test]
+    fn test_read_actions() {
+        let dir = std::env::temp_dir().join(format!("mdd-adjust-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("actions.csv");
+        std::fs::write(
+            &path,
+            "ticker,date,action,value\n\
+             AAPL,2020-08-31,split,4:1\n\
+             MSFT,2024-05-15,dividend,0.75\n\
+             aapl,2024-05-10,dividend,0.25\n\
+             ,2010-01-04,split,1:10\n",
+        )
+        .unwrap();
+        let actions = read_actions(path.to_str().unwrap(), "AAPL").unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                CorporateAction {
+                    ex_date: NaiveDate::from_ymd_opt(2020, 8, 31).unwrap(),
+                    action: Action::Split(4.0)
+                },
+                CorporateAction {
+                    ex_date: NaiveDate::from_ymd_opt(2024, 5, 10).unwrap(),
+                    action: Action::Dividend(0.25)
+                },
+                CorporateAction {
+                    ex_date: NaiveDate::from_ymd_opt(2010, 1, 4).unwrap(),
+                    action: Action::Split(0.1)
+                },
+            ]
+        );
+        std::fs::write(&path, "date,action,value\n2024-01-02,merger,1\n").unwrap();
+        let err = read_actions(path.to_str().unwrap(), "AAPL").unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown action 'merger'"),
+            "{}",
+            err
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,612 @@
+//! Exchange trading calendars.
+//!
+//! Holidays and early closes are derived from each exchange's rules (fixed
+//! dates with weekend substitution, nth weekdays, Easter) plus a short list
+//! of one-off closures, so no data files are needed. Hours are local to the
+//! exchange time zone.
+
+use chrono::{Datelike, Days, NaiveDate, TimeZone, Timelike, Weekday};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{Granularity, Session};
+
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Calendar {
+    /// Crypto for `X:` and `/` pairs and forex for `C:`, NYSE otherwise
+    #[default]
+    Auto,
+    /// New York Stock Exchange and Nasdaq
+    #[value(alias = "nasdaq")]
+    Nyse,
+    /// CME Globex, Sunday evening to Friday afternoon Chicago time
+    Cme,
+    /// London Stock Exchange
+    Lse,
+    /// Every day, around the clock
+    #[value(alias = "24/7")]
+    Crypto,
+    /// No calendar: every day may have data and nothing is expected
+    None,
+}
+
+/// Regular session of one trading day, in minutes from local midnight of
+/// that day; `open` is negative when the session starts the evening before
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hours {
+    pub open: i32,
+    pub close: i32,
+}
+
+impl Calendar {
+    /// Calendar to use for `ticker`, resolving [`Calendar::Auto`]
+    pub fn resolve(self, ticker: &str) -> Calendar {
+        if self != Calendar::Auto {
+            return self;
+        }
+        if ticker.starts_with("X:") || ticker.contains('/') {
+            Calendar::Crypto
+        } else if ticker.starts_with("C:") {
+            // Forex trades through every holiday but not at weekends; no
+            // calendar here beats skipping days that do have data
+            Calendar::None
+        } else {
+            Calendar::Nyse
+        }
+    }
+
+    /// Name used in messages
+    pub fn name(self) -> &'static str {
+        match self {
+            Calendar::Auto => "auto",
+            Calendar::Nyse => "NYSE",
+            Calendar::Cme => "CME",
+            Calendar::Lse => "LSE",
+            Calendar::Crypto => "crypto",
+            Calendar::None => "no",
+        }
+    }
+
+    pub fn tz(self) -> Tz {
+        match self {
+            Calendar::Nyse => chrono_tz::America::New_York,
+            Calendar::Cme => chrono_tz::America::Chicago,
+            Calendar::Lse => chrono_tz::Europe::London,
+            Calendar::Auto | Calendar::Crypto | Calendar::None => Tz::UTC,
+        }
+    }
+
+    /// Why the exchange is closed on `date` ("weekend" or the holiday), or
+    /// `None` on a trading day
+    pub fn closed_reason(self, date: NaiveDate) -> Option<&'static str> {
+        match self {
+            Calendar::Auto | Calendar::Crypto | Calendar::None => return None,
+            _ => {}
+        }
+        if matches!(date.weekday(), Weekday::Sat | Weekday::Sun) {
+            return Some("weekend");
+        }
+        holidays(self, date.year())
+            .into_iter()
+            .find(|(d, _)| *d == date)
+            .map(|(_, name)| name)
+    }
+
+    pub fn is_trading_day(self, date: NaiveDate) -> bool {
+        self.closed_reason(date).is_none()
+    }
+
+    /// Local close time in minutes from midnight when `date` is an early close
+    pub fn early_close(self, date: NaiveDate) -> Option<i32> {
+        let weekday = date.weekday();
+        let weekend = matches!(weekday, Weekday::Sat | Weekday::Sun);
+        if weekend || !self.is_trading_day(date) {
+            return None;
+        }
+        let day_after_thanksgiving =
+            date.month() == 11 && weekday == Weekday::Fri && (23..=29).contains(&date.day());
+        match self {
+            Calendar::Nyse => {
+                let july_3 = (date.month(), date.day()) == (7, 3);
+                let christmas_eve = (date.month(), date.day()) == (12, 24);
+                (july_3 || christmas_eve || day_after_thanksgiving).then_some(13 * 60)
+            }
+            Calendar::Cme => {
+                if day_after_thanksgiving || (date.month(), date.day()) == (12, 24) {
+                    Some(12 * 60 + 15)
+                } else {
+                    cme_early_close_holidays(date.year())
+                        .contains(&date)
+                        .then_some(12 * 60)
+                }
+            }
+            Calendar::Lse => {
+                matches!((date.month(), date.day()), (12, 24) | (12, 31)).then_some(12 * 60 + 30)
+            }
+            Calendar::Auto | Calendar::Crypto | Calendar::None => None,
+        }
+    }
+
+    /// Regular session on `date`, or `None` when the exchange is closed or
+    /// the calendar has no hours
+    pub fn hours(self, date: NaiveDate) -> Option<Hours> {
+        let (open, close) = match self {
+            Calendar::Nyse => (9 * 60 + 30, 16 * 60),
+            Calendar::Cme => (-7 * 60, 16 * 60),
+            Calendar::Lse => (8 * 60, 16 * 60 + 30),
+            Calendar::Crypto => (0, 24 * 60),
+            Calendar::Auto | Calendar::None => return None,
+        };
+        if !self.is_trading_day(date) {
+            return None;
+        }
+        Some(Hours {
+            open,
+            close: self.early_close(date).unwrap_or(close),
+        })
+    }
+
+    /// Local time, in seconds from midnight, at which the session of the next
+    /// trading day opens, for calendars whose sessions start the evening before
+    fn evening_open(self) -> Option<u32> {
+        match self {
+            Calendar::Cme => Some(17 * 3_600),
+            _ => None,
+        }
+    }
+
+    /// Trading day a bar starting at `t` (ms since the epoch) belongs to: its
+    /// local date, or the next one once an evening session has opened
+    pub fn trading_date(self, t: i64) -> Option<NaiveDate> {
+        let local = self.tz().timestamp_millis_opt(t).single()?;
+        let date = local.date_naive();
+        Some(match self.evening_open() {
+            Some(open) if local.num_seconds_from_midnight() >= open => date + Days::new(1),
+            _ => date,
+        })
+    }
+
+    /// Whether bars can fall on the local date `date`: it is a trading day,
+    /// or the session of the next one opens on its evening (e.g. CME on Sunday)
+    fn has_session(self, date: NaiveDate) -> bool {
+        self.is_trading_day(date)
+            || (self.evening_open().is_some() && self.is_trading_day(date + Days::new(1)))
+    }
+
+    /// Trading days from `from` to `to`, inclusive
+    pub fn trading_days(self, from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+        from.iter_days()
+            .take_while(move |d| *d <= to)
+            .filter(move |d| self.is_trading_day(*d))
+    }
+
+    /// `window` narrowed to the first and last local dates that hold part of a
+    /// session, or `None` when it has none; for CME that includes the evening
+    /// before each trading day, such as Sunday
+    pub fn trim(self, window: (NaiveDate, NaiveDate)) -> Option<(NaiveDate, NaiveDate)> {
+        let first = window
+            .0
+            .iter_days()
+            .take_while(|d| *d <= window.1)
+            .find(|d| self.has_session(*d))?;
+        let last = window
+            .1
+            .iter_days()
+            .rev()
+            .take_while(|d| *d >= first)
+            .find(|d| self.has_session(*d))?;
+        Some((first, last))
+    }
+
+    /// Upper bound on the bars of `multiplier` x `granularity` between `from`
+    /// and `to`, one per interval of [`Calendar::intraday_slots`] or per
+    /// trading day; `None` when the calendar has no hours or the bars are
+    /// weekly or coarser
+    pub fn expected_bars(
+        self,
+        from: NaiveDate,
+        to: NaiveDate,
+        granularity: Granularity,
+        multiplier: u32,
+        session: Session,
+    ) -> Option<u64> {
+        if matches!(self, Calendar::Auto | Calendar::None) {
+            return None;
+        }
+        match granularity {
+            Granularity::Day => Some(
+                self.trading_days(from, to)
+                    .count()
+                    .div_ceil(multiplier.max(1) as usize) as u64,
+            ),
+            Granularity::Second | Granularity::Minute | Granularity::Hour => Some(
+                self.trading_days(from, to)
+                    .filter_map(|d| self.intraday_slots(d, granularity, multiplier, session))
+                    .map(|slots| slots.len() as u64)
+                    .sum(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Start of every intraday bar expected on `date`, in seconds from local
+    /// midnight (negative before it); empty on a non-trading day and `None`
+    /// for daily or coarser bars or a calendar without hours.
+    ///
+    /// Bars are aligned to local midnight, so a partial interval at either end
+    /// of the session still holds one bar. [`Session`] hours are those of US
+    /// equities and only apply to the NYSE calendar, where [`Session::All`]
+    /// means the extended session; other calendars use their regular session.
+    pub fn intraday_slots(
+        self,
+        date: NaiveDate,
+        granularity: Granularity,
+        multiplier: u32,
+        session: Session,
+    ) -> Option<Vec<i64>> {
+        let unit: i64 = match granularity {
+            Granularity::Second => 1,
+            Granularity::Minute => 60,
+            Granularity::Hour => 3_600,
+            _ => return None,
+        };
+        if matches!(self, Calendar::Auto | Calendar::None) {
+            return None;
+        }
+        let size = unit * multiplier.max(1) as i64;
+        let (open, close) = match (self, self.hours(date)) {
+            (_, None) => return Some(Vec::new()),
+            (Calendar::Nyse, Some(_)) => match session {
+                Session::All => Session::Extended.bounds(date),
+                session => session.bounds(date),
+            },
+            (_, Some(hours)) => (hours.open, hours.close),
+        };
+        let (open, close) = (open as i64 * 60, close as i64 * 60);
+        let first = open.div_euclid(size) * size;
+        Some((first..close).step_by(size as usize).collect())
+    }
+}
+
+/// `n`-th (1-based) `weekday` of `month`
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap()
+}
+
+/// Last `weekday` of `month`
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, 5)
+        .unwrap_or_else(|| nth_weekday(year, month, weekday, 4))
+}
+
+/// Easter Sunday (anonymous Gregorian algorithm)
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+/// US rule: a Saturday holiday is observed on Friday, a Sunday one on Monday
+fn us_observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Days::new(1),
+        Weekday::Sun => date + Days::new(1),
+        _ => date,
+    }
+}
+
+/// UK rule: a weekend holiday moves to the next weekday
+fn uk_observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date + Days::new(2),
+        Weekday::Sun => date + Days::new(1),
+        _ => date,
+    }
+}
+
+/// One-off NYSE closures
+const NYSE_CLOSURES: &[((i32, u32, u32), &str)] = &[
+    ((2001, 9, 11), "September 11 attacks"),
+    ((2001, 9, 12), "September 11 attacks"),
+    ((2001, 9, 13), "September 11 attacks"),
+    ((2001, 9, 14), "September 11 attacks"),
+    ((2004, 6, 11), "National Day of Mourning"),
+    ((2007, 1, 2), "National Day of Mourning"),
+    ((2012, 10, 29), "Hurricane Sandy"),
+    ((2012, 10, 30), "Hurricane Sandy"),
+    ((2018, 12, 5), "National Day of Mourning"),
+    ((2025, 1, 9), "National Day of Mourning"),
+];
+
+/// One-off LSE closures
+const LSE_CLOSURES: &[((i32, u32, u32), &str)] = &[
+    ((1999, 12, 31), "Millennium"),
+    ((2002, 6, 3), "Golden Jubilee"),
+    ((2011, 4, 29), "Royal Wedding"),
+    ((2012, 6, 5), "Diamond Jubilee"),
+    ((2022, 6, 3), "Platinum Jubilee"),
+    ((2022, 9, 19), "State Funeral"),
+    ((2023, 5, 8), "Coronation"),
+];
+
+fn closures(list: &[((i32, u32, u32), &'static str)], year: i32) -> Vec<(NaiveDate, &'static str)> {
+    list.iter()
+        .filter(|((y, _, _), _)| *y == year)
+        .map(|&((y, m, d), name)| (ymd(y, m, d), name))
+        .collect()
+}
+
+/// Full-day closures of `calendar` in `year`
+fn holidays(calendar: Calendar, year: i32) -> Vec<(NaiveDate, &'static str)> {
+    let good_friday = easter(year) - Days::new(2);
+    match calendar {
+        Calendar::Nyse => {
+            let mut days = vec![
+                (ymd(year, 1, 1), "New Year's Day"),
+                (
+                    nth_weekday(year, 2, Weekday::Mon, 3),
+                    "Washington's Birthday",
+                ),
+                (good_friday, "Good Friday"),
+                (last_weekday(year, 5, Weekday::Mon), "Memorial Day"),
+                (us_observed(ymd(year, 7, 4)), "Independence Day"),
+                (nth_weekday(year, 9, Weekday::Mon, 1), "Labor Day"),
+                (nth_weekday(year, 11, Weekday::Thu, 4), "Thanksgiving Day"),
+                (us_observed(ymd(year, 12, 25)), "Christmas Day"),
+            ];
+            // A Saturday New Year's Day is not moved back into the old year
+            if ymd(year, 1, 1).weekday() == Weekday::Sun {
+                days.push((ymd(year, 1, 2), "New Year's Day"));
+            }
+            if year >= 1998 {
+                days.push((
+                    nth_weekday(year, 1, Weekday::Mon, 3),
+                    "Martin Luther King Jr. Day",
+                ));
+            }
+            if year >= 2022 {
+                days.push((us_observed(ymd(year, 6, 19)), "Juneteenth"));
+            }
+            days.extend(closures(NYSE_CLOSURES, year));
+            days
+        }
+        Calendar::Cme => {
+            let mut days = vec![
+                (good_friday, "Good Friday"),
+                (us_observed(ymd(year, 12, 25)), "Christmas Day"),
+            ];
+            if ymd(year, 1, 1).weekday() != Weekday::Sat {
+                days.push((uk_observed(ymd(year, 1, 1)), "New Year's Day"));
+            }
+            days
+        }
+        Calendar::Lse => {
+            let christmas = ymd(year, 12, 25);
+            let (christmas, boxing_day) = match christmas.weekday() {
+                Weekday::Sat => (ymd(year, 12, 27), ymd(year, 12, 28)),
+                Weekday::Sun => (ymd(year, 12, 27), ymd(year, 12, 26)),
+                Weekday::Fri => (christmas, ymd(year, 12, 28)),
+                _ => (christmas, ymd(year, 12, 26)),
+            };
+            let early_may = match year {
+                2020 => ymd(2020, 5, 8),
+                _ => nth_weekday(year, 5, Weekday::Mon, 1),
+            };
+            let spring = match year {
+                2002 | 2012 => ymd(year, 6, 4),
+                2022 => ymd(2022, 6, 2),
+                _ => last_weekday(year, 5, Weekday::Mon),
+            };
+            let mut days = vec![
+                (uk_observed(ymd(year, 1, 1)), "New Year's Day"),
+                (good_friday, "Good Friday"),
+                (easter(year) + Days::new(1), "Easter Monday"),
+                (early_may, "Early May Bank Holiday"),
+                (spring, "Spring Bank Holiday"),
+                (last_weekday(year, 8, Weekday::Mon), "Summer Bank Holiday"),
+                (christmas, "Christmas Day"),
+                (boxing_day, "Boxing Day"),
+            ];
+            days.extend(closures(LSE_CLOSURES, year));
+            days
+        }
+        Calendar::Auto | Calendar::Crypto | Calendar::None => Vec::new(),
+    }
+}
+
+/// US holidays on which CME Globex halts early instead of closing
+fn cme_early_close_holidays(year: i32) -> Vec<NaiveDate> {
+    let mut days = vec![
+        nth_weekday(year, 1, Weekday::Mon, 3),
+        nth_weekday(year, 2, Weekday::Mon, 3),
+        last_weekday(year, 5, Weekday::Mon),
+        us_observed(ymd(year, 7, 4)),
+        nth_weekday(year, 9, Weekday::Mon, 1),
+        nth_weekday(year, 11, Weekday::Thu, 4),
+    ];
+    if year >= 2022 {
+        days.push(us_observed(ymd(year, 6, 19)));
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(y: i32, m: u32, day: u32) -> NaiveDate {
+        ymd(y, m, day)
+    }
+
+    #[test]
+    fn test_nyse_holidays_2024_and_2025() {
+        let closed_2024 = [
+            d(2024, 1, 1),
+            d(2024, 1, 15),
+            d(2024, 2, 19),
+            d(2024, 3, 29),
+            d(2024, 5, 27),
+            d(2024, 6, 19),
+            d(2024, 7, 4),
+            d(2024, 9, 2),
+            d(2024, 11, 28),
+            d(2024, 12, 25),
+        ];
+        for date in closed_2024 {
+            assert!(!Calendar::Nyse.is_trading_day(date), "{}", date);
+        }
+        let trading_days_2024 = Calendar::Nyse
+            .trading_days(d(2024, 1, 1), d(2024, 12, 31))
+            .count();
+        assert_eq!(trading_days_2024, 252);
+        assert_eq!(
+            Calendar::Nyse.closed_reason(d(2025, 1, 9)),
+            Some("National Day of Mourning")
+        );
+        assert_eq!(
+            Calendar::Nyse.closed_reason(d(2025, 4, 18)),
+            Some("Good Friday")
+        );
+        // 2022-01-01 was a Saturday and not observed on Friday
+        assert!(Calendar::Nyse.is_trading_day(d(2021, 12, 31)));
+        // 2026-07-04 is a Saturday, observed on Friday the 3rd
+        assert_eq!(
+            Calendar::Nyse.closed_reason(d(2026, 7, 3)),
+            Some("Independence Day")
+        );
+        assert_eq!(Calendar::Nyse.early_close(d(2026, 7, 3)), None);
+    }
+
+    #[test]
+    fn test_early_closes() {
+        assert_eq!(Calendar::Nyse.early_close(d(2024, 11, 29)), Some(13 * 60));
+        assert_eq!(Calendar::Nyse.early_close(d(2024, 12, 24)), Some(13 * 60));
+        assert_eq!(Calendar::Nyse.early_close(d(2024, 12, 23)), None);
+        assert_eq!(Calendar::Cme.early_close(d(2024, 1, 15)), Some(12 * 60));
+        assert!(Calendar::Cme.is_trading_day(d(2024, 1, 15)));
+        assert_eq!(
+            Calendar::Lse.early_close(d(2024, 12, 31)),
+            Some(12 * 60 + 30)
+        );
+    }
+
+    #[test]
+    fn test_lse_substitute_days() {
+        // Christmas 2021 fell on a Saturday and Boxing Day on a Sunday
+        assert_eq!(
+            Calendar::Lse.closed_reason(d(2021, 12, 27)),
+            Some("Christmas Day")
+        );
+        assert_eq!(
+            Calendar::Lse.closed_reason(d(2021, 12, 28)),
+            Some("Boxing Day")
+        );
+        assert_eq!(
+            Calendar::Lse.closed_reason(d(2024, 4, 1)),
+            Some("Easter Monday")
+        );
+        assert!(Calendar::Lse.is_trading_day(d(2024, 7, 4)));
+        // The 2002 spring bank holiday moved next to the Golden Jubilee
+        assert_eq!(
+            Calendar::Lse.closed_reason(d(2002, 6, 3)),
+            Some("Golden Jubilee")
+        );
+        assert_eq!(
+            Calendar::Lse.closed_reason(d(2002, 6, 4)),
+            Some("Spring Bank Holiday")
+        );
+        assert!(Calendar::Lse.is_trading_day(d(2002, 5, 27)));
+    }
+
+    #[test]
+    fn test_trim_and_crypto() {
+        // Christmas week 2024: Tue 24 to Sun 29, closed on the 25th
+        let week = (d(2024, 12, 21), d(2024, 12, 29));
+        assert_eq!(
+            Calendar::Nyse.trim(week),
+            Some((d(2024, 12, 23), d(2024, 12, 27)))
+        );
+        assert_eq!(
+            Calendar::Nyse.trim((d(2024, 12, 25), d(2024, 12, 25))),
+            None
+        );
+        assert_eq!(Calendar::Crypto.trim(week), Some(week));
+        assert_eq!(Calendar::Auto.resolve("X:BTCUSD"), Calendar::Crypto);
+        assert_eq!(Calendar::Auto.resolve("AAPL"), Calendar::Nyse);
+        assert_eq!(Calendar::Lse.resolve("AAPL"), Calendar::Lse);
+        // 17:00 Chicago time on Feb 1 opens the CME session of Feb 2
+        let evening = 1706828400000; // 2024-02-01 23:00 UTC
+        assert_eq!(Calendar::Cme.trading_date(evening), Some(d(2024, 2, 2)));
+        assert_eq!(Calendar::Nyse.trading_date(evening), Some(d(2024, 2, 1)));
+        // Sunday evening opens Monday's CME session, Saturday has none
+        let sunday = (d(2024, 2, 4), d(2024, 2, 4));
+        assert_eq!(Calendar::Cme.trim(sunday), Some(sunday));
+        assert_eq!(Calendar::Nyse.trim(sunday), None);
+        assert_eq!(Calendar::Cme.trim((d(2024, 2, 3), d(2024, 2, 3))), None);
+        assert_eq!(
+            Calendar::Cme.trim((d(2024, 2, 3), d(2024, 2, 5))),
+            Some((d(2024, 2, 4), d(2024, 2, 5)))
+        );
+        // Christmas evening opens the session of the 26th
+        assert_eq!(
+            Calendar::Cme.trim((d(2024, 12, 25), d(2024, 12, 25))),
+            Some((d(2024, 12, 25), d(2024, 12, 25)))
+        );
+    }
+
+    #[test]
+    fn test_expected_bars() {
+        let day = d(2024, 2, 1);
+        let nyse = |g, m, s| Calendar::Nyse.expected_bars(day, day, g, m, s);
+        assert_eq!(nyse(Granularity::Minute, 1, Session::Regular), Some(390));
+        assert_eq!(nyse(Granularity::Minute, 5, Session::Regular), Some(78));
+        assert_eq!(nyse(Granularity::Minute, 1, Session::All), Some(960));
+        // 09:00 to 15:00, the first bar holding the half hour after the open
+        assert_eq!(nyse(Granularity::Hour, 1, Session::Regular), Some(7));
+        assert_eq!(nyse(Granularity::Day, 1, Session::All), Some(1));
+        assert_eq!(nyse(Granularity::Week, 1, Session::All), None);
+        let half_day = d(2024, 11, 29);
+        assert_eq!(
+            Calendar::Nyse.expected_bars(
+                half_day,
+                half_day,
+                Granularity::Minute,
+                1,
+                Session::Regular
+            ),
+            Some(210)
+        );
+        let weekend = Calendar::Crypto.expected_bars(
+            d(2024, 2, 3),
+            d(2024, 2, 4),
+            Granularity::Hour,
+            1,
+            Session::All,
+        );
+        assert_eq!(weekend, Some(48));
+        assert_eq!(
+            Calendar::None.expected_bars(day, day, Granularity::Minute, 1, Session::All),
+            None
+        );
+    }
+}
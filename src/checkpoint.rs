@@ -0,0 +1,266 @@
+//! On-disk progress of a download, used by `download --resume`.
+//!
+//! The checkpoint is rewritten after every page has been flushed to the sink
+//! and removed once the download completes, so its presence means the output
+//! next to it is incomplete.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::compress::Compression;
+use crate::sink::SplitMode;
+use crate::timestamp::TimestampStyle;
+use crate::{Adjustment, Agg, Granularity, OutputFormat, ProviderKind, Session};
+
+/// Identity of a download; a checkpoint only resumes the download it was written for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointKey {
+    pub ticker: String,
+    pub provider: ProviderKind,
+    pub granularity: Granularity,
+    #[serde(default = "one")]
+    pub multiplier: u32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub format: OutputFormat,
+    pub split_by_day: bool,
+    #[serde(default)]
+    pub split_mode: SplitMode,
+    #[serde(default)]
+    pub session: Session,
+    /// Split-adjusted unless recorded, as before the choice existed
+    #[serde(default)]
+    pub adjustment: Adjustment,
+    /// Compression of the output; an appended page must use the same
+    #[serde(default)]
+    pub compression: Compression,
+    /// How timestamps are written, so appended rows match the earlier ones
+    #[serde(default = "utc")]
+    pub tz: Tz,
+    #[serde(default)]
+    pub timestamp_style: TimestampStyle,
+    #[serde(default)]
+    pub timestamp_pattern: Option<String>,
+    /// Rounding and CSV header, so appended rows match the earlier ones
+    #[serde(default = "two")]
+    pub max_decimals: u8,
+    #[serde(default)]
+    pub no_header: bool,
+}
+
+/// Multiplier of checkpoints written before `multiplier` was recorded
+fn one() -> u32 {
+    1
+}
+
+/// Decimals of checkpoints written before `max_decimals` was recorded
+fn two() -> u8 {
+    2
+}
+
+/// Time zone of checkpoints written before `tz` was recorded
+fn utc() -> Tz {
+    Tz::UTC
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub download: CheckpointKey,
+    /// Pages completed so far
+    pub pages: usize,
+    /// First day of the date window still to be fetched; `None` together with
+    /// `next_url` once the last page is written
+    #[serde(default)]
+    pub next_from: Option<NaiveDate>,
+    /// Next page of that window, without the API key; `None` starts at its first page
+    pub next_url: Option<String>,
+    /// Timestamp (ms) of the last bar written
+    pub last_ts: Option<i64>,
+    /// Length of the single output file after the last completed page
+    pub bytes_written: Option<u64>,
+    /// Bars received but held back for reordering, not yet written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<Agg>,
+}
+
+impl Checkpoint {
+    pub fn new(download: CheckpointKey) -> Self {
+        Self {
+            download,
+            pages: 0,
+            next_from: None,
+            next_url: None,
+            last_ts: None,
+            bytes_written: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Checkpoint file kept next to `out_path`
+    pub fn path_for(out_path: &str) -> String {
+        format!("{}.checkpoint.json", out_path)
+    }
+
+    /// Read the checkpoint at `path`, if there is one
+    pub fn load(path: &str) -> Result<Option<Self>> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Cannot read {}", path)),
+        };
+        let checkpoint = serde_json::from_slice(&data)
+            .with_context(|| format!("Invalid checkpoint {}", path))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Atomically replace the checkpoint at `path`
+    pub fn save(&self, path: &str) -> Result<()> {
+        if let Some(parent) = Path::new(path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Cannot create directory {}", parent.display()))?;
+        }
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Cannot write {}", tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("Cannot write {}", path))
+    }
+
+    /// Delete the checkpoint at `path` if present
+    pub fn remove(path: &str) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Cannot remove {}", path))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail unless this checkpoint was written for `download`
+    pub fn ensure_matches(&self, download: &CheckpointKey, path: &str) -> Result<()> {
+        if &self.download != download {
+            return Err(anyhow!(
+                "Checkpoint {} belongs to a different download ({} {:?} {}..{}); delete it or rerun without --resume",
+                path,
+                self.download.ticker,
+                self.download.provider,
+                self.download.from,
+                self.download.to
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> CheckpointKey {
+        CheckpointKey {
+            ticker: "AAPL".into(),
+            provider: ProviderKind::Polygon,
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            from: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            to: NaiveDate::from_ymd_opt(2024, 3, 31).unwrap(),
+            format: OutputFormat::Csv,
+            split_by_day: false,
+            split_mode: SplitMode::Overwrite,
+            session: Session::Regular,
+            adjustment: Adjustment::Splits,
+            compression: Compression::None,
+            tz: Tz::UTC,
+            timestamp_style: TimestampStyle::Plain,
+            timestamp_pattern: None,
+            max_decimals: 2,
+            no_header: false,
+        }
+    }
+
+    #[test]
+    fn test_save_load_roundtrip_and_remove() {
+        let dir = std::env::temp_dir().join(format!("mdd-checkpoint-{}", std::process::id()));
+        let path = Checkpoint::path_for(dir.join("aapl.csv").to_str().unwrap());
+        let mut cp = Checkpoint::new(key());
+        cp.pages = 3;
+        cp.next_from = Some(NaiveDate::from_ymd_opt(2024, 2, 1).unwrap());
+        cp.next_url = Some("https://api.polygon.io/v2/aggs/cursor".into());
+        cp.last_ts = Some(1704067200000);
+        cp.bytes_written = Some(1234);
+        cp.save(&path).unwrap();
+
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(cp));
+        Checkpoint::remove(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_ensure_matches_rejects_other_download() {
+        let cp = Checkpoint::new(key());
+        assert!(cp.ensure_matches(&key(), "x").is_ok());
+        let mut other = key();
+        other.to = NaiveDate::from_ymd_opt(2024, 6, 30).unwrap();
+        assert!(cp.ensure_matches(&other, "x").is_err());
+        let raw = CheckpointKey {
+            adjustment: Adjustment::Raw,
+            ..key()
+        };
+        assert!(cp.ensure_matches(&raw, "x").is_err());
+        let gzip = CheckpointKey {
+            compression: Compression::Gzip,
+            ..key()
+        };
+        assert!(cp.ensure_matches(&gzip, "x").is_err());
+        let epoch = CheckpointKey {
+            timestamp_style: TimestampStyle::EpochS,
+            ..key()
+        };
+        assert!(cp.ensure_matches(&epoch, "x").is_err());
+        let rounded = CheckpointKey {
+            max_decimals: 4,
+            ..key()
+        };
+        assert!(cp.ensure_matches(&rounded, "x").is_err());
+        let headerless = CheckpointKey {
+            no_header: true,
+            ..key()
+        };
+        assert!(cp.ensure_matches(&headerless, "x").is_err());
+        let merge = CheckpointKey {
+            split_mode: SplitMode::Merge,
+            ..key()
+        };
+        assert!(cp.ensure_matches(&merge, "x").is_err());
+    }
+
+    #[test]
+    fn test_load_checkpoint_without_output_settings() {
+        let dir = std::env::temp_dir().join(format!("mdd-checkpoint-old-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("aapl.csv.checkpoint.json");
+        // Written before compression and timestamps were part of the key
+        fs::write(
+            &path,
+            r#"{"download":{"ticker":"AAPL","provider":"polygon","granularity":"minute",
+            "from":"2024-01-01","to":"2024-03-31","format":"csv","split_by_day":false},
+            "pages":1,"next_url":null,"last_ts":null,"bytes_written":null}"#,
+        )
+        .unwrap();
+        let cp = Checkpoint::load(path.to_str().unwrap()).unwrap().unwrap();
+        assert_eq!(cp.download.compression, Compression::None);
+        assert_eq!(cp.download.tz, Tz::UTC);
+        assert_eq!(cp.download.timestamp_style, TimestampStyle::Plain);
+        assert_eq!(cp.download.max_decimals, 2);
+        assert!(!cp.download.no_header);
+        assert_eq!(cp.download.split_mode, SplitMode::Overwrite);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
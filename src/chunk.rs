@@ -0,0 +1,130 @@
+//! Splitting long date ranges into provider-friendly windows.
+
+use chrono::{Datelike, Days, NaiveDate};
+use clap::ValueEnum;
+
+/// Calendar period used to split `--from`/`--to` into separate requests.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum Chunk {
+    /// Let the provider pick based on its page size and the granularity
+    #[default]
+    Auto,
+    /// One request for the whole range, relying on provider paging
+    None,
+    Day,
+    /// Monday to Sunday
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Chunk {
+    /// Split the inclusive range `from..=to` into consecutive windows aligned
+    /// to calendar periods. The first and last windows may be partial.
+    ///
+    /// `Auto` must be resolved by the caller; it behaves like `None` here.
+    pub fn windows(self, from: NaiveDate, to: NaiveDate) -> Vec<(NaiveDate, NaiveDate)> {
+        let mut out = Vec::new();
+        let mut start = from;
+        loop {
+            let end = self.period_end(start).min(to);
+            if end < start {
+                // Inverted range: leave it to the provider to report
+                out.push((start, to));
+                break;
+            }
+            out.push((start, end));
+            match end.succ_opt() {
+                Some(next) if end < to => start = next,
+                _ => break,
+            }
+        }
+        out
+    }
+
+    /// Last day of the period containing `d`
+    fn period_end(self, d: NaiveDate) -> NaiveDate {
+        match self {
+            Chunk::Auto | Chunk::None => NaiveDate::MAX,
+            Chunk::Day => d,
+            Chunk::Week => d + Days::new(6 - d.weekday().num_days_from_monday() as u64),
+            Chunk::Month => last_day_of_month(d.year(), d.month()),
+            Chunk::Quarter => last_day_of_month(d.year(), (d.month() - 1) / 3 * 3 + 3),
+            Chunk::Year => NaiveDate::from_ymd_opt(d.year(), 12, 31).unwrap(),
+        }
+    }
+}
+
+fn last_day_of_month(year: i32, month: u32) -> NaiveDate {
+    let (y, m) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(y, m, 1)
+        .unwrap()
+        .pred_opt()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(y: i32, m: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, day).unwrap()
+    }
+
+    #[test]
+    fn test_month_windows_are_calendar_aligned() {
+        let w = Chunk::Month.windows(d(2024, 1, 15), d(2024, 3, 10));
+        assert_eq!(
+            w,
+            vec![
+                (d(2024, 1, 15), d(2024, 1, 31)),
+                (d(2024, 2, 1), d(2024, 2, 29)),
+                (d(2024, 3, 1), d(2024, 3, 10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_week_and_quarter_windows() {
+        // 2024-01-03 is a Wednesday
+        let w = Chunk::Week.windows(d(2024, 1, 3), d(2024, 1, 10));
+        assert_eq!(
+            w,
+            vec![
+                (d(2024, 1, 3), d(2024, 1, 7)),
+                (d(2024, 1, 8), d(2024, 1, 10))
+            ]
+        );
+        let q = Chunk::Quarter.windows(d(2023, 11, 1), d(2024, 4, 1));
+        assert_eq!(
+            q,
+            vec![
+                (d(2023, 11, 1), d(2023, 12, 31)),
+                (d(2024, 1, 1), d(2024, 3, 31)),
+                (d(2024, 4, 1), d(2024, 4, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_none_and_single_day() {
+        assert_eq!(
+            Chunk::None.windows(d(2020, 1, 1), d(2024, 1, 1)),
+            vec![(d(2020, 1, 1), d(2024, 1, 1))]
+        );
+        assert_eq!(
+            Chunk::Day.windows(d(2024, 2, 1), d(2024, 2, 1)),
+            vec![(d(2024, 2, 1), d(2024, 2, 1))]
+        );
+        // Inverted ranges are passed through unchanged
+        assert_eq!(
+            Chunk::Month.windows(d(2024, 2, 2), d(2024, 2, 1)),
+            vec![(d(2024, 2, 2), d(2024, 2, 1))]
+        );
+    }
+}
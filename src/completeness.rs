@@ -0,0 +1,557 @@
+//! Completeness of a download: bars received on each trading day against the
+//! most the calendar allows, plus gaps, repeated and out-of-order timestamps.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use chrono::{Days, NaiveDate, NaiveTime, TimeDelta, TimeZone};
+use serde::Serialize;
+
+use crate::download::DEFAULT_REORDER_WINDOW;
+use crate::timestamp::TimestampFormat;
+use crate::{Calendar, Granularity, Session};
+
+/// At most this many days, gaps or timestamps are listed on the console
+const CONSOLE_LINES: usize = 10;
+
+/// Bars of one download as they are received, across pages and date
+/// windows; see [`Tracker::report`]
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    calendar: Calendar,
+    from: NaiveDate,
+    to: NaiveDate,
+    granularity: Granularity,
+    multiplier: u32,
+    session: Session,
+    /// Runs of bars at most one interval apart, from the first to the last
+    /// timestamp of each; enough to tell which expected bars are missing
+    covered: BTreeMap<i64, i64>,
+    /// Newest timestamps, kept to spot repeats; see [`Tracker::window`]
+    recent: BTreeSet<i64>,
+    window: usize,
+    per_day: BTreeMap<NaiveDate, u64>,
+    last: Option<i64>,
+    received: u64,
+    duplicates: Vec<i64>,
+    out_of_order: Vec<OutOfOrder>,
+}
+
+/// A bar that arrived after a newer one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OutOfOrder {
+    pub t: i64,
+    /// Newest timestamp received before it
+    pub after: i64,
+}
+
+/// Consecutive expected bars that never arrived, from `start` (inclusive) to
+/// `end` (exclusive), in ms since the epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Gap {
+    pub start: i64,
+    pub end: i64,
+    pub bars: u64,
+}
+
+/// Bars of one trading day; `expected` and `missing` are `None` when the
+/// calendar gives no estimate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DayReport {
+    pub date: NaiveDate,
+    pub expected: Option<u64>,
+    pub received: u64,
+    pub missing: Option<u64>,
+}
+
+/// Completeness of one ticker's download
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub ticker: String,
+    pub calendar: Calendar,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub granularity: Granularity,
+    pub multiplier: u32,
+    pub session: Session,
+    pub trading_days: usize,
+    pub expected: Option<u64>,
+    pub received: u64,
+    pub missing: Option<u64>,
+    /// Trading days of the range and any other day that received bars
+    pub days: Vec<DayReport>,
+    pub gaps: Vec<Gap>,
+    /// Timestamps received more than once, once per repetition
+    pub duplicates: Vec<i64>,
+    pub out_of_order: Vec<OutOfOrder>,
+}
+
+impl Tracker {
+    /// Track a download of `multiplier` x `granularity` bars of `session`
+    /// from `from` to `to`; `calendar` must already be resolved for the ticker
+    pub fn new(
+        calendar: Calendar,
+        from: NaiveDate,
+        to: NaiveDate,
+        granularity: Granularity,
+        multiplier: u32,
+        session: Session,
+    ) -> Self {
+        Self {
+            calendar,
+            from,
+            to,
+            granularity,
+            multiplier,
+            session,
+            covered: BTreeMap::new(),
+            recent: BTreeSet::new(),
+            window: DEFAULT_REORDER_WINDOW,
+            per_day: BTreeMap::new(),
+            last: None,
+            received: 0,
+            duplicates: Vec::new(),
+            out_of_order: Vec::new(),
+        }
+    }
+
+    /// Remember the newest `bars` timestamps (at least one) to spot repeats,
+    /// like [`DownloadRequest::reorder_window`](crate::DownloadRequest::reorder_window);
+    /// an older repeat is listed as out of order instead
+    pub fn window(mut self, bars: usize) -> Self {
+        self.window = bars.max(1);
+        self
+    }
+
+    /// Record the timestamps (ms) of bars in the order they were received,
+    /// i.e. [`FetchedPage::received`](crate::FetchedPage::received), which
+    /// still holds the repeated and late bars left out of the output
+    pub fn observe(&mut self, timestamps: &[i64]) {
+        for &t in timestamps {
+            self.received += 1;
+            if let Some(date) = self.calendar.trading_date(t) {
+                *self.per_day.entry(date).or_default() += 1;
+            }
+            self.cover(t);
+            if !self.recent.insert(t) {
+                self.duplicates.push(t);
+            } else if let Some(last) = self.last
+                && t < last
+            {
+                self.out_of_order.push(OutOfOrder { t, after: last });
+            }
+            if self.recent.len() > self.window {
+                self.recent.pop_first();
+            }
+            self.last = Some(self.last.map_or(t, |last| last.max(t)));
+        }
+    }
+
+    /// Length of one intraday interval in ms; `None` for daily and coarser
+    /// bars, whose length varies
+    fn step(&self) -> Option<i64> {
+        let seconds = match self.granularity {
+            Granularity::Second => 1,
+            Granularity::Minute => 60,
+            Granularity::Hour => 3_600,
+            _ => return None,
+        };
+        Some(seconds * 1_000 * self.multiplier.max(1) as i64)
+    }
+
+    /// Add `t` to the runs in [`Tracker::covered`], joining runs that end up
+    /// at most one interval apart
+    fn cover(&mut self, t: i64) {
+        let step = self.step().unwrap_or(0);
+        let (start, mut end) = match self.covered.range(..=t).next_back() {
+            Some((_, &end)) if t <= end => return,
+            Some((&start, &end)) if t - end <= step => (start, t),
+            _ => (t, t),
+        };
+        while let Some((&next, &next_end)) = self.covered.range(end + 1..).next() {
+            if next - end > step {
+                break;
+            }
+            self.covered.remove(&next);
+            end = end.max(next_end);
+        }
+        self.covered.insert(start, end);
+    }
+
+    /// Whether any bar was received from `start` (inclusive) to `end`
+    /// (exclusive), an interval no longer than one bar
+    fn received_in(&self, start: i64, end: i64) -> bool {
+        // Bars of a run are at most one interval apart, so any interval
+        // between its first and last bar holds one of them
+        self.covered
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, &last)| last >= start)
+    }
+
+    /// Start of `date` plus `seconds`, in ms since the epoch; `None` when that
+    /// local time is skipped by a daylight saving change
+    fn local_ms(&self, date: NaiveDate, seconds: i64) -> Option<i64> {
+        let naive = date.and_time(NaiveTime::MIN) + TimeDelta::seconds(seconds);
+        self.calendar
+            .tz()
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|dt| dt.timestamp_millis())
+    }
+
+    /// Intervals of the bars expected on `date`, or `None` without an estimate
+    fn slots(&self, date: NaiveDate) -> Option<Vec<(i64, i64)>> {
+        if matches!(self.calendar, Calendar::Auto | Calendar::None) {
+            return None;
+        }
+        match self.granularity {
+            Granularity::Day if self.multiplier == 1 => {
+                if !self.calendar.is_trading_day(date) {
+                    return Some(Vec::new());
+                }
+                let start = self.local_ms(date, 0)?;
+                let end = self.local_ms(date + Days::new(1), 0)?;
+                Some(vec![(start, end)])
+            }
+            granularity => {
+                let starts = self.calendar.intraday_slots(
+                    date,
+                    granularity,
+                    self.multiplier,
+                    self.session,
+                )?;
+                let step = match granularity {
+                    Granularity::Second => 1,
+                    Granularity::Minute => 60,
+                    _ => 3_600,
+                } * self.multiplier.max(1) as i64;
+                Some(
+                    starts
+                        .into_iter()
+                        .filter_map(|s| {
+                            Some((self.local_ms(date, s)?, self.local_ms(date, s + step)?))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    /// Compare what was received with the calendar, day by day
+    pub fn report(&self, ticker: &str) -> Report {
+        let mut days = Vec::new();
+        let mut gaps: Vec<Gap> = Vec::new();
+        let mut expected_total = None;
+        let mut missing_total = None;
+        let trading_days: Vec<_> = self.calendar.trading_days(self.from, self.to).collect();
+        let mut dates: BTreeSet<NaiveDate> = trading_days.iter().copied().collect();
+        dates.extend(self.per_day.keys().copied());
+        // Daily gaps run across weekends and holidays, intraday ones stop at the close
+        let mut previous_missing = false;
+        for date in dates {
+            let received = self.per_day.get(&date).copied().unwrap_or(0);
+            let Some(slots) = self.slots(date) else {
+                days.push(DayReport {
+                    date,
+                    expected: None,
+                    received,
+                    missing: None,
+                });
+                continue;
+            };
+            if self.granularity != Granularity::Day {
+                previous_missing = false;
+            }
+            let mut missing = 0;
+            for (start, end) in &slots {
+                if self.received_in(*start, *end) {
+                    previous_missing = false;
+                    continue;
+                }
+                missing += 1;
+                match gaps.last_mut() {
+                    Some(gap) if previous_missing => {
+                        gap.end = *end;
+                        gap.bars += 1;
+                    }
+                    _ => gaps.push(Gap {
+                        start: *start,
+                        end: *end,
+                        bars: 1,
+                    }),
+                }
+                previous_missing = true;
+            }
+            *expected_total.get_or_insert(0) += slots.len() as u64;
+            *missing_total.get_or_insert(0) += missing;
+            days.push(DayReport {
+                date,
+                expected: Some(slots.len() as u64),
+                received,
+                missing: Some(missing),
+            });
+        }
+        // Weekly and coarser bars have no meaningful days
+        if self.granularity > Granularity::Day {
+            days.clear();
+        }
+        Report {
+            ticker: ticker.to_string(),
+            calendar: self.calendar,
+            from: self.from,
+            to: self.to,
+            granularity: self.granularity,
+            multiplier: self.multiplier,
+            session: self.session,
+            trading_days: trading_days.len(),
+            expected: expected_total,
+            received: self.received,
+            missing: missing_total,
+            days,
+            gaps,
+            duplicates: self.duplicates.clone(),
+            out_of_order: self.out_of_order.clone(),
+        }
+    }
+}
+
+impl Report {
+    /// Bar size for messages, e.g. `minute` or `5-minute`
+    fn interval(&self) -> String {
+        match self.multiplier {
+            1 => self.granularity.name().to_owned(),
+            m => format!("{}-{}", m, self.granularity.name()),
+        }
+    }
+
+    /// Whether every expected bar arrived, once and in order
+    pub fn is_complete(&self) -> bool {
+        self.missing.unwrap_or(0) == 0 && self.duplicates.is_empty() && self.out_of_order.is_empty()
+    }
+
+    /// Console summary with timestamps rendered by `ts`: the totals, then
+    /// incomplete days, gaps, repeated and out-of-order bars, each cut short
+    /// after a few lines
+    pub fn table(&self, ts: &TimestampFormat) -> String {
+        let mut out = String::new();
+        match self.expected {
+            Some(expected) => writeln!(
+                out,
+                "Received {} of up to {} {} bar(s) expected over {} {} trading day(s)",
+                self.received,
+                expected,
+                self.interval(),
+                self.trading_days,
+                self.calendar.name()
+            ),
+            None => writeln!(out, "Received {} {} bar(s)", self.received, self.interval()),
+        }
+        .unwrap();
+
+        let incomplete: Vec<_> = self
+            .days
+            .iter()
+            .filter(|d| d.missing.unwrap_or(0) > 0)
+            .collect();
+        if !incomplete.is_empty() {
+            writeln!(
+                out,
+                "  {:<10}  {:>8}  {:>8}  {:>8}",
+                "date", "expected", "received", "missing"
+            )
+            .unwrap();
+            for day in incomplete.iter().take(CONSOLE_LINES) {
+                writeln!(
+                    out,
+                    "  {:<10}  {:>8}  {:>8}  {:>8}",
+                    day.date,
+                    day.expected.unwrap_or(0),
+                    day.received,
+                    day.missing.unwrap_or(0)
+                )
+                .unwrap();
+            }
+            more(&mut out, incomplete.len(), "incomplete day(s)");
+        }
+        if !self.gaps.is_empty() {
+            writeln!(out, "  {} gap(s):", self.gaps.len()).unwrap();
+            for gap in self.gaps.iter().take(CONSOLE_LINES) {
+                writeln!(
+                    out,
+                    "    {} to {} ({} bar(s))",
+                    ts.format(gap.start),
+                    ts.format(gap.end),
+                    gap.bars
+                )
+                .unwrap();
+            }
+            more(&mut out, self.gaps.len(), "gap(s)");
+        }
+        if !self.duplicates.is_empty() {
+            writeln!(out, "  {} repeated timestamp(s):", self.duplicates.len()).unwrap();
+            for t in self.duplicates.iter().take(CONSOLE_LINES) {
+                writeln!(out, "    {}", ts.format(*t)).unwrap();
+            }
+            more(&mut out, self.duplicates.len(), "repeated timestamp(s)");
+        }
+        if !self.out_of_order.is_empty() {
+            writeln!(out, "  {} out-of-order bar(s):", self.out_of_order.len()).unwrap();
+            for o in self.out_of_order.iter().take(CONSOLE_LINES) {
+                writeln!(out, "    {} after {}", ts.format(o.t), ts.format(o.after)).unwrap();
+            }
+            more(&mut out, self.out_of_order.len(), "out-of-order bar(s)");
+        }
+        out
+    }
+}
+
+/// Note how many of `total` entries were left out of a console list
+fn more(out: &mut String, total: usize, what: &str) {
+    if total > CONSOLE_LINES {
+        writeln!(out, "    ... and {} more {}", total - CONSOLE_LINES, what).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-01 14:30:00 UTC, the NYSE open
+    const T0: i64 = 1706797800000;
+    const MIN: i64 = 60_000;
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_gaps_duplicates_and_disorder() {
+        let feb_1 = day(2024, 2, 1);
+        let mut tracker = Tracker::new(
+            Calendar::Nyse,
+            feb_1,
+            feb_1,
+            Granularity::Minute,
+            1,
+            Session::Regular,
+        );
+        let mut times: Vec<i64> = (0..390).map(|i| T0 + i * MIN).collect();
+        // 09:35 to 09:37 never arrive, 10:00 is sent twice, 10:05 comes late
+        times.retain(|t| !(T0 + 5 * MIN..T0 + 8 * MIN).contains(t));
+        times.retain(|t| *t != T0 + 35 * MIN);
+        times.push(T0 + 30 * MIN);
+        times.push(T0 + 35 * MIN);
+        tracker.observe(&times);
+
+        let report = tracker.report("AAPL");
+        assert_eq!(report.expected, Some(390));
+        assert_eq!(report.received, 388);
+        assert_eq!(report.missing, Some(3));
+        assert_eq!(
+            report.gaps,
+            vec![Gap {
+                start: T0 + 5 * MIN,
+                end: T0 + 8 * MIN,
+                bars: 3
+            }]
+        );
+        assert_eq!(report.duplicates, vec![T0 + 30 * MIN]);
+        assert_eq!(
+            report.out_of_order,
+            vec![OutOfOrder {
+                t: T0 + 35 * MIN,
+                after: T0 + 389 * MIN
+            }]
+        );
+        assert!(!report.is_complete());
+        let table = report.table(&TimestampFormat::default());
+        assert!(
+            table.contains("2024-02-01       390       388         3"),
+            "{}",
+            table
+        );
+        assert!(table.contains("2024-02-01 14:35:00 to 2024-02-01 14:38:00 (3 bar(s))"));
+    }
+
+    #[test]
+    fn test_memory_stays_bounded() {
+        let feb_1 = day(2024, 2, 1);
+        let mut tracker = Tracker::new(
+            Calendar::Nyse,
+            feb_1,
+            feb_1,
+            Granularity::Minute,
+            1,
+            Session::Regular,
+        )
+        .window(10);
+        let mut times: Vec<i64> = (0..390).map(|i| T0 + i * MIN).collect();
+        times.retain(|t| *t != T0 + 100 * MIN);
+        // Repeats of the previous bar and of one long gone
+        times.push(T0 + 389 * MIN);
+        times.push(T0);
+        tracker.observe(&times);
+
+        assert_eq!(tracker.recent.len(), 10);
+        assert_eq!(tracker.covered.len(), 2);
+        let report = tracker.report("AAPL");
+        assert_eq!(report.missing, Some(1));
+        assert_eq!(report.gaps[0].start, T0 + 100 * MIN);
+        assert_eq!(report.duplicates, vec![T0 + 389 * MIN]);
+        assert_eq!(
+            report.out_of_order,
+            vec![OutOfOrder {
+                t: T0,
+                after: T0 + 389 * MIN
+            }]
+        );
+    }
+
+    #[test]
+    fn test_daily_gaps_span_non_trading_days() {
+        // Thu Feb 1 to Tue Feb 6, 2024; Friday and Monday are missing
+        let mut tracker = Tracker::new(
+            Calendar::Nyse,
+            day(2024, 2, 1),
+            day(2024, 2, 6),
+            Granularity::Day,
+            1,
+            Session::All,
+        );
+        // Daily bars start at midnight New York time
+        let midnight = T0 - 9 * 60 * MIN - 30 * MIN;
+        let day_ms = 24 * 60 * MIN;
+        tracker.observe(&[midnight, midnight + 5 * day_ms]);
+
+        let report = tracker.report("AAPL");
+        assert_eq!(report.trading_days, 4);
+        assert_eq!(report.missing, Some(2));
+        assert_eq!(report.gaps.len(), 1);
+        assert_eq!(report.gaps[0].bars, 2);
+        assert_eq!(report.gaps[0].start, midnight + day_ms);
+        assert_eq!(report.gaps[0].end, midnight + 5 * day_ms);
+    }
+
+    #[test]
+    fn test_no_calendar_only_counts() {
+        let feb_1 = day(2024, 2, 1);
+        let mut tracker = Tracker::new(
+            Calendar::None,
+            feb_1,
+            feb_1,
+            Granularity::Minute,
+            1,
+            Session::All,
+        );
+        tracker.observe(&[T0, T0 + MIN]);
+        let report = tracker.report("C:EURUSD");
+        assert_eq!(report.expected, None);
+        assert!(report.gaps.is_empty());
+        assert!(report.is_complete());
+        assert_eq!(
+            report.table(&TimestampFormat::default()),
+            "Received 2 minute bar(s)\n"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Streaming gzip/zstd compression of text outputs.
+//!
+//! [`CompressedWriter`] ends a gzip member or zstd frame on every flush. The
+//! sinks flush once per page, so a compressed file is valid after each page,
+//! can be truncated back to a checkpoint, and can be extended by appending
+//! further members; both formats define a concatenation of members as the
+//! concatenation of their contents.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use flate2::Compression as GzLevel;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Suffix appended to the file name, including the dot
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Compression implied by the extension of `path`
+    pub fn from_path(path: &str) -> Self {
+        let path = path.to_ascii_lowercase();
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") || path.ends_with(".zstd") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// `path` without the compression suffix, e.g. to look at the format extension
+    pub fn strip_suffix(path: &str) -> &str {
+        let lower = path.to_ascii_lowercase();
+        for suffix in [".gz", ".zstd", ".zst"] {
+            if lower.ends_with(suffix) {
+                return &path[..path.len() - suffix.len()];
+            }
+        }
+        path
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(compression: Compression) -> io::Result<Option<Self>> {
+        Ok(match compression {
+            Compression::None => None,
+            Compression::Gzip => Some(Encoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                GzLevel::default(),
+            ))),
+            Compression::Zstd => Some(Encoder::Zstd(zstd::Encoder::new(Vec::new(), 0)?)),
+        })
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Zstd(e) => e.finish(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(e) => e.write(buf),
+            Encoder::Zstd(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// File writer that compresses everything written between two flushes into
+/// one self-contained gzip member or zstd frame.
+///
+/// The compressed bytes of the current member are held in memory until the
+/// next flush. Data written after the last flush is lost if the writer is
+/// dropped, like an unflushed `BufWriter` whose flush failed.
+pub struct CompressedWriter {
+    file: File,
+    compression: Compression,
+    encoder: Option<Encoder>,
+}
+
+impl CompressedWriter {
+    pub fn new(file: File, compression: Compression) -> Self {
+        Self {
+            file,
+            compression,
+            encoder: None,
+        }
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.compression == Compression::None {
+            return self.file.write(buf);
+        }
+        if self.encoder.is_none() {
+            self.encoder = Encoder::new(self.compression)?;
+        }
+        self.encoder.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            self.file.write_all(&encoder.finish()?)?;
+        }
+        self.file.flush()
+    }
+}
+
+/// Open `path` for reading, decompressing according to its extension
+pub fn open_reader(path: &str) -> Result<Box<dyn Read>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path))?;
+    Ok(match Compression::from_path(path) {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        Compression::Zstd => {
+            Box::new(zstd::Decoder::new(file).with_context(|| format!("Cannot read {}", path))?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+
+    fn roundtrip(compression: Compression) {
+        let dir = std::env::temp_dir().join(format!("mdd-compress-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("data.txt{}", compression.suffix()));
+        let path = path.to_str().unwrap();
+
+        let mut w = CompressedWriter::new(File::create(path).unwrap(), compression);
+        w.write_all(b"page 1\n").unwrap();
+        w.flush().unwrap();
+        w.write_all(b"page 2\n").unwrap();
+        w.flush().unwrap();
+        let checkpoint = std::fs::metadata(path).unwrap().len();
+        w.write_all(b"lost\n").unwrap();
+        w.flush().unwrap();
+        drop(w);
+
+        // Truncate back to the checkpoint and append another member
+        let file = OpenOptions::new().append(true).open(path).unwrap();
+        file.set_len(checkpoint).unwrap();
+        let mut w = CompressedWriter::new(file, compression);
+        w.write_all(b"page 3\n").unwrap();
+        w.flush().unwrap();
+        // Never flushed, so never written
+        w.write_all(b"unflushed\n").unwrap();
+        drop(w);
+
+        let mut text = String::new();
+        open_reader(path)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "page 1\npage 2\npage 3\n");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_gzip_members_survive_truncate_and_append() {
+        roundtrip(Compression::Gzip);
+    }
+
+    #[test]
+    fn test_zstd_frames_survive_truncate_and_append() {
+        roundtrip(Compression::Zstd);
+    }
+
+    #[test]
+    fn test_from_path_and_strip_suffix() {
+        assert_eq!(Compression::from_path("a.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("a.json.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("a.csv"), Compression::None);
+        assert_eq!(Compression::strip_suffix("out/a.JSON.ZST"), "out/a.JSON");
+    }
+}
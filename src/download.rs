@@ -0,0 +1,663 @@
+//! Paging loop shared by every provider.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt, TryStreamExt, stream};
+use reqwest::Url;
+
+use crate::chunk::Chunk;
+use crate::provider::{Page, Provider, ProviderKind, Query, strip_query_param};
+use crate::ratelimit::{RateLimit, RateLimiter};
+use crate::retry::{RetryPolicy, is_retryable_status, retry_after};
+use crate::{Adjustment, Agg, Calendar, Granularity, Session};
+
+/// What to download. Built with [`DownloadRequest::new`] and refined with the setters.
+#[derive(Debug, Clone)]
+pub struct DownloadRequest {
+    pub ticker: String,
+    pub from: NaiveDate,
+    /// Inclusive end date
+    pub to: NaiveDate,
+    pub granularity: Granularity,
+    /// Number of `granularity` timespans per bar
+    pub multiplier: u32,
+    pub provider: ProviderKind,
+    /// Falls back to the provider's environment variable when `None`
+    pub api_key: Option<String>,
+    /// Falls back to the provider's base URL environment variable, then the public API
+    pub base_url: Option<String>,
+    /// How to split the range into separate requests; `Auto` asks the provider
+    pub chunk: Chunk,
+    /// Number of windows fetched at the same time (pages are still yielded in order)
+    pub concurrency: usize,
+    /// Start from this position (a [`FetchedPage::next`] cursor) instead of `from`
+    pub resume_from: Option<Cursor>,
+    /// Drop bars at or before this timestamp (ms), e.g. the last one already written
+    pub after: Option<i64>,
+    /// Only keep intraday bars starting in this trading session
+    pub session: Session,
+    /// Exchange calendar whose non-trading days are not requested
+    pub calendar: Calendar,
+    /// Corporate actions the provider adjusts prices for
+    pub adjustment: Adjustment,
+    /// Newest bars held back so that late ones can still be put in order
+    pub reorder_window: usize,
+    /// Bars held back when the download was interrupted ([`FetchedPage::pending`])
+    pub pending: Vec<Agg>,
+}
+
+/// Default [`DownloadRequest::reorder_window`], about a day of minute bars
+pub const DEFAULT_REORDER_WINDOW: usize = 1_000;
+
+impl DownloadRequest {
+    pub fn new(ticker: impl Into<String>, from: NaiveDate, to: NaiveDate) -> Self {
+        Self {
+            ticker: ticker.into(),
+            from,
+            to,
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            provider: ProviderKind::Polygon,
+            api_key: None,
+            base_url: None,
+            chunk: Chunk::Auto,
+            concurrency: 1,
+            resume_from: None,
+            after: None,
+            session: Session::All,
+            calendar: Calendar::Auto,
+            adjustment: Adjustment::Splits,
+            reorder_window: DEFAULT_REORDER_WINDOW,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn granularity(mut self, granularity: Granularity) -> Self {
+        self.granularity = granularity;
+        self
+    }
+
+    /// Bars spanning `multiplier` units of the granularity, e.g. 5 with minutes
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn provider(mut self, provider: ProviderKind) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Send requests to a mock server or proxy instead of the public API
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    pub fn chunk(mut self, chunk: Chunk) -> Self {
+        self.chunk = chunk;
+        self
+    }
+
+    /// Fetch up to `concurrency` windows in parallel (at least one)
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Continue an interrupted download at `cursor`, skipping bars up to `after`
+    pub fn resume(mut self, cursor: Cursor, after: Option<i64>) -> Self {
+        self.resume_from = Some(cursor);
+        self.after = after;
+        self
+    }
+
+    /// Ask the provider for raw, split-adjusted or fully adjusted prices
+    pub fn adjustment(mut self, adjustment: Adjustment) -> Self {
+        self.adjustment = adjustment;
+        self
+    }
+
+    /// Hold back up to `bars` of the newest bars before yielding them, so that
+    /// bars arriving in a later page than newer ones are still yielded in
+    /// order; with 0 such bars are dropped
+    pub fn reorder_window(mut self, bars: usize) -> Self {
+        self.reorder_window = bars;
+        self
+    }
+
+    /// Bars that were held back when a download was interrupted, to be
+    /// yielded along with those of the resumed one
+    pub fn pending(mut self, bars: Vec<Agg>) -> Self {
+        self.pending = bars;
+        self
+    }
+
+    /// Keep only bars of `session`, e.g. regular trading hours
+    pub fn session(mut self, session: Session) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Skip days on which `calendar` has no trading; [`Calendar::None`]
+    /// requests every day of the range
+    pub fn calendar(mut self, calendar: Calendar) -> Self {
+        self.calendar = calendar;
+        self
+    }
+
+    /// Only keep bars strictly newer than `after` (ms since epoch)
+    pub fn after(mut self, after: i64) -> Self {
+        self.after = Some(after);
+        self
+    }
+}
+
+/// Position within a download, used to resume it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// First day of the window still to be fetched
+    pub from: NaiveDate,
+    /// Page within that window (without the API key); `None` starts at its first page
+    pub next: Option<Url>,
+}
+
+/// One page of bars as returned by the provider.
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    /// 1-based page number, counted across all windows
+    pub number: usize,
+    /// Date window this page belongs to
+    pub window: (NaiveDate, NaiveDate),
+    /// Bars released by this page, in strictly increasing timestamp order
+    pub bars: Vec<Agg>,
+    /// Where the download continues, or `None` after the last page; pass it
+    /// to [`DownloadRequest::resume`] to continue from there
+    pub next: Option<Cursor>,
+    /// Bars received so far but still held back for reordering; pass them to
+    /// [`DownloadRequest::pending`] along with `next`
+    pub pending: Vec<Agg>,
+    /// Timestamps of every bar of the provider's page within the session, in
+    /// the order sent, including the repeated and late ones that `bars` leaves out
+    pub received: Vec<i64>,
+}
+
+/// HTTP client plus the rate limiting and retry policy applied to every request.
+///
+/// Clones share the same rate limiter, so one `Downloader` can drive several
+/// downloads concurrently without exceeding the provider's budget.
+#[derive(Clone)]
+pub struct Downloader {
+    client: reqwest::Client,
+    limiter: Arc<RateLimiter>,
+    rate_limits: HashMap<ProviderKind, RateLimit>,
+    retry: RetryPolicy,
+    verbose: u8,
+}
+
+impl Downloader {
+    pub fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!(
+                "market-data-downloader/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .build()?;
+        Ok(Self {
+            client,
+            limiter: Arc::new(RateLimiter::new()),
+            rate_limits: HashMap::new(),
+            retry: RetryPolicy::default(),
+            verbose: 0,
+        })
+    }
+
+    /// Override the request budget of `provider` (defaults to its entry-level plan)
+    pub fn rate_limit(mut self, provider: ProviderKind, limit: RateLimit) -> Self {
+        self.rate_limits.insert(provider, limit);
+        self
+    }
+
+    /// Retry policy for rate limiting, server errors and connection failures
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Log progress to stderr when greater than zero
+    pub fn verbose(mut self, level: u8) -> Self {
+        self.verbose = level;
+        self
+    }
+
+    /// Stream the pages of `request` in order.
+    ///
+    /// The range is split into windows per [`DownloadRequest::chunk`], each
+    /// narrowed to trading days of [`DownloadRequest::calendar`]. Bars come
+    /// out sorted and unique; see [`DownloadRequest::reorder_window`]. Invalid
+    /// requests fail immediately, while HTTP and parse errors end the stream.
+    pub fn pages(
+        &self,
+        request: DownloadRequest,
+    ) -> Result<impl Stream<Item = Result<FetchedPage>> + Send + use<>> {
+        let provider = request.provider.provider();
+        let rate_limit = self
+            .rate_limits
+            .get(&request.provider)
+            .copied()
+            .unwrap_or_else(|| provider.default_rate_limit());
+        provider.check_interval(request.granularity, request.multiplier)?;
+        provider.check_adjustment(request.adjustment)?;
+        if request.session != Session::All && request.granularity >= Granularity::Day {
+            return Err(anyhow!(
+                "Session filtering applies to intraday bars, not {} bars",
+                request.granularity.name()
+            ));
+        }
+        let api_key = provider.resolve_api_key(request.api_key.as_deref())?;
+        let base_url = provider.resolve_base_url(request.base_url.as_deref())?;
+        let chunk = match request.chunk {
+            Chunk::Auto => provider.default_chunk(request.granularity),
+            chunk => chunk,
+        };
+        let (start, mut resume) = match request.resume_from {
+            Some(cursor) => (cursor.from, cursor.next),
+            None => (request.from, None),
+        };
+        let calendar = request.calendar.resolve(&request.ticker);
+        let all = chunk.windows(start, request.to);
+        let listed = all.len();
+        // The window being resumed is kept as is, its next page depends on it
+        let windows: Vec<_> = all
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, window)| match (i, &resume) {
+                (0, Some(_)) => Some(window),
+                _ => calendar.trim(window),
+            })
+            .collect();
+        if self.verbose > 0 && windows.len() < listed {
+            eprintln!(
+                "Skipping {} window(s) without {} trading days",
+                listed - windows.len(),
+                calendar.name()
+            );
+        }
+        let total = windows.len();
+
+        let pagers: Vec<_> = windows
+            .iter()
+            .enumerate()
+            .map(|(i, &window)| {
+                let pager = Pager {
+                    client: self.client.clone(),
+                    provider: request.provider.provider(),
+                    api_key: api_key.clone(),
+                    base_url: base_url.clone(),
+                    ticker: request.ticker.clone(),
+                    granularity: request.granularity,
+                    multiplier: request.multiplier,
+                    adjustment: request.adjustment,
+                    index: i,
+                    window,
+                    label: if total > 1 {
+                        format!(
+                            "window {}/{} ({}..{}) page",
+                            i + 1,
+                            total,
+                            window.0,
+                            window.1
+                        )
+                    } else {
+                        "page".to_string()
+                    },
+                    resume: resume.take(),
+                    started: false,
+                    next: None,
+                    page: 0,
+                    limiter: Arc::clone(&self.limiter),
+                    rate_limit,
+                    retry: self.retry,
+                    verbose: self.verbose,
+                };
+                pager.into_stream()
+            })
+            .collect();
+
+        // Windows are independent, so they can be fetched ahead of time; the
+        // output order is still that of the windows
+        let ordered: BoxStream<'static, Result<WindowPage>> = if request.concurrency <= 1 {
+            stream::iter(pagers).flatten().boxed()
+        } else {
+            stream::iter(pagers.into_iter().map(|p| p.try_collect::<Vec<_>>()))
+                .buffered(request.concurrency)
+                .map_ok(|pages| stream::iter(pages.into_iter().map(Ok)))
+                .try_flatten()
+                .boxed()
+        };
+
+        let verbose = self.verbose;
+        let mut number = 0;
+        let session = request.session;
+        let mut reorder = Reorder::new(request.reorder_window, request.after);
+        reorder.push(request.pending);
+        Ok(ordered.map_ok(move |page| {
+            number += 1;
+            let mut bars = page.bars;
+            bars.retain(|bar| session.contains(bar.t));
+            let received = bars.iter().map(|bar| bar.t).collect();
+            reorder.push(bars);
+            let next = match page.next {
+                Some(url) => Some(Cursor {
+                    from: windows[page.index].0,
+                    next: Some(url),
+                }),
+                None => windows.get(page.index + 1).map(|w| Cursor {
+                    from: w.0,
+                    next: None,
+                }),
+            };
+            let bars = reorder.release(next.is_none());
+            if next.is_none() && verbose > 0 {
+                if reorder.dropped > 0 {
+                    eprintln!(
+                        "Dropped {} repeated or late bar(s) to keep timestamps increasing",
+                        reorder.dropped
+                    );
+                }
+                eprintln!("Done. Total pages: {}", number);
+            }
+            FetchedPage {
+                number,
+                window: windows[page.index],
+                bars,
+                pending: reorder.pending(),
+                received,
+                next,
+            }
+        }))
+    }
+
+    /// Stream the bars of `request` one by one.
+    pub fn bars(
+        &self,
+        request: DownloadRequest,
+    ) -> Result<impl Stream<Item = Result<Agg>> + Send + use<>> {
+        Ok(self
+            .pages(request)?
+            .map_ok(|page| stream::iter(page.bars.into_iter().map(Ok)))
+            .try_flatten())
+    }
+}
+
+/// Bars on their way out of [`Downloader::pages`], kept sorted and unique.
+///
+/// The newest `window` bars are held back; a bar that arrives after a newer
+/// one is slotted in while that one is still held, and dropped once a bar at
+/// or after it has been released. Repeated timestamps keep the first bar.
+struct Reorder {
+    window: usize,
+    held: BTreeMap<i64, Agg>,
+    /// Newest timestamp released, or the lower bound given by the request
+    last: Option<i64>,
+    dropped: usize,
+}
+
+impl Reorder {
+    fn new(window: usize, after: Option<i64>) -> Self {
+        Self {
+            window,
+            held: BTreeMap::new(),
+            last: after,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, bars: Vec<Agg>) {
+        for bar in bars {
+            if self.last.is_some_and(|last| bar.t <= last) || self.held.contains_key(&bar.t) {
+                self.dropped += 1;
+                continue;
+            }
+            self.held.insert(bar.t, bar);
+        }
+    }
+
+    /// Oldest bars beyond the window, or every bar at the end of the download
+    fn release(&mut self, all: bool) -> Vec<Agg> {
+        let keep = if all { 0 } else { self.window };
+        let count = self.held.len().saturating_sub(keep);
+        let bars: Vec<Agg> = (0..count)
+            .filter_map(|_| self.held.pop_first())
+            .map(|(_, bar)| bar)
+            .collect();
+        if let Some(bar) = bars.last() {
+            self.last = Some(bar.t);
+        }
+        bars
+    }
+
+    fn pending(&self) -> Vec<Agg> {
+        self.held.values().cloned().collect()
+    }
+}
+
+/// Pages of a single date window
+struct Pager {
+    client: reqwest::Client,
+    provider: Box<dyn Provider>,
+    api_key: String,
+    base_url: String,
+    ticker: String,
+    granularity: Granularity,
+    multiplier: u32,
+    adjustment: Adjustment,
+    /// Position of `window` in the download
+    index: usize,
+    window: (NaiveDate, NaiveDate),
+    /// Prefix for log messages, e.g. "page" or "window 2/3 (...) page"
+    label: String,
+    /// Page to start at instead of the first one of the window
+    resume: Option<Url>,
+    started: bool,
+    next: Option<Url>,
+    page: usize,
+    limiter: Arc<RateLimiter>,
+    rate_limit: RateLimit,
+    retry: RetryPolicy,
+    verbose: u8,
+}
+
+/// A page as fetched by a [`Pager`], before cross-window bookkeeping
+struct WindowPage {
+    /// Index of the window in the download
+    index: usize,
+    bars: Vec<Agg>,
+    /// Next page of the same window, without the API key
+    next: Option<Url>,
+}
+
+/// Outcome of a single HTTP attempt that did not fail for good
+enum Attempt {
+    Done(Vec<u8>),
+    Retry {
+        error: anyhow::Error,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl Pager {
+    fn into_stream(self) -> BoxStream<'static, Result<WindowPage>> {
+        stream::try_unfold(self, |mut pager| async move {
+            let page = pager.next_page().await?;
+            Ok(page.map(|p| (p, pager)))
+        })
+        .boxed()
+    }
+
+    async fn next_page(&mut self) -> Result<Option<WindowPage>> {
+        if !self.started {
+            self.started = true;
+            self.next = Some(match self.resume.take() {
+                Some(next) => {
+                    let mut url = strip_query_param(&next, self.provider.api_key_param());
+                    url.query_pairs_mut()
+                        .append_pair(self.provider.api_key_param(), &self.api_key);
+                    url
+                }
+                None => {
+                    let query = Query {
+                        ticker: &self.ticker,
+                        from: self.window.0,
+                        to: self.window.1,
+                        granularity: self.granularity,
+                        multiplier: self.multiplier,
+                        adjustment: self.adjustment,
+                    };
+                    self.provider
+                        .first_request(&self.base_url, &query, &self.api_key)?
+                }
+            });
+        }
+        let Some(fetch_url) = self.next.take() else {
+            return Ok(None);
+        };
+        self.page += 1;
+        if self.verbose > 0 {
+            eprintln!("Fetching {} {}: {}", self.label, self.page, fetch_url);
+        }
+
+        let body = self.fetch(&fetch_url).await?;
+        let Page {
+            results,
+            next: cursor,
+        } = self.provider.parse_page(&body)?;
+
+        self.next = match cursor {
+            Some(cursor) => Some(self.provider.next_request(
+                &self.base_url,
+                &fetch_url,
+                &cursor,
+                &self.api_key,
+            )?),
+            None => None,
+        };
+        Ok(Some(WindowPage {
+            index: self.index,
+            bars: results,
+            next: self
+                .next
+                .as_ref()
+                .map(|u| strip_query_param(u, self.provider.api_key_param())),
+        }))
+    }
+
+    async fn fetch(&self, fetch_url: &Url) -> Result<Vec<u8>> {
+        let attempts = self.retry.max_retries + 1;
+        let mut attempt = 1;
+        loop {
+            if self.verbose > 0 && attempt > 1 {
+                eprintln!(
+                    "Fetching {} {} (attempt {}/{}): {}",
+                    self.label, self.page, attempt, attempts, fetch_url
+                );
+            }
+            match self.try_fetch(fetch_url).await? {
+                Attempt::Done(body) => return Ok(body),
+                Attempt::Retry { error, .. } if attempt >= attempts => {
+                    return Err(error.context(format!(
+                        "Giving up on {} {} after {} attempt(s)",
+                        self.label, self.page, attempt
+                    )));
+                }
+                Attempt::Retry { error, retry_after } => {
+                    let delay = self.retry.delay(attempt, retry_after);
+                    if self.verbose > 0 {
+                        eprintln!(
+                            "Attempt {}/{} failed: {}. Retrying in {:.1}s...",
+                            attempt,
+                            attempts,
+                            error,
+                            delay.as_secs_f64()
+                        );
+                    }
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn try_fetch(&self, fetch_url: &Url) -> Result<Attempt> {
+        let key = self.provider.name();
+        let wait = self.limiter.reserve(key, self.rate_limit);
+        if !wait.is_zero() {
+            if self.verbose > 0 {
+                eprintln!(
+                    "Waiting {:.1}s to respect {} rate limit ({})...",
+                    wait.as_secs_f64(),
+                    key,
+                    self.rate_limit
+                );
+            }
+            tokio::time::sleep(wait).await;
+        }
+
+        let resp = match self.client.get(fetch_url.clone()).send().await {
+            Ok(resp) => resp,
+            Err(e) if e.is_connect() || e.is_timeout() => {
+                return Ok(Attempt::Retry {
+                    error: anyhow::Error::new(e).context(format!("Request failed: {}", fetch_url)),
+                    retry_after: None,
+                });
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e).context(format!("Request failed: {}", fetch_url)));
+            }
+        };
+
+        self.limiter.observe(key, resp.headers());
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let wait = retry_after(resp.headers());
+            let text = resp.text().await.unwrap_or_default();
+            if status.as_u16() == 403 {
+                return Err(anyhow!(
+                    "HTTP 403 Forbidden: {}\nHint: Your API key may not be entitled to this data. Try:\n- Using --granularity day (daily aggregates) instead of minute\n- Using a different ticker (e.g., equities like AAPL)\n- Upgrading your plan for minute/index data\nRequest URL: {}",
+                    text,
+                    fetch_url
+                ));
+            }
+            let error = anyhow!("HTTP {}: {}", status, text);
+            if is_retryable_status(status) {
+                return Ok(Attempt::Retry {
+                    error,
+                    retry_after: wait,
+                });
+            }
+            return Err(error);
+        }
+
+        match resp.bytes().await {
+            Ok(body) => Ok(Attempt::Done(body.to_vec())),
+            // The connection dropped mid-body; worth another try
+            Err(e) => Ok(Attempt::Retry {
+                error: anyhow::Error::new(e).context(format!(
+                    "Failed to read response from {}",
+                    self.provider.name()
+                )),
+                retry_after: None,
+            }),
+        }
+    }
+}
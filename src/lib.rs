@@ -0,0 +1,228 @@
+//! Download market data aggregates from Polygon.io or Twelve Data.
+//!
+//! The [`Downloader`] pages through a provider and yields normalized [`Agg`]
+//! bars as a [`Stream`](futures_util::Stream); the [`sink`] module writes them
+//! out as CSV or JSON. The `market-data-downloader` binary is a thin CLI over
+//! this crate.
+//!
+//! ```no_run
+//! use futures_util::TryStreamExt;
+//! use market_data_downloader::{DownloadRequest, Downloader, Granularity};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let from = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+//! let request = DownloadRequest::new("AAPL", from, from).granularity(Granularity::Day);
+//! let bars: Vec<_> = Downloader::new()?.bars(request)?.try_collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+pub mod adjust;
+pub mod calendar;
+pub mod checkpoint;
+pub mod chunk;
+pub mod completeness;
+pub mod compress;
+pub mod download;
+pub mod provider;
+pub mod ratelimit;
+pub mod reader;
+pub mod resample;
+pub mod retry;
+pub mod session;
+pub mod sink;
+pub mod timestamp;
+pub mod validate;
+
+pub use adjust::Adjustment;
+pub use calendar::Calendar;
+pub use chunk::Chunk;
+pub use download::{Cursor, DownloadRequest, Downloader, FetchedPage};
+pub use provider::{Provider, ProviderKind};
+pub use session::Session;
+
+/// A single OHLCV bar, normalized across providers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Agg {
+    pub t: i64,         // timestamp in ms
+    pub o: f64,         // open
+    pub h: f64,         // high
+    pub l: f64,         // low
+    pub c: f64,         // close
+    pub v: Option<f64>, // volume may be missing for indices
+    pub vw: Option<f64>,
+    pub n: Option<i64>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Csv,
+    Json,
+    /// Newline-delimited JSON, one bar object per line
+    #[value(alias = "jsonl")]
+    Ndjson,
+    Parquet,
+    /// Arrow IPC file format, also known as Feather v2
+    #[value(alias = "feather", alias = "ipc")]
+    Arrow,
+    /// SQLite database with a `bars` table, upserting on (ticker, provider, granularity, ts)
+    Sqlite,
+}
+
+impl OutputFormat {
+    /// File extension, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
+            OutputFormat::Sqlite => "db",
+        }
+    }
+
+    /// Whether an existing file can be extended by `--resume` and `update`
+    pub fn appendable(self) -> bool {
+        !matches!(self, OutputFormat::Parquet | OutputFormat::Arrow)
+    }
+
+    /// Whether one output can hold several tickers, series being kept apart inside it
+    pub fn multi_series(self) -> bool {
+        matches!(self, OutputFormat::Sqlite)
+    }
+
+    /// Whether `--split-by-day` can write per-day files in this format
+    pub fn splittable(self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Ndjson)
+    }
+}
+
+/// Timespan of one bar, multiplied by the request's `multiplier`
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Granularity {
+    /// Lowercase name, as used on the command line and in Polygon URLs
+    pub fn name(self) -> &'static str {
+        match self {
+            Granularity::Second => "second",
+            Granularity::Minute => "minute",
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+            Granularity::Quarter => "quarter",
+            Granularity::Year => "year",
+        }
+    }
+}
+
+/// Format milliseconds since epoch into UTC timestamp string
+pub fn fmt_ts(ms: i64) -> String {
+    if let Some(dt) = Utc.timestamp_millis_opt(ms).single() {
+        dt.format("%Y-%m-%d %H:%M:%S").to_string()
+    } else {
+        ms.to_string()
+    }
+}
+
+/// Parse a timestamp written by [`fmt_ts`] back into milliseconds since epoch
+pub fn parse_ts(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S") {
+        return Some(Utc.from_utc_datetime(&dt).timestamp_millis());
+    }
+    // fmt_ts falls back to raw milliseconds for out-of-range values
+    s.parse().ok()
+}
+
+/// Output path for a download: `out` when given (with `{ticker}` replaced by
+/// the ticker), otherwise `output/TICKER_FROM_TO.ext`
+pub fn compute_out_path(
+    ticker: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    format: OutputFormat,
+    out: &Option<String>,
+) -> String {
+    match out {
+        Some(p) => p.replace("{ticker}", ticker),
+        None => {
+            // Place files under output/ instead of project root
+            format!("output/{}_{}_{}.{}", ticker, from, to, format.extension())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fmt_ts_zero() {
+        assert_eq!(fmt_ts(0), "1970-01-01 00:00:00");
+    }
+
+    #[test]
+    fn test_fmt_ts_known() {
+        // 2024-04-01 00:00:00 UTC in ms
+        let ts = 1711929600000i64;
+        assert_eq!(fmt_ts(ts), "2024-04-01 00:00:00");
+    }
+
+    #[test]
+    fn test_parse_ts_roundtrips_fmt_ts() {
+        let ts = 1711929660000i64;
+        assert_eq!(parse_ts(&fmt_ts(ts)), Some(ts));
+        assert_eq!(parse_ts("not a time"), None);
+    }
+
+    #[test]
+    fn test_compute_out_path_defaults_csv() {
+        let d1 = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        let d2 = NaiveDate::from_ymd_opt(2025, 1, 31).unwrap();
+        let out = compute_out_path("I:NDX", d1, d2, OutputFormat::Csv, &None);
+        assert_eq!(out, "output/I:NDX_2025-01-01_2025-01-31.csv");
+    }
+
+    #[test]
+    fn test_compute_out_path_defaults_json() {
+        let d1 = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+        let d2 = NaiveDate::from_ymd_opt(2024, 2, 2).unwrap();
+        let out = compute_out_path("AAPL", d1, d2, OutputFormat::Json, &None);
+        assert_eq!(out, "output/AAPL_2024-02-01_2024-02-02.json");
+    }
+
+    #[test]
+    fn test_compute_out_path_respects_explicit() {
+        let d1 = NaiveDate::from_ymd_opt(2025, 9, 1).unwrap();
+        let d2 = NaiveDate::from_ymd_opt(2025, 9, 4).unwrap();
+        let explicit = Some(String::from("custom.csv"));
+        let out = compute_out_path("I:SPX", d1, d2, OutputFormat::Csv, &explicit);
+        assert_eq!(out, "custom.csv");
+    }
+
+    #[test]
+    fn test_compute_out_path_expands_ticker_template() {
+        let d1 = NaiveDate::from_ymd_opt(2025, 9, 1).unwrap();
+        let explicit = Some(String::from("data/{ticker}.csv"));
+        let out = compute_out_path("MSFT", d1, d1, OutputFormat::Csv, &explicit);
+        assert_eq!(out, "data/MSFT.csv");
+    }
+}
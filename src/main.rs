@@ -1,16 +1,40 @@
-use std::{env, time::Duration};
+use std::collections::HashSet;
+use std::pin::pin;
+use std::time::Duration;
 
 use anyhow::{Context, Result, anyhow};
-use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use futures_util::{StreamExt, TryStreamExt, stream};
+use market_data_downloader::adjust::{Adjuster, read_actions};
+use market_data_downloader::checkpoint::{Checkpoint, CheckpointKey};
+use market_data_downloader::chunk::Chunk;
+use market_data_downloader::completeness::Tracker;
+use market_data_downloader::compress::Compression;
+use market_data_downloader::download::DEFAULT_REORDER_WINDOW;
+use market_data_downloader::ratelimit::RateLimit;
+use market_data_downloader::reader::{infer_format, last_timestamp, latest_day_file, read_bars};
+use market_data_downloader::resample::{Interval, Offset, ResampleSink, Resampler};
+use market_data_downloader::retry::RetryPolicy;
+use market_data_downloader::sink::{
+    SPLIT_ROOT, Sink, SinkOptions, SplitByDaySink, SplitMode, open_sink, open_sink_append, sqlite,
+    truncate_output,
+};
+use market_data_downloader::timestamp::{TimestampFormat, TimestampStyle, parse_tz};
+use market_data_downloader::validate::{ValidationMode, Validator};
+use market_data_downloader::{
+    Adjustment, Calendar, Cursor, DownloadRequest, Downloader, Granularity, OutputFormat,
+    ProviderKind, Session, compute_out_path, fmt_ts,
+};
 use reqwest::Url;
-use serde::Deserialize;
 
 /// Market data downloader
 ///
 /// Examples:
 ///   market-data-downloader download --apikey=... --ticker AAPL --from 2024-01-01 --to 2024-01-03 --out aapl.csv
 ///   POLYGON_API_KEY=... market-data-downloader download -t I:NDX -f 2024-02-01 -T 2024-02-01 --format json
+///   market-data-downloader download --tickers-file sp500.txt -f 2024-01-01 -T 2024-01-31 --out data/{ticker}.csv --concurrency 4
 #[derive(Parser, Debug)]
 #[command(name = "market-data-downloader", version, about)]
 struct Cli {
@@ -22,33 +46,30 @@ struct Cli {
 enum Commands {
     /// Download aggregates for an index, stock, or crypto ticker
     Download(DownloadArgs),
-}
-
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-enum OutputFormat {
-    Csv,
-    Json,
-}
-
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-enum Granularity {
-    Minute,
-    Day,
-}
-
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-enum Provider {
-    #[value(name = "polygon", alias = "polygon")]
-    Polygon,
-    #[value(name = "twelvedata", aliases = ["twelve-data", "twelve_data"])]
-    TwelveData,
+    /// Append bars newer than the last one in an existing output
+    Update(UpdateArgs),
+    /// Derive split- or dividend-adjusted bars from raw ones and a corporate-actions file
+    Adjust(AdjustArgs),
 }
 
 #[derive(Parser, Debug)]
 struct DownloadArgs {
-    /// Ticker, e.g. AAPL, I:SPX, I:NDX, I:VIX
-    #[arg(short = 't', long = "ticker")]
-    ticker: String,
+    /// Ticker, e.g. AAPL, I:SPX, I:NDX, I:VIX; repeat or comma-separate for several
+    #[arg(
+        short = 't',
+        long = "ticker",
+        value_delimiter = ',',
+        required_unless_present = "tickers_file"
+    )]
+    tickers: Vec<String>,
+
+    /// File with one ticker per line (blank lines and # comments are ignored)
+    #[arg(long = "tickers-file")]
+    tickers_file: Option<String>,
+
+    /// Number of tickers downloaded at the same time; all share one rate limit
+    #[arg(long = "concurrency", default_value_t = 1usize)]
+    concurrency: usize,
 
     /// Start date (YYYY-MM-DD)
     #[arg(short = 'f', long = "from")]
@@ -58,63 +79,367 @@ struct DownloadArgs {
     #[arg(short = 'T', long = "to")]
     to: NaiveDate,
 
-    /// Polygon API key (can use env POLYGON_API_KEY)
-    #[arg(short = 'k', long = "apikey")]
-    api_key: Option<String>,
-
-    /// Output file path (defaults to ticker_from_to.csv or .json)
+    /// Output file path (defaults to ticker_from_to.csv or .json); {ticker} is
+    /// replaced by the ticker and is required when downloading several
+    /// tickers, except into one SQLite database
     #[arg(short = 'o', long = "out")]
     out: Option<String>,
 
-    /// Output format
+    /// Output format (parquet and arrow write typed columns, one row group or record batch per page)
     #[arg(long = "format", value_enum, default_value_t = OutputFormat::Csv)]
     format: OutputFormat,
 
-    /// Data granularity (minute or day)
+    /// Compress CSV, JSON or NDJSON output (inferred from a .gz or .zst --out)
+    #[arg(long = "compress", value_enum)]
+    compress: Option<Compression>,
+
+    /// Bar timespan (second, minute, hour, day, week, month, quarter or year)
     #[arg(long = "granularity", value_enum, default_value_t = Granularity::Minute)]
     granularity: Granularity,
 
+    /// Number of timespans per bar, e.g. 5 with --granularity minute for 5-minute bars
+    #[arg(long = "multiplier", default_value_t = 1u32)]
+    multiplier: u32,
+
+    /// Keep only bars of a US equity session in New York time (regular
+    /// 09:30-16:00, premarket from 04:00, afterhours until 20:00, extended
+    /// for all three), honoring early closes
+    #[arg(long = "session", value_enum, default_value_t = Session::All)]
+    session: Session,
+
+    /// Exchange calendar whose weekends and holidays are not requested and
+    /// which sets the expected bar count (auto: crypto for X: and pairs,
+    /// none for C: forex, nyse otherwise)
+    #[arg(long = "calendar", value_enum, default_value_t = Calendar::Auto)]
+    calendar: Calendar,
+
+    /// Check every bar for NaN or infinite values, high below low, negative
+    /// volume and a close outside [low, high], then keep (warn), drop or stop
+    /// at (fail) the ones that break a rule
+    #[arg(long = "validate", value_enum, default_value_t = ValidationMode::Warn)]
+    validate: ValidationMode,
+
+    /// Corporate actions the provider adjusts prices for: raw, splits or all
+    /// (splits and cash dividends; Twelve Data only)
+    #[arg(long = "adjustment", value_enum, default_value_t = Adjustment::Splits)]
+    adjustment: Adjustment,
+
     /// Omit header row in CSV output
     #[arg(long = "no-header", default_value_t = false)]
     no_header: bool,
 
-    /// Respect free plan by waiting between requests (~12s for 5 req/min)
-    #[arg(long = "rate-limit-wait-secs", default_value_t = 12u64)]
-    wait_secs: u64,
+    /// Maximum number of decimal places for OHLCV values
+    #[arg(long = "max-decimals", default_value_t = 2u8)]
+    max_decimals: u8,
+
+    /// Split output into per-day CSV or NDJSON files under output/YYYY/MM/TICKER_YYYY-MM-DD.csv
+    #[arg(long = "split-by-day", default_value_t = false)]
+    split_by_day: bool,
+
+    /// How --split-by-day treats day files that already exist
+    #[arg(long = "split-mode", value_enum, default_value_t = SplitMode::Overwrite, requires = "split_by_day")]
+    split_mode: SplitMode,
+
+    /// Data provider (polygon or twelvedata)
+    #[arg(long = "provider", value_enum, default_value_t = ProviderKind::Polygon)]
+    provider: ProviderKind,
+
+    /// Continue an interrupted download from its checkpoint, appending to the existing output
+    #[arg(long = "resume", default_value_t = false)]
+    resume: bool,
+
+    /// Aggregate the downloaded bars into coarser ones before writing, e.g. 15m, 1h, 1d or 1w
+    #[arg(long = "resample", conflicts_with = "resume")]
+    resample: Option<Interval>,
+
+    /// Shift the --resample grid, e.g. 30m for hourly bars starting at half past
+    /// (not with --session-boundary, whose times already place the grid)
+    #[arg(
+        long = "resample-offset",
+        requires = "resample",
+        conflicts_with = "session_boundaries",
+        default_value = "0m"
+    )]
+    resample_offset: Offset,
+
+    /// Time of day (HH:MM, in --tz) at which intraday --resample buckets close
+    /// and restart, e.g. the session open and close; repeat or comma-separate
+    #[arg(
+        long = "session-boundary",
+        requires = "resample",
+        value_delimiter = ',',
+        value_parser = parse_time_of_day
+    )]
+    session_boundaries: Vec<NaiveTime>,
+
+    /// Also write the completeness report (bars per day, gaps, repeated and
+    /// out-of-order timestamps) as JSON to this file; {ticker} is replaced by
+    /// the ticker and is required when downloading several tickers
+    #[arg(long = "report")]
+    report: Option<String>,
+
+    #[command(flatten)]
+    timestamps: TimestampArgs,
+
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+/// Connection settings shared by every subcommand that talks to a provider
+#[derive(Parser, Debug)]
+struct ClientArgs {
+    /// Provider API key (can use env POLYGON_API_KEY or TWELVEDATA_API_KEY)
+    #[arg(short = 'k', long = "apikey")]
+    api_key: Option<String>,
+
+    /// Request budget as N/WINDOW (e.g. 5/min, 100/s, 1/12s) or "unlimited";
+    /// defaults to the provider's entry plan (Polygon 5/min, Twelve Data 8/min)
+    #[arg(long = "rate-limit", conflicts_with = "wait_secs")]
+    rate_limit: Option<RateLimit>,
+
+    /// Fixed pause between requests; shorthand for --rate-limit 1/<secs>s (0 = unlimited)
+    #[arg(long = "rate-limit-wait-secs")]
+    wait_secs: Option<u64>,
+
+    /// Retries for rate-limited (429), server error (5xx) and connection failures
+    #[arg(long = "max-retries", default_value_t = 3u32)]
+    max_retries: u32,
+
+    /// Backoff before the first retry in milliseconds, doubled on every retry (with jitter)
+    #[arg(long = "retry-base-delay-ms", default_value_t = 1000u64)]
+    retry_base_delay_ms: u64,
+
+    /// Upper bound for the retry backoff in seconds (Retry-After headers are honored beyond it)
+    #[arg(long = "retry-max-delay-secs", default_value_t = 60u64)]
+    retry_max_delay_secs: u64,
 
     /// Verbose logging
     #[arg(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    /// Provider API base URL, e.g. a local mock server or proxy
+    /// (can use env POLYGON_BASE_URL or TWELVEDATA_BASE_URL)
+    #[arg(long = "base-url")]
+    base_url: Option<String>,
+
+    /// Split the date range into one request per day, week, month, quarter or year;
+    /// "auto" picks a size that fits the provider's page limit
+    #[arg(long = "chunk", value_enum, default_value_t = Chunk::Auto)]
+    chunk: Chunk,
+
+    /// Number of date chunks fetched in parallel (still within the rate limit)
+    #[arg(long = "chunk-concurrency", default_value_t = 1usize)]
+    chunk_concurrency: usize,
+
+    /// Number of the newest bars held back so that bars arriving after newer
+    /// ones are still written in order (0 drops them instead)
+    #[arg(long = "reorder-window", default_value_t = DEFAULT_REORDER_WINDOW)]
+    reorder_window: usize,
+}
+
+#[derive(Parser, Debug)]
+struct UpdateArgs {
+    /// Ticker, e.g. AAPL, I:SPX, I:NDX, I:VIX
+    #[arg(short = 't', long = "ticker")]
+    ticker: String,
+
+    /// Existing CSV, JSON, NDJSON or SQLite file to extend (not needed with --split-by-day)
+    #[arg(short = 'o', long = "out", required_unless_present = "split_by_day")]
+    out: Option<String>,
+
+    /// Start date used when there is no existing data yet (YYYY-MM-DD)
+    #[arg(short = 'f', long = "from")]
+    from: Option<NaiveDate>,
+
+    /// End date inclusive (YYYY-MM-DD), defaults to today (UTC)
+    #[arg(short = 'T', long = "to")]
+    to: Option<NaiveDate>,
+
+    /// Output format (inferred from the --out extension when omitted)
+    #[arg(long = "format", value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Compression of the existing output (inferred from a .gz or .zst --out)
+    #[arg(long = "compress", value_enum)]
+    compress: Option<Compression>,
+
+    /// Bar timespan (second, minute, hour, day, week, month, quarter or year)
+    #[arg(long = "granularity", value_enum, default_value_t = Granularity::Minute)]
+    granularity: Granularity,
+
+    /// Number of timespans per bar, e.g. 5 with --granularity minute for 5-minute bars
+    #[arg(long = "multiplier", default_value_t = 1u32)]
+    multiplier: u32,
+
+    /// Keep only bars of a US equity session in New York time (regular
+    /// 09:30-16:00, premarket from 04:00, afterhours until 20:00, extended
+    /// for all three), honoring early closes
+    #[arg(long = "session", value_enum, default_value_t = Session::All)]
+    session: Session,
+
+    /// Exchange calendar whose weekends and holidays are not requested and
+    /// which sets the expected bar count (auto: crypto for X: and pairs,
+    /// none for C: forex, nyse otherwise)
+    #[arg(long = "calendar", value_enum, default_value_t = Calendar::Auto)]
+    calendar: Calendar,
+
+    /// Check every bar for NaN or infinite values, high below low, negative
+    /// volume and a close outside [low, high], then keep (warn), drop or stop
+    /// at (fail) the ones that break a rule
+    #[arg(long = "validate", value_enum, default_value_t = ValidationMode::Warn)]
+    validate: ValidationMode,
+
+    /// Corporate actions the provider adjusts prices for: raw, splits or all
+    /// (splits and cash dividends; Twelve Data only)
+    #[arg(long = "adjustment", value_enum, default_value_t = Adjustment::Splits)]
+    adjustment: Adjustment,
+
+    /// Omit header row when a new CSV file has to be created
+    #[arg(long = "no-header", default_value_t = false)]
+    no_header: bool,
+
     /// Maximum number of decimal places for OHLCV values
     #[arg(long = "max-decimals", default_value_t = 2u8)]
     max_decimals: u8,
 
-    /// Split output into per-day CSV files under output/YYYY/MM/TICKER_YYYY-MM-DD.csv
+    /// Extend the per-day files under output/YYYY/MM instead of a single file
     #[arg(long = "split-by-day", default_value_t = false)]
     split_by_day: bool,
 
     /// Data provider (polygon or twelvedata)
-    #[arg(long = "provider", value_enum, default_value_t = Provider::Polygon)]
-    provider: Provider,
+    #[arg(long = "provider", value_enum, default_value_t = ProviderKind::Polygon)]
+    provider: ProviderKind,
+
+    #[command(flatten)]
+    timestamps: TimestampArgs,
+
+    #[command(flatten)]
+    client: ClientArgs,
 }
 
-#[derive(Debug, Deserialize)]
-struct AggsResponse {
-    results: Option<Vec<Agg>>,
-    next_url: Option<String>,
+#[derive(Parser, Debug)]
+struct AdjustArgs {
+    /// CSV, JSON or NDJSON file of raw bars, e.g. from download --adjustment raw
+    input: String,
+
+    /// CSV of corporate actions with a date,action,value header and an
+    /// optional ticker column; action is split (value 4, 4:1 or 1:10) or
+    /// dividend (cash per share)
+    #[arg(long = "actions")]
+    actions: String,
+
+    /// Ticker whose rows of --actions apply; also picks the exchange time zone
+    /// of the ex-dates through --calendar
+    #[arg(short = 't', long = "ticker")]
+    ticker: String,
+
+    /// Corporate actions to adjust for: splits or all (splits and dividends)
+    #[arg(long = "adjustment", value_enum, default_value_t = Adjustment::All)]
+    adjustment: Adjustment,
+
+    /// Output file path for the adjusted bars
+    #[arg(short = 'o', long = "out")]
+    out: String,
+
+    /// Output format (inferred from the --out extension when omitted)
+    #[arg(long = "format", value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Format of the input (inferred from its extension when omitted)
+    #[arg(long = "input-format", value_enum)]
+    input_format: Option<OutputFormat>,
+
+    /// Bar timespan of the input, recorded in SQLite, Parquet and Arrow output
+    #[arg(long = "granularity", value_enum, default_value_t = Granularity::Minute)]
+    granularity: Granularity,
+
+    /// Number of timespans per bar of the input
+    #[arg(long = "multiplier", default_value_t = 1u32)]
+    multiplier: u32,
+
+    /// Provider the input was downloaded from, recorded like --granularity
+    #[arg(long = "provider", value_enum, default_value_t = ProviderKind::Polygon)]
+    provider: ProviderKind,
+
+    /// Exchange calendar whose time zone decides on which day a bar falls
+    #[arg(long = "calendar", value_enum, default_value_t = Calendar::Auto)]
+    calendar: Calendar,
+
+    /// Omit header row in CSV output
+    #[arg(long = "no-header", default_value_t = false)]
+    no_header: bool,
+
+    /// Maximum number of decimal places for OHLCV values
+    #[arg(long = "max-decimals", default_value_t = 2u8)]
+    max_decimals: u8,
+
+    #[command(flatten)]
+    timestamps: TimestampArgs,
+}
+
+/// How timestamps are rendered in CSV, JSON and NDJSON output
+#[derive(Parser, Debug)]
+struct TimestampArgs {
+    /// IANA time zone for timestamps and per-day files, e.g. America/New_York
+    #[arg(long = "tz", default_value = "UTC", value_parser = parse_tz)]
+    tz: Tz,
+
+    /// Timestamp layout: plain ("2024-02-01 09:30:00", with the offset outside
+    /// UTC), iso8601, epoch-ms, epoch-s or custom
+    #[arg(long = "timestamp-format", value_enum, default_value_t = TimestampStyle::Plain)]
+    style: TimestampStyle,
+
+    /// strftime pattern for --timestamp-format custom, e.g. "%d/%m/%Y %H:%M"
+    #[arg(long = "timestamp-pattern")]
+    pattern: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Agg {
-    t: i64,         // timestamp in ms
-    o: f64,         // open
-    h: f64,         // high
-    l: f64,         // low
-    c: f64,         // close
-    v: Option<f64>, // volume may be missing for indices
-    vw: Option<f64>,
-    n: Option<i64>,
+impl TimestampArgs {
+    fn format(&self) -> Result<TimestampFormat> {
+        TimestampFormat::new(self.style, self.tz, self.pattern.clone())
+    }
+}
+
+impl ClientArgs {
+    /// Explicit rate limit from --rate-limit or the legacy --rate-limit-wait-secs
+    fn rate_limit(&self) -> Option<RateLimit> {
+        match (self.rate_limit, self.wait_secs) {
+            (Some(limit), _) => Some(limit),
+            (None, Some(0)) => Some(RateLimit::Unlimited),
+            (None, Some(secs)) => RateLimit::per_window(1, Duration::from_secs(secs)).ok(),
+            (None, None) => None,
+        }
+    }
+
+    /// Downloader configured from these settings
+    fn downloader(&self, provider: ProviderKind) -> Result<Downloader> {
+        let mut downloader = Downloader::new()?
+            .retry(RetryPolicy {
+                max_retries: self.max_retries,
+                base_delay: Duration::from_millis(self.retry_base_delay_ms),
+                max_delay: Duration::from_secs(self.retry_max_delay_secs),
+            })
+            .verbose(self.verbose);
+        if let Some(limit) = self.rate_limit() {
+            downloader = downloader.rate_limit(provider, limit);
+        }
+        Ok(downloader)
+    }
+
+    /// Apply the API key, base URL and chunking settings to `request`
+    fn apply(&self, request: DownloadRequest) -> DownloadRequest {
+        let mut request = request
+            .chunk(self.chunk)
+            .concurrency(self.chunk_concurrency)
+            .reorder_window(self.reorder_window);
+        if let Some(key) = &self.api_key {
+            request = request.api_key(key);
+        }
+        if let Some(base_url) = &self.base_url {
+            request = request.base_url(base_url);
+        }
+        request
+    }
 }
 
 #[tokio::main]
@@ -122,483 +447,613 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     match cli.command {
         Commands::Download(args) => download(args).await,
+        Commands::Update(args) => update(args).await,
+        Commands::Adjust(args) => adjust(args),
     }
 }
 
-// Format milliseconds since epoch into UTC timestamp string
-pub(crate) fn fmt_ts(ms: i64) -> String {
-    if let Some(dt) = Utc.timestamp_millis_opt(ms).single() {
-        dt.format("%Y-%m-%d %H:%M:%S").to_string()
-    } else {
-        ms.to_string()
-    }
+fn parse_time_of_day(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s.trim(), "%H:%M")
+        .with_context(|| format!("Invalid time of day '{}': expected HH:MM", s))
 }
 
-pub(crate) fn compute_out_path(
-    ticker: &str,
-    from: NaiveDate,
-    to: NaiveDate,
+/// Compression from --compress, else implied by the --out extension. The two
+/// must agree, since outputs are read back according to their extension.
+fn resolve_compression(
+    flag: Option<Compression>,
+    out: Option<&str>,
     format: OutputFormat,
-    out: &Option<String>,
-) -> String {
-    match out {
-        Some(p) => p.clone(),
-        None => {
-            let ext = match format {
-                OutputFormat::Csv => "csv",
-                OutputFormat::Json => "json",
-            };
-            // Place files under output/ instead of project root
-            format!("output/{}_{}_{}.{}", ticker, from, to, ext)
-        }
+) -> Result<Compression> {
+    if let (Some(flag), Some(out)) = (flag, out)
+        && Compression::from_path(out) != flag
+    {
+        return Err(anyhow!(
+            "--compress {} does not match the extension of {}; use --out {}{}",
+            flag.to_possible_value()
+                .map(|v| v.get_name().to_owned())
+                .unwrap_or_default(),
+            out,
+            Compression::strip_suffix(out),
+            flag.suffix()
+        ));
     }
+    let compression = flag.unwrap_or_else(|| out.map(Compression::from_path).unwrap_or_default());
+    if compression != Compression::None
+        && !matches!(
+            format,
+            OutputFormat::Csv | OutputFormat::Json | OutputFormat::Ndjson
+        )
+    {
+        return Err(anyhow!(
+            "--compress applies to CSV, JSON and NDJSON output, not {:?}",
+            format
+        ));
+    }
+    Ok(compression)
 }
 
-pub(crate) fn ensure_api_key_present(url: &mut Url, api_key: &str) {
-    let has_key = url.query_pairs().any(|(k, _)| k == "apiKey");
-    if !has_key {
-        url.query_pairs_mut().append_pair("apiKey", api_key);
-    }
+/// Whether new pages can be appended to an existing output. A compressed JSON
+/// array cannot be reopened to move its closing bracket.
+fn appendable(format: OutputFormat, compression: Compression) -> bool {
+    format.appendable() && !(format == OutputFormat::Json && compression != Compression::None)
 }
 
 async fn download(args: DownloadArgs) -> Result<()> {
-    // Resolve API key depending on provider
-    let api_key = match args.provider {
-        Provider::Polygon => args
-            .api_key
-            .clone()
-            .or_else(|| env::var("POLYGON_API_KEY").ok())
-            .ok_or_else(|| anyhow!(
-                "API key not provided. Use --apikey or set POLYGON_API_KEY."
-            ))?,
-        Provider::TwelveData => args
-            .api_key
-            .clone()
-            .or_else(|| env::var("TWELVEDATA_API_KEY").ok())
-            .ok_or_else(|| anyhow!(
-                "API key not provided. Use --apikey or set TWELVEDATA_API_KEY."
-            ))?,
-    };
-
-    if args.split_by_day && matches!(args.format, OutputFormat::Json) {
-        return Err(anyhow!("--split-by-day currently supports CSV format only"));
+    if args.split_by_day && !args.format.splittable() {
+        return Err(anyhow!(
+            "--split-by-day supports CSV and NDJSON formats only"
+        ));
+    }
+    let compression = resolve_compression(args.compress, args.out.as_deref(), args.format)?;
+    if args.resume && !args.split_by_day && !appendable(args.format, compression) {
+        return Err(anyhow!(
+            "--resume is not supported for {}{:?} output; rerun without it",
+            if compression == Compression::None {
+                ""
+            } else {
+                "compressed "
+            },
+            args.format
+        ));
     }
 
-    let out_path = compute_out_path(&args.ticker, args.from, args.to, args.format, &args.out);
+    let mut tickers = args.tickers.clone();
+    if let Some(path) = &args.tickers_file {
+        tickers.extend(read_tickers(path)?);
+    }
+    let mut seen = HashSet::new();
+    tickers.retain(|t| seen.insert(t.clone()));
+    if tickers.is_empty() {
+        return Err(anyhow!("No tickers given; use --ticker or --tickers-file"));
+    }
+    if tickers.len() > 1
+        && !args.format.multi_series()
+        && let Some(out) = &args.out
+        && !out.contains("{ticker}")
+    {
+        return Err(anyhow!(
+            "--out must contain {{ticker}} when downloading several tickers, e.g. data/{{ticker}}.csv"
+        ));
+    }
 
-    let client = reqwest::Client::builder()
-        .user_agent("market-data-downloader/0.1")
-        .build()?;
+    if tickers.len() > 1
+        && let Some(report) = &args.report
+        && !report.contains("{ticker}")
+    {
+        return Err(anyhow!(
+            "--report must contain {{ticker}} when downloading several tickers, e.g. reports/{{ticker}}.json"
+        ));
+    }
 
-    let mut writer_csv;
-    let mut wrote_any = false;
+    let resampler = args
+        .resample
+        .map(|interval| {
+            Resampler::new(interval)
+                .timezone(args.timestamps.tz)
+                .offset(args.resample_offset)
+                .boundaries(&args.session_boundaries)
+        })
+        .transpose()?;
 
-    enum Sink {
-        Csv(csv::Writer<std::fs::File>),
-        Json(std::fs::File),
-        None,
+    // One downloader for every ticker, so they share the provider's rate limit
+    let downloader = args.client.downloader(args.provider)?;
+    let output = Output {
+        compression,
+        resampler,
+        timestamps: args.timestamps.format()?,
+    };
+    if tickers.len() == 1 {
+        return download_ticker(&args, &downloader, &tickers[0], &output).await;
     }
-    let mut sink: Sink = Sink::None;
 
-    // Prepare provider-specific initial URL and paging
-    let mut page = 0usize;
-    let mut next: Option<Url>;
+    let results: Vec<(String, Result<()>)> = stream::iter(&tickers)
+        .map(|ticker| {
+            let downloader = &downloader;
+            let args = &args;
+            let output = &output;
+            async move {
+                let result = download_ticker(args, downloader, ticker, output).await;
+                if let Err(e) = &result {
+                    eprintln!("{}: {:#}", ticker, e);
+                }
+                (ticker.clone(), result)
+            }
+        })
+        .buffer_unordered(args.concurrency.max(1))
+        .collect()
+        .await;
 
-    match args.provider {
-        Provider::Polygon => {
-            let gran = match args.granularity {
-                Granularity::Minute => "minute",
-                Granularity::Day => "day",
-            };
-            let mut url = Url::parse(&format!(
-                "https://api.polygon.io/v2/aggs/ticker/{}/range/1/{}/{}/{}",
-                urlencoding::encode(&args.ticker),
-                gran,
-                args.from,
-                args.to
-            ))?;
-            url.query_pairs_mut()
-                .append_pair("adjusted", "true")
-                .append_pair("sort", "asc")
-                .append_pair("limit", "50000")
-                .append_pair("apiKey", &api_key);
-            next = Some(url);
-        }
-        Provider::TwelveData => {
-            let interval = match args.granularity {
-                Granularity::Minute => "1min",
-                Granularity::Day => "1day",
-            };
-            let mut url = Url::parse("https://api.twelvedata.com/time_series")?;
-            url.query_pairs_mut()
-                .append_pair("symbol", &args.ticker)
-                .append_pair("interval", interval)
-                .append_pair("start_date", &args.from.to_string())
-                .append_pair("end_date", &args.to.to_string())
-                .append_pair("order", "ASC")
-                .append_pair("timezone", "UTC")
-                .append_pair("format", "JSON")
-                .append_pair("outputsize", "5000")
-                .append_pair("apikey", &api_key);
-            next = Some(url);
-        }
+    let failed: Vec<&str> = results
+        .iter()
+        .filter(|(_, r)| r.is_err())
+        .map(|(ticker, _)| ticker.as_str())
+        .collect();
+    eprintln!(
+        "Downloaded {} of {} ticker(s)",
+        results.len() - failed.len(),
+        results.len()
+    );
+    if failed.is_empty() {
+        return Ok(());
     }
+    Err(anyhow!(
+        "{} of {} ticker(s) failed: {}",
+        failed.len(),
+        results.len(),
+        failed.join(", ")
+    ))
+}
 
-    loop {
-        let Some(fetch_url) = next.take() else { break };
-        page += 1;
-        if args.verbose > 0 {
-            eprintln!("Fetching page {}: {}", page, fetch_url);
-        }
-        let resp = client
-            .get(fetch_url.clone())
-            .send()
-            .await
-            .with_context(|| format!("Request failed: {}", fetch_url))?;
-
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let text = resp.text().await.unwrap_or_default();
-            if status.as_u16() == 403 {
-                return Err(anyhow!(
-                    "HTTP 403 Forbidden: {}\nHint: Your API key may not be entitled to this data. Try:\n- Using --granularity day (daily aggregates) instead of minute\n- Using a different ticker (e.g., equities like AAPL)\n- Upgrading your plan for minute/index data\nRequest URL: {}",
-                    text,
-                    fetch_url
-                ));
-            }
-            return Err(anyhow!("HTTP {}: {}", status, text));
-        }
+/// Tickers listed in `path`, one per line; blank lines and `#` comments are ignored
+fn read_tickers(path: &str) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path))?;
+    Ok(text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
 
-        // Parse response depending on provider and capture paging info if available
-        let (results, next_from_resp): (Vec<Agg>, Option<String>) = match args.provider {
-            Provider::Polygon => {
-                let aggs: AggsResponse = resp
-                    .json()
-                    .await
-                    .with_context(|| "Invalid JSON from API")?;
-                (aggs.results.unwrap_or_default(), aggs.next_url)
-            }
-            Provider::TwelveData => {
-                // Twelve Data response shape: { status, values: [ { datetime, open, high, low, close, volume }, ... ], next_page_token? }
-                #[derive(Deserialize)]
-                struct TDResp {
-                    status: Option<String>,
-                    values: Option<Vec<TDVal>>,
-                    #[serde(default)]
-                    next_page_token: Option<String>,
-                    #[allow(dead_code)]
-                    message: Option<String>,
-                }
-                #[derive(Deserialize)]
-                struct TDVal {
-                    datetime: String,
-                    open: String,
-                    high: String,
-                    low: String,
-                    close: String,
-                    #[serde(default)]
-                    volume: Option<String>,
-                }
-                let td: TDResp = resp.json().await.with_context(|| "Invalid JSON from Twelve Data API")?;
-                if let Some(s) = &td.status {
-                    if s.eq_ignore_ascii_case("error") {
-                        let msg = td.message.unwrap_or_else(|| String::from("Unknown Twelve Data error"));
-                        return Err(anyhow!("Twelve Data API error: {}", msg));
-                    }
-                }
-                let mut vec = Vec::new();
-                if let Some(vals) = td.values {
-                    for v in vals {
-                        // Parse datetime as UTC
-                        let dt = chrono::NaiveDateTime::parse_from_str(&v.datetime, "%Y-%m-%d %H:%M:%S")
-                            .or_else(|_| chrono::NaiveDateTime::parse_from_str(&v.datetime, "%Y-%m-%d"))
-                            .with_context(|| format!("Invalid datetime in Twelve Data response: {}", v.datetime))?;
-                        let ts = Utc.from_utc_datetime(&dt).timestamp_millis();
-                        let parsef = |s: &str| -> Result<f64> { Ok(s.parse::<f64>()?) };
-                        let o = parsef(&v.open)?;
-                        let h = parsef(&v.high)?;
-                        let l = parsef(&v.low)?;
-                        let c = parsef(&v.close)?;
-                        let vol = match v.volume.as_deref() { Some(s) if !s.is_empty() => Some(s.parse::<f64>()?), _ => None };
-                        vec.push(Agg { t: ts, o, h, l, c, v: vol, vw: None, n: None });
-                    }
-                }
-                let next_token = td.next_page_token;
-                (vec, next_token)
-            }
-        };
+/// Settings of `download` resolved once for all tickers
+struct Output {
+    compression: Compression,
+    resampler: Option<Resampler>,
+    timestamps: TimestampFormat,
+}
 
-        if args.split_by_day {
-            // Write each record into per-day CSV under output/YYYY/MM/TICKER_YYYY-MM-DD.csv
-            use std::fs::{OpenOptions, create_dir_all, metadata};
-            use std::io::Write as _;
-            let prec = args.max_decimals as usize;
-            for r in &results {
-                if let Some(dt) = Utc.timestamp_millis_opt(r.t).single() {
-                    let date = dt.date_naive();
-                    let year = date.year();
-                    let month = date.month();
-                    let day = date.day();
-                    let dir = format!("output/{}/{:02}", year, month);
-                    create_dir_all(&dir)
-                        .with_context(|| format!("Cannot create directory {}", dir))?;
-                    let file_path = format!(
-                        "{}/{}_{}-{:02}-{:02}.csv",
-                        dir, args.ticker, year, month, day
-                    );
-                    let mut file = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(&file_path)
-                        .with_context(|| format!("Cannot open {}", file_path))?;
-                    let is_new = metadata(&file_path).map(|m| m.len() == 0).unwrap_or(true);
-                    // Write header if new and not omitted
-                    if is_new && !args.no_header {
-                        writeln!(file, "ticker,timestamp,open,high,low,close,volume").ok();
-                    }
-                    let ts = fmt_ts(r.t);
-                    let o = format!("{:.1$}", r.o, prec);
-                    let h = format!("{:.1$}", r.h, prec);
-                    let l = format!("{:.1$}", r.l, prec);
-                    let c = format!("{:.1$}", r.c, prec);
-                    let v = match r.v {
-                        Some(val) => format!("{:.1$}", val, prec),
-                        None => String::from(""),
-                    };
-                    writeln!(file, "{},{},{},{},{},{},{}", args.ticker, ts, o, h, l, c, v).ok();
-                }
-            }
-            wrote_any = wrote_any || !results.is_empty();
+async fn download_ticker(
+    args: &DownloadArgs,
+    downloader: &Downloader,
+    ticker: &str,
+    output: &Output,
+) -> Result<()> {
+    let compression = output.compression;
+    let mut out_path = compute_out_path(ticker, args.from, args.to, args.format, &args.out);
+    if args.out.is_none() {
+        out_path.push_str(compression.suffix());
+    }
+    // Resampled bars are stored under their own interval, e.g. in SQLite's
+    // granularity column, not under that of the downloaded ones
+    let (granularity, multiplier) = args
+        .resample
+        .map(Interval::granularity)
+        .unwrap_or((args.granularity, args.multiplier));
+    let sink_opts = SinkOptions {
+        ticker: ticker.to_string(),
+        no_header: args.no_header,
+        max_decimals: args.max_decimals,
+        provider: args.provider,
+        granularity,
+        multiplier,
+        compression,
+        timestamps: output.timestamps.clone(),
+    };
+    let open = |append: bool| -> Result<Box<dyn Sink>> {
+        let sink: Box<dyn Sink> = if args.split_by_day {
+            // A resumed day may already hold the bars written before the interruption
+            let mode = if append {
+                SplitMode::Merge
+            } else {
+                args.split_mode
+            };
+            Box::new(SplitByDaySink::new(&sink_opts, mode).with_format(args.format))
+        } else if append {
+            open_sink_append(args.format, &out_path, &sink_opts)?
         } else {
-            if !wrote_any && !results.is_empty() {
-                // Open sink lazily
-                match args.format {
-                    OutputFormat::Csv => {
-                        // Ensure parent directory exists if path includes directories
-                        if let Some(parent) = std::path::Path::new(&out_path).parent() {
-                            if !parent.as_os_str().is_empty() {
-                                std::fs::create_dir_all(parent).with_context(|| {
-                                    format!("Cannot create directory {}", parent.display())
-                                })?;
-                            }
-                        }
-                        let file = std::fs::File::create(&out_path)
-                            .with_context(|| format!("Cannot create {}", out_path))?;
-                        writer_csv = csv::Writer::from_writer(file);
-                        // write header (unless omitted)
-                        if !args.no_header {
-                            writer_csv
-                                .write_record([
-                                    "ticker",
-                                    "timestamp",
-                                    "open",
-                                    "high",
-                                    "low",
-                                    "close",
-                                    "volume",
-                                ])
-                                .ok();
-                        }
-                        sink = Sink::Csv(writer_csv);
-                    }
-                    OutputFormat::Json => {
-                        // Ensure parent directory exists if path includes directories
-                        if let Some(parent) = std::path::Path::new(&out_path).parent() {
-                            if !parent.as_os_str().is_empty() {
-                                std::fs::create_dir_all(parent).with_context(|| {
-                                    format!("Cannot create directory {}", parent.display())
-                                })?;
-                            }
-                        }
-                        let file = std::fs::File::create(&out_path)
-                            .with_context(|| format!("Cannot create {}", out_path))?;
-                        // Write opening bracket for an array
-                        use std::io::Write;
-                        write!(&file, "[").ok();
-                        sink = Sink::Json(file);
-                    }
-                }
-            }
+            open_sink(args.format, &out_path, &sink_opts)?
+        };
+        Ok(match &output.resampler {
+            Some(resampler) => Box::new(ResampleSink::new(sink, resampler.clone())),
+            None => sink,
+        })
+    };
 
-            match &mut sink {
-                Sink::Csv(w) => {
-                    let prec = args.max_decimals as usize;
-                    for r in &results {
-                        let ts = fmt_ts(r.t);
-                        let o = format!("{:.1$}", r.o, prec);
-                        let h = format!("{:.1$}", r.h, prec);
-                        let l = format!("{:.1$}", r.l, prec);
-                        let c = format!("{:.1$}", r.c, prec);
-                        let v = match r.v {
-                            Some(val) => format!("{:.1$}", val, prec),
-                            None => String::new(),
-                        };
-                        w.write_record(&[
-                            args.ticker.as_str(),
-                            ts.as_str(),
-                            o.as_str(),
-                            h.as_str(),
-                            l.as_str(),
-                            c.as_str(),
-                            v.as_str(),
-                        ])
-                        .ok();
-                    }
-                    w.flush().ok();
+    let mut request = args.client.apply(
+        DownloadRequest::new(ticker, args.from, args.to)
+            .granularity(args.granularity)
+            .multiplier(args.multiplier)
+            .session(args.session)
+            .calendar(args.calendar)
+            .adjustment(args.adjustment)
+            .provider(args.provider),
+    );
+
+    let checkpoint_path = if args.format.multi_series() {
+        // Tickers of a batch share the database but not their progress
+        Checkpoint::path_for(&format!("{}.{}", out_path, ticker))
+    } else {
+        Checkpoint::path_for(&out_path)
+    };
+    let key = CheckpointKey {
+        ticker: ticker.to_string(),
+        provider: args.provider,
+        granularity: args.granularity,
+        multiplier: args.multiplier,
+        from: args.from,
+        to: args.to,
+        format: args.format,
+        split_by_day: args.split_by_day,
+        split_mode: args.split_mode,
+        session: args.session,
+        adjustment: args.adjustment,
+        compression,
+        tz: output.timestamps.tz,
+        timestamp_style: output.timestamps.style,
+        timestamp_pattern: output.timestamps.pattern.clone(),
+        max_decimals: args.max_decimals,
+        no_header: args.no_header,
+    };
+    let mut checkpoint = Checkpoint::new(key.clone());
+    // Open the sink lazily so that an empty download leaves no file behind
+    let mut sink: Option<Box<dyn Sink>> = None;
+    let mut fetch = true;
+    let mut validator = Validator::new(args.validate);
+    let calendar = args.calendar.resolve(ticker);
+    // A resumed download only sees the bars of its last run
+    let mut tracker = (!args.resume).then(|| {
+        Tracker::new(
+            calendar,
+            args.from,
+            args.to,
+            args.granularity,
+            args.multiplier,
+            args.session,
+        )
+        .window(args.client.reorder_window)
+    });
+
+    if args.resume {
+        match Checkpoint::load(&checkpoint_path)? {
+            Some(saved) => {
+                saved.ensure_matches(&key, &checkpoint_path)?;
+                eprintln!(
+                    "Resuming after {} page(s) from {}",
+                    saved.pages, checkpoint_path
+                );
+                if let Some(len) = saved.bytes_written {
+                    truncate_output(&out_path, len)?;
+                    sink = Some(open(true)?);
+                } else if args.split_by_day && saved.last_ts.is_some() {
+                    sink = Some(open(true)?);
                 }
-                Sink::Json(f) => {
-                    use std::io::Write;
-                    let prec = args.max_decimals as i32;
-                    let pow = 10f64.powi(prec);
-                    let round_to = |x: f64| (x * pow).round() / pow;
-                    for (i, r) in results.iter().enumerate() {
-                        if wrote_any || i > 0 {
-                            write!(f, ",").ok();
-                        }
-                        let obj = serde_json::json!({
-                            "timestamp": fmt_ts(r.t),
-                            "open": round_to(r.o),
-                            "high": round_to(r.h),
-                            "low": round_to(r.l),
-                            "close": round_to(r.c),
-                            "volume": r.v.map(|v| round_to(v)),
-                            "vw": r.vw.map(|x| round_to(x)),
-                            "n": r.n,
-                        });
-                        write!(f, "{}", obj).ok();
+                match (saved.next_from, &saved.next_url) {
+                    // Every page was written; only the sink is left to close
+                    (None, None) => fetch = false,
+                    (from, next) => {
+                        let next = next
+                            .as_deref()
+                            .map(Url::parse)
+                            .transpose()
+                            .with_context(|| format!("Invalid URL in {}", checkpoint_path))?;
+                        let cursor = Cursor {
+                            from: from.unwrap_or(args.from),
+                            next,
+                        };
+                        request = request
+                            .resume(cursor, saved.last_ts)
+                            .pending(saved.pending.clone());
                     }
-                    f.flush().ok();
                 }
-                Sink::None => {}
+                checkpoint = saved;
             }
-            wrote_any = wrote_any || !results.is_empty();
+            None => eprintln!(
+                "No checkpoint at {}; starting from the beginning",
+                checkpoint_path
+            ),
         }
+    }
 
-        // Determine next page
-        next = match args.provider {
-            Provider::Polygon => match next_from_resp {
-                Some(next_url) => {
-                    let mut u = Url::parse(&next_url)?;
-                    ensure_api_key_present(&mut u, &api_key);
-                    Some(u)
+    // Parquet and Arrow files are only readable once their footer is
+    // written, so there is nothing to resume after a failure. Resampling
+    // holds back the bucket in progress, which a checkpoint cannot capture.
+    let resumable =
+        (args.split_by_day || appendable(args.format, compression)) && output.resampler.is_none();
+    if fetch {
+        let mut pages = pin!(downloader.pages(request)?);
+        loop {
+            let mut page = match pages.try_next().await {
+                Ok(Some(page)) => page,
+                Ok(None) => break,
+                Err(e) if checkpoint.pages > 0 && resumable => {
+                    return Err(e.context(format!(
+                        "Download interrupted after {} page(s); rerun with --resume to continue",
+                        checkpoint.pages
+                    )));
                 }
-                None => None,
-            },
-            Provider::TwelveData => match next_from_resp {
-                Some(token) => {
-                    // Build next page URL by adding page_token parameter
-                    let mut u = fetch_url.clone();
-                    // remove any existing page_token before appending
-                    let existing: Vec<(String, String)> = u.query_pairs().map(|(k,v)| (k.to_string(), v.to_string())).collect();
-                    u.set_query(None);
-                    {
-                        let mut qp = u.query_pairs_mut();
-                        for (k, v) in existing {
-                            if k != "page_token" && k != "next_page_token" { qp.append_pair(&k, &v); }
-                        }
-                        // Twelve Data uses page_token as request param
-                        qp.append_pair("page_token", &token);
-                    }
-                    Some(u)
+                Err(e) => return Err(e),
+            };
+            // The report covers what the provider sent, before repeats and
+            // late bars are left out
+            if let Some(tracker) = &mut tracker {
+                tracker.observe(&page.received);
+            }
+            validator.apply(&mut page.bars, &output.timestamps)?;
+            if !page.bars.is_empty() {
+                let sink = match &mut sink {
+                    Some(sink) => sink,
+                    None => sink.insert(open(false)?),
+                };
+                sink.write_batch(&page.bars)?;
+                checkpoint.last_ts = page.bars.last().map(|b| b.t);
+                // Upserts make a repeated page harmless, so SQLite needs no truncation
+                if !args.split_by_day && !args.format.multi_series() {
+                    checkpoint.bytes_written = Some(std::fs::metadata(&out_path)?.len());
                 }
-                None => None,
-            },
-        };
-
-        if next.is_some() {
-            if args.verbose > 0 {
-                eprintln!("Sleeping {}s to respect rate limit...", args.wait_secs);
             }
-            tokio::time::sleep(Duration::from_secs(args.wait_secs)).await;
-        } else {
-            if args.verbose > 0 {
-                eprintln!("Done. Total pages: {}", page);
+            checkpoint.pages += 1;
+            checkpoint.next_from = page.next.as_ref().map(|c| c.from);
+            checkpoint.next_url = page.next.and_then(|c| c.next).map(String::from);
+            checkpoint.pending = page.pending;
+            if resumable {
+                checkpoint.save(&checkpoint_path)?;
             }
-            break;
         }
     }
 
-    // Close JSON array if needed
-    if let Sink::Json(mut f) = sink {
-        use std::io::Write;
-        write!(&mut f, "]").ok();
-        f.flush().ok();
+    match sink {
+        None => eprintln!(
+            "No data returned for {} between {} and {}{}",
+            ticker,
+            args.from,
+            args.to,
+            closed_note(calendar, args.from, args.to)
+        ),
+        Some(sink) => {
+            sink.finish()?;
+            if args.split_by_day {
+                eprintln!("Saved {} per-day files under output/YYYY/MM", ticker);
+            } else {
+                eprintln!("Saved to {}", out_path);
+            }
+        }
     }
+    Checkpoint::remove(&checkpoint_path)?;
 
-    if !wrote_any {
-        eprintln!(
-            "No data returned for {} between {} and {}",
-            args.ticker, args.from, args.to
-        );
-    } else if args.split_by_day {
-        eprintln!("Saved per-day CSV files under output/YYYY/MM");
-    } else {
-        eprintln!("Saved to {}", out_path);
+    if let Some(summary) = validator.summary(&output.timestamps) {
+        eprint!("{}: {}", ticker, summary);
+    }
+    if let Some(tracker) = tracker {
+        let report = tracker.report(ticker);
+        eprint!("{}", report.table(&output.timestamps));
+        if let Some(path) = &args.report {
+            let path = path.replace("{ticker}", ticker);
+            let json = serde_json::to_string_pretty(&report)?;
+            std::fs::write(&path, json + "\n")
+                .with_context(|| format!("Cannot write report {}", path))?;
+        }
     }
 
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use chrono::NaiveDate;
-
-    #[test]
-    fn test_fmt_ts_zero() {
-        assert_eq!(fmt_ts(0), "1970-01-01 00:00:00");
+/// Why `from..=to` has no data when `calendar` has no session in it,
+/// e.g. " (no NYSE trading days: weekend, Christmas Day)"; empty otherwise
+fn closed_note(calendar: Calendar, from: NaiveDate, to: NaiveDate) -> String {
+    if calendar.trim((from, to)).is_some() {
+        return String::new();
     }
-
-    #[test]
-    fn test_fmt_ts_known() {
-        // 2024-04-01 00:00:00 UTC in ms
-        let ts = 1711929600000i64;
-        assert_eq!(fmt_ts(ts), "2024-04-01 00:00:00");
+    let mut reasons: Vec<&str> = Vec::new();
+    for reason in from
+        .iter_days()
+        .take_while(|d| *d <= to)
+        .filter_map(|d| calendar.closed_reason(d))
+    {
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+        }
     }
+    format!(
+        " (no {} trading days: {})",
+        calendar.name(),
+        reasons.join(", ")
+    )
+}
 
-    #[test]
-    fn test_compute_out_path_defaults_csv() {
-        let d1 = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
-        let d2 = NaiveDate::from_ymd_opt(2025, 1, 31).unwrap();
-        let out = compute_out_path("I:NDX", d1, d2, OutputFormat::Csv, &None);
-        assert_eq!(out, "output/I:NDX_2025-01-01_2025-01-31.csv");
+async fn update(args: UpdateArgs) -> Result<()> {
+    let format = match (&args.format, &args.out) {
+        (Some(format), _) => *format,
+        (None, Some(out)) => infer_format(out),
+        (None, None) => OutputFormat::Csv,
+    };
+    if args.split_by_day && !format.splittable() {
+        return Err(anyhow!(
+            "--split-by-day supports CSV and NDJSON formats only"
+        ));
+    }
+    let out = if args.split_by_day {
+        None
+    } else {
+        args.out.as_deref()
+    };
+    let compression = resolve_compression(args.compress, out, format)?;
+    if !args.split_by_day && !appendable(format, compression) {
+        return Err(anyhow!(
+            "update cannot append to {}{:?} output",
+            if compression == Compression::None {
+                ""
+            } else {
+                "compressed "
+            },
+            format
+        ));
     }
 
-    #[test]
-    fn test_compute_out_path_defaults_json() {
-        let d1 = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
-        let d2 = NaiveDate::from_ymd_opt(2024, 2, 2).unwrap();
-        let out = compute_out_path("AAPL", d1, d2, OutputFormat::Json, &None);
-        assert_eq!(out, "output/AAPL_2024-02-01_2024-02-02.json");
+    let sink_opts = SinkOptions {
+        ticker: args.ticker.clone(),
+        no_header: args.no_header,
+        max_decimals: args.max_decimals,
+        provider: args.provider,
+        granularity: args.granularity,
+        multiplier: args.multiplier,
+        compression,
+        timestamps: args.timestamps.format()?,
+    };
+
+    let (target, last) = if args.split_by_day {
+        match latest_day_file(SPLIT_ROOT, &args.ticker, format, compression)? {
+            Some((_, path)) => {
+                let path = path.to_string_lossy().into_owned();
+                let last = last_timestamp(&path, format, &sink_opts.timestamps)?;
+                (path, last)
+            }
+            None => (format!("{}/YYYY/MM", SPLIT_ROOT), None),
+        }
+    } else {
+        let out = args.out.clone().unwrap_or_default();
+        let last = if format == OutputFormat::Sqlite {
+            sqlite::last_timestamp(&out, &sink_opts)?
+        } else {
+            last_timestamp(&out, format, &sink_opts.timestamps)?
+        };
+        (out, last)
+    };
+
+    let from = match (last, args.from) {
+        (Some(ts), _) => Utc
+            .timestamp_millis_opt(ts)
+            .single()
+            .map(|dt| dt.date_naive())
+            .ok_or_else(|| anyhow!("Invalid last timestamp {} in {}", ts, target))?,
+        (None, Some(from)) => from,
+        (None, None) => {
+            return Err(anyhow!(
+                "No existing data for {} in {}; pass --from to start a new download",
+                args.ticker,
+                target
+            ));
+        }
+    };
+    let to = args.to.unwrap_or_else(|| Utc::now().date_naive());
+    if from > to {
+        eprintln!("{} is already up to date", target);
+        return Ok(());
+    }
+    if args.client.verbose > 0 {
+        match last {
+            Some(ts) => eprintln!("Last bar in {}: {}", target, fmt_ts(ts)),
+            None => eprintln!("Starting new output {}", target),
+        }
     }
 
-    #[test]
-    fn test_compute_out_path_respects_explicit() {
-        let d1 = NaiveDate::from_ymd_opt(2025, 9, 1).unwrap();
-        let d2 = NaiveDate::from_ymd_opt(2025, 9, 4).unwrap();
-        let explicit = Some(String::from("custom.csv"));
-        let out = compute_out_path("I:SPX", d1, d2, OutputFormat::Csv, &explicit);
-        assert_eq!(out, "custom.csv");
+    let downloader = args.client.downloader(args.provider)?;
+    let mut request = args.client.apply(
+        DownloadRequest::new(&args.ticker, from, to)
+            .granularity(args.granularity)
+            .multiplier(args.multiplier)
+            .session(args.session)
+            .calendar(args.calendar)
+            .adjustment(args.adjustment)
+            .provider(args.provider),
+    );
+    if let Some(ts) = last {
+        request = request.after(ts);
     }
 
-    #[test]
-    fn test_ensure_api_key_present_adds_when_missing() {
-        let mut u = Url::parse("https://example.com/path?foo=1").unwrap();
-        ensure_api_key_present(&mut u, "KEY123");
-        let query: Vec<_> = u.query_pairs().collect();
-        assert!(query.iter().any(|(k, v)| k == "apiKey" && v == "KEY123"));
+    let mut pages = pin!(downloader.pages(request)?);
+    let mut sink: Option<Box<dyn Sink>> = None;
+    let mut appended = 0usize;
+    let mut validator = Validator::new(args.validate);
+    while let Some(mut page) = pages.try_next().await? {
+        validator.apply(&mut page.bars, &sink_opts.timestamps)?;
+        if page.bars.is_empty() {
+            continue;
+        }
+        let sink = match &mut sink {
+            Some(sink) => sink,
+            None => sink.insert(if args.split_by_day {
+                // Merge so the partially filled last day is extended, not replaced
+                Box::new(SplitByDaySink::new(&sink_opts, SplitMode::Merge).with_format(format))
+            } else {
+                open_sink_append(format, &target, &sink_opts)?
+            }),
+        };
+        sink.write_batch(&page.bars)?;
+        appended += page.bars.len();
     }
 
-    #[test]
-    fn test_ensure_api_key_present_keeps_when_present() {
-        let mut u = Url::parse("https://example.com/path?apiKey=ABC&x=1").unwrap();
-        ensure_api_key_present(&mut u, "SHOULD_NOT_OVERRIDE");
-        // ensure existing value is not overridden
-        let pairs: Vec<_> = u.query_pairs().collect();
-        let found: Vec<_> = pairs.into_iter().filter(|(k, _)| k == "apiKey").collect();
-        assert_eq!(found.len(), 1);
-        assert_eq!(found[0].1, "ABC");
+    match sink {
+        None => eprintln!(
+            "No new data for {} between {} and {}{}; {} is up to date",
+            args.ticker,
+            from,
+            to,
+            closed_note(args.calendar.resolve(&args.ticker), from, to),
+            target
+        ),
+        Some(sink) => {
+            sink.finish()?;
+            if args.split_by_day {
+                eprintln!(
+                    "Appended {} bar(s) to per-day files under output/YYYY/MM",
+                    appended
+                );
+            } else {
+                eprintln!("Appended {} bar(s) to {}", appended, target);
+            }
+        }
     }
+    if let Some(summary) = validator.summary(&sink_opts.timestamps) {
+        eprint!("{}: {}", args.ticker, summary);
+    }
+
+    Ok(())
+}
+
+fn adjust(args: AdjustArgs) -> Result<()> {
+    let timestamps = args.timestamps.format()?;
+    let input_format = args
+        .input_format
+        .unwrap_or_else(|| infer_format(&args.input));
+    let mut bars = read_bars(&args.input, input_format, &timestamps)?;
+    bars.sort_by_key(|bar| bar.t);
+    let actions = read_actions(&args.actions, &args.ticker)?;
+    let tz = args.calendar.resolve(&args.ticker).tz();
+    Adjuster::new(actions, args.adjustment, tz)
+        .apply(&mut bars)
+        .with_context(|| format!("Cannot adjust {}", args.input))?;
+
+    let format = args.format.unwrap_or_else(|| infer_format(&args.out));
+    let sink_opts = SinkOptions {
+        ticker: args.ticker.clone(),
+        no_header: args.no_header,
+        max_decimals: args.max_decimals,
+        provider: args.provider,
+        granularity: args.granularity,
+        multiplier: args.multiplier,
+        compression: resolve_compression(None, Some(&args.out), format)?,
+        timestamps,
+    };
+    let mut sink = open_sink(format, &args.out, &sink_opts)?;
+    sink.write_batch(&bars)?;
+    sink.finish()?;
+    eprintln!("Saved {} adjusted bar(s) to {}", bars.len(), args.out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
     fn test_cli_parses_no_header_flag() {
@@ -613,7 +1068,9 @@ mod tests {
             "-T",
             "2025-01-01",
         ]);
-        let Commands::Download(args) = cli.command;
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
         assert!(!args.no_header);
 
         // explicit true via --no-header
@@ -628,7 +1085,9 @@ mod tests {
             "2025-01-01",
             "--no-header",
         ]);
-        let Commands::Download(args2) = cli2.command;
+        let Commands::Download(args2) = cli2.command else {
+            unreachable!()
+        };
         assert!(args2.no_header);
     }
 
@@ -644,7 +1103,9 @@ mod tests {
             "-T",
             "2025-01-01",
         ]);
-        let Commands::Download(args) = cli.command;
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
         assert_eq!(args.max_decimals, 2);
     }
 
@@ -662,7 +1123,9 @@ mod tests {
             "--max-decimals",
             "4",
         ]);
-        let Commands::Download(args) = cli.command;
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
         assert_eq!(args.max_decimals, 4);
     }
 
@@ -679,7 +1142,9 @@ mod tests {
             "-T",
             "2025-01-01",
         ]);
-        let Commands::Download(args) = cli.command;
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
         assert!(!args.split_by_day);
 
         // explicit
@@ -694,7 +1159,9 @@ mod tests {
             "2025-01-01",
             "--split-by-day",
         ]);
-        let Commands::Download(args2) = cli2.command;
+        let Commands::Download(args2) = cli2.command else {
+            unreachable!()
+        };
         assert!(args2.split_by_day);
     }
 
@@ -710,8 +1177,10 @@ mod tests {
             "-T",
             "2025-01-01",
         ]);
-        let Commands::Download(args) = cli.command;
-        assert!(matches!(args.provider, Provider::Polygon));
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
+        assert!(matches!(args.provider, ProviderKind::Polygon));
     }
 
     #[test]
@@ -728,7 +1197,198 @@ mod tests {
             "--provider",
             "twelvedata",
         ]);
-        let Commands::Download(args) = cli.command;
-        assert!(matches!(args.provider, Provider::TwelveData));
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
+        assert!(matches!(args.provider, ProviderKind::TwelveData));
+    }
+
+    #[test]
+    fn test_cli_base_url() {
+        let cli = Cli::parse_from([
+            "market-data-downloader",
+            "download",
+            "-t",
+            "AAPL",
+            "-f",
+            "2025-01-01",
+            "-T",
+            "2025-01-01",
+            "--base-url",
+            "http://127.0.0.1:8080",
+        ]);
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
+        assert_eq!(
+            args.client.base_url.as_deref(),
+            Some("http://127.0.0.1:8080")
+        );
+    }
+
+    #[test]
+    fn test_cli_rate_limit() {
+        let base = [
+            "market-data-downloader",
+            "download",
+            "-t",
+            "AAPL",
+            "-f",
+            "2025-01-01",
+            "-T",
+            "2025-01-01",
+        ];
+        let Commands::Download(args) = Cli::parse_from(base).command else {
+            unreachable!()
+        };
+        assert_eq!(args.client.rate_limit(), None);
+
+        let Commands::Download(args) =
+            Cli::parse_from(base.iter().chain(&["--rate-limit", "8/min"])).command
+        else {
+            unreachable!()
+        };
+        assert_eq!(args.client.rate_limit(), Some("8/min".parse().unwrap()));
+
+        let Commands::Download(args) =
+            Cli::parse_from(base.iter().chain(&["--rate-limit-wait-secs", "0"])).command
+        else {
+            unreachable!()
+        };
+        assert_eq!(args.client.rate_limit(), Some(RateLimit::Unlimited));
+
+        let Commands::Download(args) =
+            Cli::parse_from(base.iter().chain(&["--rate-limit-wait-secs", "12"])).command
+        else {
+            unreachable!()
+        };
+        assert_eq!(args.client.rate_limit(), Some("1/12s".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cli_update_requires_out_unless_split_by_day() {
+        assert!(Cli::try_parse_from(["market-data-downloader", "update", "-t", "AAPL"]).is_err());
+        let cli = Cli::parse_from([
+            "market-data-downloader",
+            "update",
+            "-t",
+            "AAPL",
+            "--split-by-day",
+        ]);
+        let Commands::Update(args) = cli.command else {
+            unreachable!()
+        };
+        assert!(args.split_by_day);
+        assert!(args.to.is_none());
+    }
+
+    #[test]
+    fn test_cli_resample_offset_conflicts_with_session_boundaries() {
+        let args = |extra: &[&'static str]| {
+            let mut args = vec![
+                "market-data-downloader",
+                "download",
+                "-t",
+                "AAPL",
+                "-f",
+                "2025-01-01",
+                "-T",
+                "2025-01-01",
+                "--resample",
+                "1h",
+            ];
+            args.extend_from_slice(extra);
+            args
+        };
+        assert!(Cli::try_parse_from(args(&["--session-boundary", "14:30"])).is_ok());
+        assert!(Cli::try_parse_from(args(&["--resample-offset", "30m"])).is_ok());
+        assert!(
+            Cli::try_parse_from(args(&[
+                "--resample-offset",
+                "30m",
+                "--session-boundary",
+                "14:30"
+            ]))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_compress_must_match_out_extension() {
+        let csv = OutputFormat::Csv;
+        assert_eq!(
+            resolve_compression(None, Some("a.csv.gz"), csv).unwrap(),
+            Compression::Gzip
+        );
+        assert_eq!(
+            resolve_compression(Some(Compression::Zstd), Some("a.csv.zst"), csv).unwrap(),
+            Compression::Zstd
+        );
+        assert_eq!(
+            resolve_compression(Some(Compression::Gzip), None, csv).unwrap(),
+            Compression::Gzip
+        );
+        let err = resolve_compression(Some(Compression::Gzip), Some("a.csv"), csv).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--compress gzip does not match the extension of a.csv; use --out a.csv.gz"
+        );
+        assert!(resolve_compression(Some(Compression::None), Some("a.csv.gz"), csv).is_err());
+    }
+
+    #[test]
+    fn test_cli_multiple_tickers() {
+        let cli = Cli::parse_from([
+            "market-data-downloader",
+            "download",
+            "-t",
+            "AAPL,MSFT",
+            "-t",
+            "I:NDX",
+            "-f",
+            "2025-01-01",
+            "-T",
+            "2025-01-01",
+            "--concurrency",
+            "4",
+        ]);
+        let Commands::Download(args) = cli.command else {
+            unreachable!()
+        };
+        assert_eq!(args.tickers, vec!["AAPL", "MSFT", "I:NDX"]);
+        assert_eq!(args.concurrency, 4);
+
+        // A tickers file replaces --ticker
+        let cli = Cli::try_parse_from([
+            "market-data-downloader",
+            "download",
+            "--tickers-file",
+            "universe.txt",
+            "-f",
+            "2025-01-01",
+            "-T",
+            "2025-01-01",
+        ]);
+        assert!(cli.is_ok());
+        assert!(
+            Cli::try_parse_from([
+                "market-data-downloader",
+                "download",
+                "-f",
+                "2025-01-01",
+                "-T",
+                "2025-01-01",
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_read_tickers_skips_blanks_and_comments() {
+        let path = std::env::temp_dir().join(format!("mdd-tickers-{}.txt", std::process::id()));
+        std::fs::write(&path, "# universe\nAAPL\n\n  MSFT  # software\nI:NDX\n").unwrap();
+        let tickers = read_tickers(path.to_str().unwrap()).unwrap();
+        assert_eq!(tickers, vec!["AAPL", "MSFT", "I:NDX"]);
+        let _ = std::fs::remove_file(path);
     }
 }
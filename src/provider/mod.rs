@@ -0,0 +1,210 @@
+//! Data provider abstraction.
+//!
+//! Each provider knows how to build its first request, turn a response body
+//! into normalized [`Agg`] bars, and derive the follow-up request from the
+//! paging information it returned. The paging loop and the sinks only talk to
+//! the [`Provider`] trait, so adding a new source does not touch them.
+
+use std::env;
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::Chunk;
+use crate::ratelimit::RateLimit;
+use crate::{Adjustment, Agg, Granularity};
+
+mod polygon;
+mod twelvedata;
+
+pub use polygon::Polygon;
+pub use twelvedata::TwelveData;
+
+/// Built-in providers selectable from the CLI
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum, Debug, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[value(name = "polygon", alias = "polygon")]
+    Polygon,
+    #[value(name = "twelvedata", aliases = ["twelve-data", "twelve_data"])]
+    TwelveData,
+}
+
+impl ProviderKind {
+    pub fn provider(self) -> Box<dyn Provider> {
+        match self {
+            ProviderKind::Polygon => Box::new(Polygon),
+            ProviderKind::TwelveData => Box::new(TwelveData),
+        }
+    }
+}
+
+/// Parameters of a single download, independent of the provider.
+#[derive(Debug, Clone)]
+pub struct Query<'a> {
+    pub ticker: &'a str,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub granularity: Granularity,
+    /// Number of `granularity` timespans per bar, e.g. 5 for 5-minute bars
+    pub multiplier: u32,
+    /// Corporate actions the prices are adjusted for
+    pub adjustment: Adjustment,
+}
+
+/// One parsed response page.
+#[derive(Debug, Default)]
+pub struct Page {
+    pub results: Vec<Agg>,
+    /// Provider-specific paging cursor (a URL for Polygon, a token for Twelve Data)
+    pub next: Option<String>,
+}
+
+pub trait Provider: Send + Sync {
+    /// Name used in log and error messages
+    fn name(&self) -> &'static str;
+
+    /// Environment variable consulted when no API key is passed explicitly
+    fn api_key_env(&self) -> &'static str;
+
+    /// Query parameter carrying the API key
+    fn api_key_param(&self) -> &'static str;
+
+    /// Environment variable that overrides [`Provider::default_base_url`]
+    fn base_url_env(&self) -> &'static str;
+
+    /// Scheme and host of the public API, without a trailing slash
+    fn default_base_url(&self) -> &'static str;
+
+    /// Request budget of the provider's entry-level plan
+    fn default_rate_limit(&self) -> RateLimit;
+
+    /// How to split a long range so that each request fits in a few pages
+    fn default_chunk(&self, granularity: Granularity) -> Chunk;
+
+    /// Fail when the provider has no bars of `multiplier` x `granularity`
+    fn check_interval(&self, granularity: Granularity, multiplier: u32) -> Result<()>;
+
+    /// Fail when the provider cannot adjust prices as `adjustment` asks
+    fn check_adjustment(&self, adjustment: Adjustment) -> Result<()> {
+        let _ = adjustment;
+        Ok(())
+    }
+
+    /// Build the URL of the first page for `query` against `base_url`
+    fn first_request(&self, base_url: &str, query: &Query<'_>, api_key: &str) -> Result<Url>;
+
+    /// Parse a response body into bars and an optional paging cursor
+    fn parse_page(&self, body: &[u8]) -> Result<Page>;
+
+    /// Build the URL of the page following `current`, given the cursor from [`Page::next`]
+    fn next_request(
+        &self,
+        base_url: &str,
+        current: &Url,
+        cursor: &str,
+        api_key: &str,
+    ) -> Result<Url>;
+
+    /// Resolve the API key from an explicit value or the provider's environment variable
+    fn resolve_api_key(&self, explicit: Option<&str>) -> Result<String> {
+        explicit
+            .map(str::to_owned)
+            .or_else(|| env::var(self.api_key_env()).ok())
+            .ok_or_else(|| {
+                anyhow!(
+                    "API key not provided. Use --apikey or set {}.",
+                    self.api_key_env()
+                )
+            })
+    }
+
+    /// Resolve the base URL from an explicit value, the provider's environment
+    /// variable, or the public default
+    fn resolve_base_url(&self, explicit: Option<&str>) -> Result<String> {
+        let base = explicit
+            .map(str::to_owned)
+            .or_else(|| env::var(self.base_url_env()).ok())
+            .unwrap_or_else(|| self.default_base_url().to_owned());
+        Url::parse(&base).map_err(|e| anyhow!("Invalid base URL {}: {}", base, e))?;
+        Ok(base.trim_end_matches('/').to_owned())
+    }
+}
+
+/// Remove every `name` parameter from the query of `url`
+pub(crate) fn strip_query_param(url: &Url, name: &str) -> Url {
+    let mut u = url.clone();
+    let kept: Vec<(String, String)> = u
+        .query_pairs()
+        .filter(|(k, _)| k != name)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    u.set_query(None);
+    if !kept.is_empty() {
+        u.query_pairs_mut().extend_pairs(kept);
+    }
+    u
+}
+
+/// Point an absolute URL returned by the public API at `base_url` instead,
+/// so that paging keeps going through a proxy or mock server.
+pub(crate) fn rebase_url(url: &str, default_base: &str, base_url: &str) -> String {
+    match url.strip_prefix(default_base) {
+        Some(rest) if base_url != default_base => format!("{}{}", base_url, rest),
+        _ => url.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase_url_swaps_public_host() {
+        let u = rebase_url(
+            "https://api.polygon.io/v2/aggs/cursor?x=1",
+            "https://api.polygon.io",
+            "http://127.0.0.1:8080/proxy",
+        );
+        assert_eq!(u, "http://127.0.0.1:8080/proxy/v2/aggs/cursor?x=1");
+    }
+
+    #[test]
+    fn test_rebase_url_keeps_foreign_host() {
+        let u = rebase_url(
+            "http://mock.test/v2/aggs/cursor",
+            "https://api.polygon.io",
+            "http://mock.test",
+        );
+        assert_eq!(u, "http://mock.test/v2/aggs/cursor");
+    }
+
+    #[test]
+    fn test_strip_query_param() {
+        let u = Url::parse("http://x.test/p?a=1&apiKey=SECRET&b=2").unwrap();
+        assert_eq!(
+            strip_query_param(&u, "apiKey").as_str(),
+            "http://x.test/p?a=1&b=2"
+        );
+        let u = Url::parse("http://x.test/p?apiKey=SECRET").unwrap();
+        assert_eq!(strip_query_param(&u, "apiKey").as_str(), "http://x.test/p");
+    }
+
+    #[test]
+    fn test_resolve_base_url_explicit_trims_slash() {
+        let base = Polygon
+            .resolve_base_url(Some("http://localhost:9000/"))
+            .unwrap();
+        assert_eq!(base, "http://localhost:9000");
+    }
+
+    #[test]
+    fn test_resolve_base_url_rejects_garbage() {
+        assert!(TwelveData.resolve_base_url(Some("not a url")).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use std::num::NonZeroU32;
+
+use anyhow::{Context, Result, anyhow};
+use reqwest::Url;
+use serde::Deserialize;
+
+use super::{Page, Provider, Query, rebase_url};
+use crate::chunk::Chunk;
+use crate::ratelimit::RateLimit;
+use crate::{Adjustment, Agg, Granularity};
+
+/// Polygon.io aggregates (`/v2/aggs`) endpoint
+pub struct Polygon;
+
+#[derive(Debug, Deserialize)]
+struct AggsResponse {
+    results: Option<Vec<Agg>>,
+    next_url: Option<String>,
+}
+
+impl Provider for Polygon {
+    fn name(&self) -> &'static str {
+        "Polygon"
+    }
+
+    fn api_key_env(&self) -> &'static str {
+        "POLYGON_API_KEY"
+    }
+
+    fn api_key_param(&self) -> &'static str {
+        "apiKey"
+    }
+
+    fn base_url_env(&self) -> &'static str {
+        "POLYGON_BASE_URL"
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        "https://api.polygon.io"
+    }
+
+    fn default_rate_limit(&self) -> RateLimit {
+        // Free plan: 5 calls per minute
+        RateLimit::per_minute(const { NonZeroU32::new(5).unwrap() })
+    }
+
+    fn default_chunk(&self, granularity: Granularity) -> Chunk {
+        // The 50000 limit counts the base aggregates a request covers, so the
+        // multiplier does not widen the window
+        match granularity {
+            // Up to 57600 seconds in a day of extended hours, fewer with trades
+            Granularity::Second => Chunk::Day,
+            // A month of minute bars, extended hours included, stays under the
+            // 50000-result limit, so each window is normally a single page
+            Granularity::Minute => Chunk::Month,
+            Granularity::Hour => Chunk::Year,
+            _ => Chunk::None,
+        }
+    }
+
+    fn check_interval(&self, _granularity: Granularity, multiplier: u32) -> Result<()> {
+        // Every timespan is available with any multiplier
+        if multiplier == 0 {
+            return Err(anyhow!("Multiplier must be at least 1"));
+        }
+        Ok(())
+    }
+
+    fn check_adjustment(&self, adjustment: Adjustment) -> Result<()> {
+        if adjustment == Adjustment::All {
+            return Err(anyhow!(
+                "Polygon adjusts for splits only; download with --adjustment raw and apply dividends with the adjust command"
+            ));
+        }
+        Ok(())
+    }
+
+    fn first_request(&self, base_url: &str, query: &Query<'_>, api_key: &str) -> Result<Url> {
+        let mut url = Url::parse(&format!(
+            "{}/v2/aggs/ticker/{}/range/{}/{}/{}/{}",
+            base_url,
+            urlencoding::encode(query.ticker),
+            query.multiplier,
+            query.granularity.name(),
+            query.from,
+            query.to
+        ))?;
+        url.query_pairs_mut()
+            .append_pair(
+                "adjusted",
+                if query.adjustment == Adjustment::Raw {
+                    "false"
+                } else {
+                    "true"
+                },
+            )
+            .append_pair("sort", "asc")
+            .append_pair("limit", "50000")
+            .append_pair(self.api_key_param(), api_key);
+        Ok(url)
+    }
+
+    fn parse_page(&self, body: &[u8]) -> Result<Page> {
+        let aggs: AggsResponse =
+            serde_json::from_slice(body).with_context(|| "Invalid JSON from API")?;
+        Ok(Page {
+            results: aggs.results.unwrap_or_default(),
+            next: aggs.next_url,
+        })
+    }
+
+    fn next_request(
+        &self,
+        base_url: &str,
+        _current: &Url,
+        cursor: &str,
+        api_key: &str,
+    ) -> Result<Url> {
+        // next_url is a complete URL on the public host and omits the API key
+        let mut u = Url::parse(&rebase_url(cursor, self.default_base_url(), base_url))?;
+        ensure_api_key_present(&mut u, api_key);
+        Ok(u)
+    }
+}
+
+pub(crate) fn ensure_api_key_present(url: &mut Url, api_key: &str) {
+    let has_key = url.query_pairs().any(|(k, _)| k == "apiKey");
+    if !has_key {
+        url.query_pairs_mut().append_pair("apiKey", api_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_api_key_present_adds_when_missing() {
+        let mut u = Url::parse("https://example.com/path?foo=1").unwrap();
+        ensure_api_key_present(&mut u, "KEY123");
+        let query: Vec<_> = u.query_pairs().collect();
+        assert!(query.iter().any(|(k, v)| k == "apiKey" && v == "KEY123"));
+    }
+
+    #[test]
+    fn test_ensure_api_key_present_keeps_when_present() {
+        let mut u = Url::parse("https://example.com/path?apiKey=ABC&x=1").unwrap();
+        ensure_api_key_present(&mut u, "SHOULD_NOT_OVERRIDE");
+        // ensure existing value is not overridden
+        let pairs: Vec<_> = u.query_pairs().collect();
+        let found: Vec<_> = pairs.into_iter().filter(|(k, _)| k == "apiKey").collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1, "ABC");
+    }
+
+    #[test]
+    fn test_first_request_uses_base_url() {
+        let query = Query {
+            ticker: "I:NDX",
+            from: chrono::NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+            to: chrono::NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            adjustment: Adjustment::Splits,
+        };
+        let u = Polygon
+            .first_request("http://127.0.0.1:1234", &query, "K")
+            .unwrap();
+        assert_eq!(
+            u.as_str(),
+            "http://127.0.0.1:1234/v2/aggs/ticker/I%3ANDX/range/1/minute/2024-02-01/2024-02-01?adjusted=true&sort=asc&limit=50000&apiKey=K"
+        );
+    }
+
+    #[test]
+    fn test_first_request_uses_multiplier_and_timespan() {
+        let day = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let query = Query {
+            ticker: "AAPL",
+            from: day,
+            to: day,
+            granularity: Granularity::Quarter,
+            multiplier: 2,
+            adjustment: Adjustment::Raw,
+        };
+        let u = Polygon.first_request("http://x.test", &query, "K").unwrap();
+        assert_eq!(
+            u.path(),
+            "/v2/aggs/ticker/AAPL/range/2/quarter/2024-01-01/2024-01-01"
+        );
+        assert!(u.query().unwrap().starts_with("adjusted=false&"));
+        assert!(Polygon.check_adjustment(Adjustment::All).is_err());
+    }
+
+    #[test]
+    fn test_parse_page_reads_results_and_next_url() {
+        let body = br#"{"results":[{"t":1,"o":1.0,"h":2.0,"l":0.5,"c":1.5,"v":10}],"next_url":"https://api.polygon.io/next"}"#;
+        let page = Polygon.parse_page(body).unwrap();
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.next.as_deref(), Some("https://api.polygon.io/next"));
+    }
+}
@@ -0,0 +1,251 @@
+use std::num::NonZeroU32;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use reqwest::Url;
+use serde::Deserialize;
+
+use super::{Page, Provider, Query};
+use crate::chunk::Chunk;
+use crate::ratelimit::RateLimit;
+use crate::{Adjustment, Agg, Granularity};
+
+/// Twelve Data `time_series` endpoint
+pub struct TwelveData;
+
+// Twelve Data response shape: { status, values: [ { datetime, open, high, low, close, volume }, ... ], next_page_token? }
+#[derive(Deserialize)]
+struct TDResp {
+    status: Option<String>,
+    values: Option<Vec<TDVal>>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TDVal {
+    datetime: String,
+    open: String,
+    high: String,
+    low: String,
+    close: String,
+    #[serde(default)]
+    volume: Option<String>,
+}
+
+impl Provider for TwelveData {
+    fn name(&self) -> &'static str {
+        "Twelve Data"
+    }
+
+    fn api_key_env(&self) -> &'static str {
+        "TWELVEDATA_API_KEY"
+    }
+
+    fn api_key_param(&self) -> &'static str {
+        "apikey"
+    }
+
+    fn base_url_env(&self) -> &'static str {
+        "TWELVEDATA_BASE_URL"
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        "https://api.twelvedata.com"
+    }
+
+    fn default_rate_limit(&self) -> RateLimit {
+        // Basic plan: 8 API credits per minute
+        RateLimit::per_minute(const { NonZeroU32::new(8).unwrap() })
+    }
+
+    fn default_chunk(&self, granularity: Granularity) -> Chunk {
+        match granularity {
+            // outputsize caps a response at 5000 bars, about a week of
+            // regular-hours minute data
+            Granularity::Second | Granularity::Minute => Chunk::Week,
+            Granularity::Hour => Chunk::Year,
+            _ => Chunk::None,
+        }
+    }
+
+    fn check_interval(&self, granularity: Granularity, multiplier: u32) -> Result<()> {
+        interval(granularity, multiplier).map(|_| ())
+    }
+
+    fn first_request(&self, base_url: &str, query: &Query<'_>, api_key: &str) -> Result<Url> {
+        let interval = interval(query.granularity, query.multiplier)?;
+        let mut url = Url::parse(&format!("{}/time_series", base_url))?;
+        url.query_pairs_mut()
+            .append_pair("symbol", query.ticker)
+            .append_pair("interval", &interval)
+            .append_pair("start_date", &query.from.to_string())
+            .append_pair("end_date", &query.to.to_string())
+            .append_pair("order", "ASC")
+            .append_pair(
+                "adjust",
+                match query.adjustment {
+                    Adjustment::Raw => "none",
+                    Adjustment::Splits => "splits",
+                    Adjustment::All => "all",
+                },
+            )
+            // Bars are normalized to UTC here; --tz only changes how they are written
+            .append_pair("timezone", "UTC")
+            .append_pair("format", "JSON")
+            .append_pair("outputsize", "5000")
+            .append_pair(self.api_key_param(), api_key);
+        Ok(url)
+    }
+
+    fn parse_page(&self, body: &[u8]) -> Result<Page> {
+        let td: TDResp =
+            serde_json::from_slice(body).with_context(|| "Invalid JSON from Twelve Data API")?;
+        if let Some(s) = &td.status
+            && s.eq_ignore_ascii_case("error")
+        {
+            let msg = td
+                .message
+                .unwrap_or_else(|| String::from("Unknown Twelve Data error"));
+            return Err(anyhow!("Twelve Data API error: {}", msg));
+        }
+        let mut results = Vec::new();
+        for v in td.values.unwrap_or_default() {
+            // Parse datetime as UTC
+            let dt = NaiveDateTime::parse_from_str(&v.datetime, "%Y-%m-%d %H:%M:%S")
+                .or_else(|_| {
+                    chrono::NaiveDate::parse_from_str(&v.datetime, "%Y-%m-%d")
+                        .map(|d| d.and_hms_opt(0, 0, 0).unwrap())
+                })
+                .with_context(|| {
+                    format!("Invalid datetime in Twelve Data response: {}", v.datetime)
+                })?;
+            let ts = Utc.from_utc_datetime(&dt).timestamp_millis();
+            let parsef = |s: &str| -> Result<f64> {
+                s.parse::<f64>()
+                    .with_context(|| format!("Invalid number in Twelve Data response: {}", s))
+            };
+            let vol = match v.volume.as_deref() {
+                Some(s) if !s.is_empty() => Some(parsef(s)?),
+                _ => None,
+            };
+            results.push(Agg {
+                t: ts,
+                o: parsef(&v.open)?,
+                h: parsef(&v.high)?,
+                l: parsef(&v.low)?,
+                c: parsef(&v.close)?,
+                v: vol,
+                vw: None,
+                n: None,
+            });
+        }
+        Ok(Page {
+            results,
+            next: td.next_page_token,
+        })
+    }
+
+    fn next_request(
+        &self,
+        _base_url: &str,
+        current: &Url,
+        cursor: &str,
+        _api_key: &str,
+    ) -> Result<Url> {
+        // Build next page URL by replacing any existing page_token parameter
+        let mut u = current.clone();
+        let existing: Vec<(String, String)> = u
+            .query_pairs()
+            .filter(|(k, _)| k != "page_token" && k != "next_page_token")
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        u.set_query(None);
+        {
+            let mut qp = u.query_pairs_mut();
+            for (k, v) in existing {
+                qp.append_pair(&k, &v);
+            }
+            // Twelve Data uses page_token as request param
+            qp.append_pair("page_token", cursor);
+        }
+        Ok(u)
+    }
+}
+
+/// Twelve Data `interval` for bars of `multiplier` x `granularity`
+fn interval(granularity: Granularity, multiplier: u32) -> Result<String> {
+    let (unit, supported): (&str, &[u32]) = match granularity {
+        Granularity::Minute => ("min", &[1, 5, 15, 30, 45]),
+        Granularity::Hour => ("h", &[1, 2, 4, 8]),
+        Granularity::Day => ("day", &[1]),
+        Granularity::Week => ("week", &[1]),
+        Granularity::Month => ("month", &[1]),
+        Granularity::Second | Granularity::Quarter | Granularity::Year => {
+            return Err(anyhow!(
+                "Twelve Data has no {} bars; use --provider polygon",
+                granularity.name()
+            ));
+        }
+    };
+    if !supported.contains(&multiplier) {
+        let options: Vec<String> = supported.iter().map(|m| format!("{}{}", m, unit)).collect();
+        return Err(anyhow!(
+            "Twelve Data does not support {} x {} bars; supported intervals are {}",
+            multiplier,
+            granularity.name(),
+            options.join(", ")
+        ));
+    }
+    Ok(format!("{}{}", multiplier, unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_page_daily_values() {
+        let body = br#"{"status":"ok","values":[{"datetime":"2025-01-02","open":"1.5","high":"2","low":"1","close":"1.75","volume":"100"}]}"#;
+        let page = TwelveData.parse_page(body).unwrap();
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.results[0].t, 1735776000000);
+        assert_eq!(page.results[0].v, Some(100.0));
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn test_parse_page_error_status() {
+        let body = br#"{"status":"error","message":"bad symbol"}"#;
+        let err = TwelveData.parse_page(body).unwrap_err();
+        assert!(err.to_string().contains("bad symbol"));
+    }
+
+    #[test]
+    fn test_interval_maps_supported_and_rejects_others() {
+        assert_eq!(interval(Granularity::Minute, 1).unwrap(), "1min");
+        assert_eq!(interval(Granularity::Minute, 5).unwrap(), "5min");
+        assert_eq!(interval(Granularity::Hour, 4).unwrap(), "4h");
+        assert_eq!(interval(Granularity::Week, 1).unwrap(), "1week");
+        assert_eq!(interval(Granularity::Month, 1).unwrap(), "1month");
+        let err = interval(Granularity::Minute, 7).unwrap_err().to_string();
+        assert!(err.contains("1min, 5min, 15min, 30min, 45min"), "{}", err);
+        assert!(interval(Granularity::Second, 1).is_err());
+        assert!(interval(Granularity::Quarter, 1).is_err());
+    }
+
+    #[test]
+    fn test_next_request_replaces_page_token() {
+        let cur = Url::parse("https://x.test/time_series?symbol=A&page_token=old").unwrap();
+        let next = TwelveData
+            .next_request("https://x.test", &cur, "new", "KEY")
+            .unwrap();
+        let tokens: Vec<_> = next
+            .query_pairs()
+            .filter(|(k, _)| k == "page_token")
+            .map(|(_, v)| v.into_owned())
+            .collect();
+        assert_eq!(tokens, vec!["new".to_string()]);
+    }
+}
@@ -0,0 +1,319 @@
+//! Token-bucket rate limiting shared by every request the process makes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use chrono::{Timelike, Utc};
+use reqwest::header::HeaderMap;
+use tokio::time::Instant;
+
+/// Request budget of a provider plan, e.g. `5/min` or `unlimited`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimit {
+    Unlimited,
+    /// At most `requests` per `window`, bursting up to `requests` at once
+    PerWindow {
+        requests: NonZeroU32,
+        window: Duration,
+    },
+}
+
+impl RateLimit {
+    pub fn per_minute(requests: NonZeroU32) -> Self {
+        RateLimit::PerWindow {
+            requests,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    /// At most `requests` per `window`; both must be non-zero
+    pub fn per_window(requests: u32, window: Duration) -> Result<Self> {
+        let requests = NonZeroU32::new(requests)
+            .ok_or_else(|| anyhow!("A rate limit must allow at least one request"))?;
+        if window.is_zero() {
+            return Err(anyhow!("A rate limit window cannot be empty"));
+        }
+        Ok(RateLimit::PerWindow { requests, window })
+    }
+
+    /// Time for one token to refill
+    fn interval(&self) -> Option<Duration> {
+        match *self {
+            RateLimit::Unlimited => None,
+            RateLimit::PerWindow { requests, window } => Some(window / requests.get()),
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        match *self {
+            RateLimit::Unlimited => f64::INFINITY,
+            RateLimit::PerWindow { requests, .. } => requests.get() as f64,
+        }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    /// Parse `unlimited` or `N/WINDOW` where WINDOW is `s`, `min`, `h`, `day`
+    /// or a count of seconds such as `12s`.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("unlimited") || s.eq_ignore_ascii_case("none") {
+            return Ok(RateLimit::Unlimited);
+        }
+        let err = || {
+            anyhow!(
+                "Invalid rate limit '{}': expected N/WINDOW (e.g. 5/min) or unlimited",
+                s
+            )
+        };
+        let (n, window) = s.split_once('/').ok_or_else(err)?;
+        let requests: u32 = n.trim().parse().map_err(|_| err())?;
+        let window = window.trim().to_ascii_lowercase();
+        let secs = match window.as_str() {
+            "s" | "sec" | "second" => 1,
+            "m" | "min" | "minute" => 60,
+            "h" | "hour" => 3600,
+            "d" | "day" => 86_400,
+            other => other
+                .strip_suffix('s')
+                .and_then(|n| n.parse::<u64>().ok())
+                .ok_or_else(err)?,
+        };
+        RateLimit::per_window(requests, Duration::from_secs(secs)).map_err(|_| err())
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimit::Unlimited => write!(f, "unlimited"),
+            RateLimit::PerWindow { requests, window } => match window.as_secs() {
+                1 => write!(f, "{}/s", requests),
+                60 => write!(f, "{}/min", requests),
+                3600 => write!(f, "{}/h", requests),
+                86_400 => write!(f, "{}/day", requests),
+                secs => write!(f, "{}/{}s", requests, secs),
+            },
+        }
+    }
+}
+
+struct Bucket {
+    limit: RateLimit,
+    /// May go negative: every reservation takes a token and waits out the debt
+    tokens: f64,
+    refilled_at: Instant,
+    /// Set when the provider reported an exhausted quota
+    blocked_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.capacity(),
+            refilled_at: now,
+            blocked_until: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(interval) = self.limit.interval() {
+            let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+            self.tokens =
+                (self.tokens + elapsed / interval.as_secs_f64()).min(self.limit.capacity());
+        }
+        self.refilled_at = now;
+    }
+}
+
+/// Token buckets keyed by provider name.
+///
+/// Clone an `Arc<RateLimiter>` into every task so that concurrent downloads
+/// draw from the same budget.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take one token from `key`'s bucket and return how long the caller must
+    /// wait before sending its request.
+    pub fn reserve(&self, key: &str, limit: RateLimit) -> Duration {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::new(limit, now));
+        if bucket.limit != limit {
+            *bucket = Bucket::new(limit, now);
+        }
+        bucket.refill(now);
+
+        let blocked = bucket
+            .blocked_until
+            .map(|until| until.saturating_duration_since(now))
+            .unwrap_or_default();
+        let Some(interval) = limit.interval() else {
+            return blocked;
+        };
+        bucket.tokens -= 1.0;
+        let debt = if bucket.tokens < 0.0 {
+            interval.mul_f64(-bucket.tokens)
+        } else {
+            Duration::ZERO
+        };
+        debt.max(blocked)
+    }
+
+    /// Adjust `key`'s bucket from rate-limit response headers, if the provider sent any.
+    ///
+    /// Understands the common `X-RateLimit-Remaining`/`X-RateLimit-Reset` pair
+    /// and Twelve Data's `api-credits-left`, whose quota resets every minute.
+    pub fn observe(&self, key: &str, headers: &HeaderMap) {
+        let Some(quota) = parse_quota(headers) else {
+            return;
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let Some(bucket) = buckets.get_mut(key) else {
+            return;
+        };
+        bucket.refill(now);
+        if quota.remaining <= 0 {
+            bucket.blocked_until = Some(now + quota.reset);
+        } else if bucket.limit != RateLimit::Unlimited {
+            bucket.tokens = bucket.tokens.min(quota.remaining as f64);
+        }
+    }
+}
+
+struct Quota {
+    remaining: i64,
+    /// Time until the quota refills
+    reset: Duration,
+}
+
+fn header_i64(headers: &HeaderMap, name: &str) -> Option<i64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+fn parse_quota(headers: &HeaderMap) -> Option<Quota> {
+    if let Some(remaining) = header_i64(headers, "x-ratelimit-remaining")
+        .or_else(|| header_i64(headers, "ratelimit-remaining"))
+    {
+        let now = Utc::now().timestamp();
+        let reset = header_i64(headers, "x-ratelimit-reset")
+            .or_else(|| header_i64(headers, "ratelimit-reset"))
+            // Large values are epoch seconds, small ones are seconds from now
+            .map(|r| if r > 1_000_000_000 { r - now } else { r })
+            .unwrap_or(60)
+            .max(0);
+        return Some(Quota {
+            remaining,
+            reset: Duration::from_secs(reset as u64),
+        });
+    }
+    if let Some(remaining) = header_i64(headers, "api-credits-left") {
+        // Twelve Data credits reset at the start of every minute
+        let into_minute = Utc::now().second() as u64;
+        return Some(Quota {
+            remaining,
+            reset: Duration::from_secs(60 - into_minute),
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(
+            "unlimited".parse::<RateLimit>().unwrap(),
+            RateLimit::Unlimited
+        );
+        assert_eq!(
+            "5/min".parse::<RateLimit>().unwrap(),
+            RateLimit::per_window(5, Duration::from_secs(60)).unwrap()
+        );
+        assert_eq!(
+            "1/12s".parse::<RateLimit>().unwrap(),
+            RateLimit::per_window(1, Duration::from_secs(12)).unwrap()
+        );
+        assert!("0/min".parse::<RateLimit>().is_err());
+        assert!("5 per minute".parse::<RateLimit>().is_err());
+        assert_eq!("8/min".parse::<RateLimit>().unwrap().to_string(), "8/min");
+    }
+
+    #[test]
+    fn test_empty_budget_is_rejected() {
+        assert!(RateLimit::per_window(0, Duration::from_secs(60)).is_err());
+        assert!(RateLimit::per_window(5, Duration::ZERO).is_err());
+        let limit = RateLimit::per_window(5, Duration::from_secs(60)).unwrap();
+        assert_eq!(limit.interval(), Some(Duration::from_secs(12)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bucket_bursts_then_paces() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit::per_window(5, Duration::from_secs(60)).unwrap();
+        for _ in 0..5 {
+            assert_eq!(limiter.reserve("p", limit), Duration::ZERO);
+        }
+        // The sixth request waits for one refill, the seventh for two
+        assert_eq!(limiter.reserve("p", limit), Duration::from_secs(12));
+        assert_eq!(limiter.reserve("p", limit), Duration::from_secs(24));
+        // Buckets are independent per provider
+        assert_eq!(limiter.reserve("q", limit), Duration::ZERO);
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(limiter.reserve("p", limit), Duration::from_secs(0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unlimited_never_waits() {
+        let limiter = RateLimiter::new();
+        for _ in 0..100 {
+            assert_eq!(limiter.reserve("p", RateLimit::Unlimited), Duration::ZERO);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exhausted_quota_header_blocks_until_reset() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit::per_window(100, Duration::from_secs(60)).unwrap();
+        limiter.reserve("p", limit);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("30"));
+        limiter.observe("p", &headers);
+        assert_eq!(limiter.reserve("p", limit), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_remaining_header_caps_tokens() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit::per_window(60, Duration::from_secs(60)).unwrap();
+        limiter.reserve("p", limit);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("1"));
+        limiter.observe("p", &headers);
+        assert_eq!(limiter.reserve("p", limit), Duration::ZERO);
+        assert_eq!(limiter.reserve("p", limit), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,499 @@
+//! Read back outputs written by this tool.
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::compress::{Compression, open_reader};
+use crate::sink::CSV_HEADER;
+use crate::timestamp::TimestampFormat;
+use crate::{Agg, OutputFormat};
+
+/// Guess the format of an existing output from its extension, looking
+/// through a `.gz`/`.zst` compression suffix
+pub fn infer_format(path: &str) -> OutputFormat {
+    let path = Compression::strip_suffix(path).to_ascii_lowercase();
+    if path.ends_with(".json") {
+        OutputFormat::Json
+    } else if path.ends_with(".ndjson") || path.ends_with(".jsonl") {
+        OutputFormat::Ndjson
+    } else if path.ends_with(".parquet") {
+        OutputFormat::Parquet
+    } else if path.ends_with(".arrow") || path.ends_with(".feather") {
+        OutputFormat::Arrow
+    } else if [".db", ".sqlite", ".sqlite3"]
+        .iter()
+        .any(|ext| path.ends_with(ext))
+    {
+        OutputFormat::Sqlite
+    } else {
+        OutputFormat::Csv
+    }
+}
+
+/// Latest bar timestamp (ms) in the file at `path`, or `None` when the file
+/// is missing or holds no bars. Compressed files are decoded according to
+/// their extension; timestamps are parsed with `timestamps`, and a file
+/// whose bars all fail to parse is an error rather than an empty one.
+pub fn last_timestamp(
+    path: &str,
+    format: OutputFormat,
+    timestamps: &TimestampFormat,
+) -> Result<Option<i64>> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+    match format {
+        OutputFormat::Csv => last_timestamp_csv(path, timestamps),
+        OutputFormat::Json => last_timestamp_json(path, timestamps),
+        OutputFormat::Ndjson => last_timestamp_ndjson(path, timestamps),
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            Err(anyhow!("Reading back {:?} output is not supported", format))
+        }
+        // A database holds several series; see `sink::sqlite::last_timestamp`
+        OutputFormat::Sqlite => Err(anyhow!(
+            "{} holds several series; query it per ticker",
+            path
+        )),
+    }
+}
+
+/// `last`, or an error when `rows` bars were read but none of their
+/// timestamps parsed, e.g. because the file was written with another format
+fn parsed(path: &str, rows: usize, last: Option<i64>) -> Result<Option<i64>> {
+    if rows > 0 && last.is_none() {
+        return Err(anyhow!(
+            "None of the {} timestamp(s) in {} could be read; pass the --timestamp-format, --timestamp-pattern and --tz it was written with",
+            rows,
+            path
+        ));
+    }
+    Ok(last)
+}
+
+fn last_timestamp_csv(path: &str, timestamps: &TimestampFormat) -> Result<Option<i64>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(open_reader(path)?);
+    let ts_col = CSV_HEADER.iter().position(|c| *c == "timestamp").unwrap();
+    let mut last = None;
+    let mut rows = 0;
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Invalid CSV in {}", path))?;
+        let Some(field) = record.get(ts_col) else {
+            continue;
+        };
+        // The header row (if any) is skipped
+        if field == "timestamp" {
+            continue;
+        }
+        rows += 1;
+        last = last.max(timestamps.parse(field));
+    }
+    parsed(path, rows, last)
+}
+
+#[derive(Deserialize)]
+struct Row {
+    /// A string, or a number with the epoch timestamp formats
+    timestamp: serde_json::Value,
+}
+
+fn last_timestamp_json(path: &str, timestamps: &TimestampFormat) -> Result<Option<i64>> {
+    let rows: Vec<Row> = serde_json::from_reader(BufReader::new(open_reader(path)?))
+        .with_context(|| format!("Invalid JSON array in {}", path))?;
+    let last = rows
+        .iter()
+        .filter_map(|r| timestamps.parse_json(&r.timestamp))
+        .max();
+    parsed(path, rows.len(), last)
+}
+
+fn last_timestamp_ndjson(path: &str, timestamps: &TimestampFormat) -> Result<Option<i64>> {
+    let mut reader = BufReader::new(open_reader(path)?);
+    let mut line = Vec::new();
+    let mut last = None;
+    let mut number = 0;
+    let mut rows = 0;
+    loop {
+        line.clear();
+        if reader
+            .read_until(b'\n', &mut line)
+            .with_context(|| format!("Cannot read {}", path))?
+            == 0
+        {
+            break;
+        }
+        number += 1;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        match serde_json::from_slice::<Row>(&line) {
+            Ok(row) => {
+                rows += 1;
+                last = last.max(timestamps.parse_json(&row.timestamp));
+            }
+            // A line without its newline was cut off mid-write; appending drops it
+            Err(_) if !line.ends_with(b"\n") => break,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Invalid NDJSON in {}:{}", path, number));
+            }
+        }
+    }
+    parsed(path, rows, last)
+}
+
+/// Bar as written by the JSON and NDJSON sinks
+#[derive(Deserialize)]
+struct BarRow {
+    timestamp: serde_json::Value,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: Option<f64>,
+    #[serde(default)]
+    vw: Option<f64>,
+    #[serde(default)]
+    n: Option<i64>,
+}
+
+impl BarRow {
+    fn into_agg(self, timestamps: &TimestampFormat) -> Option<Agg> {
+        Some(Agg {
+            t: timestamps.parse_json(&self.timestamp)?,
+            o: self.open,
+            h: self.high,
+            l: self.low,
+            c: self.close,
+            v: self.volume,
+            vw: self.vw,
+            n: self.n,
+        })
+    }
+}
+
+/// Every bar of the CSV, JSON or NDJSON file at `path`, in file order. CSV
+/// has no `vw` or `n` columns, so those are `None`.
+pub fn read_bars(
+    path: &str,
+    format: OutputFormat,
+    timestamps: &TimestampFormat,
+) -> Result<Vec<Agg>> {
+    if !Path::new(path).exists() {
+        return Err(anyhow!("{} does not exist", path));
+    }
+    let invalid_ts = |n: usize| anyhow!("Invalid timestamp in {}:{}", path, n);
+    match format {
+        OutputFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(open_reader(path)?);
+            let col = |name: &str| CSV_HEADER.iter().position(|c| *c == name).unwrap();
+            let (ts, open, high, low, close, volume) = (
+                col("timestamp"),
+                col("open"),
+                col("high"),
+                col("low"),
+                col("close"),
+                col("volume"),
+            );
+            let mut bars = Vec::new();
+            for (i, record) in reader.records().enumerate() {
+                let record = record.with_context(|| format!("Invalid CSV in {}", path))?;
+                let number = |c: usize| -> Result<f64> {
+                    let field = record.get(c).unwrap_or("");
+                    field.parse().with_context(|| {
+                        format!("Invalid number '{}' in {}:{}", field, path, i + 1)
+                    })
+                };
+                let Some(t) = record.get(ts).and_then(|s| timestamps.parse(s)) else {
+                    // Only the header row may lack a timestamp
+                    if i == 0 {
+                        continue;
+                    }
+                    return Err(invalid_ts(i + 1));
+                };
+                bars.push(Agg {
+                    t,
+                    o: number(open)?,
+                    h: number(high)?,
+                    l: number(low)?,
+                    c: number(close)?,
+                    v: match record.get(volume) {
+                        None | Some("") => None,
+                        Some(_) => Some(number(volume)?),
+                    },
+                    vw: None,
+                    n: None,
+                });
+            }
+            Ok(bars)
+        }
+        OutputFormat::Json => {
+            let rows: Vec<BarRow> = serde_json::from_reader(BufReader::new(open_reader(path)?))
+                .with_context(|| format!("Invalid JSON array in {}", path))?;
+            rows.into_iter()
+                .enumerate()
+                .map(|(i, row)| row.into_agg(timestamps).ok_or_else(|| invalid_ts(i + 1)))
+                .collect()
+        }
+        OutputFormat::Ndjson => {
+            let reader = BufReader::new(open_reader(path)?);
+            let mut bars = Vec::new();
+            for (i, line) in reader.lines().enumerate() {
+                let line = line.with_context(|| format!("Cannot read {}", path))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let row: BarRow = serde_json::from_str(&line)
+                    .with_context(|| format!("Invalid NDJSON in {}:{}", path, i + 1))?;
+                bars.push(row.into_agg(timestamps).ok_or_else(|| invalid_ts(i + 1))?);
+            }
+            Ok(bars)
+        }
+        OutputFormat::Parquet | OutputFormat::Arrow | OutputFormat::Sqlite => {
+            Err(anyhow!("Reading back {:?} output is not supported", format))
+        }
+    }
+}
+
+/// Most recent per-day file in `format` and `compression` written by
+/// `--split-by-day` for `ticker` under `root`
+pub fn latest_day_file(
+    root: &str,
+    ticker: &str,
+    format: OutputFormat,
+    compression: Compression,
+) -> Result<Option<(NaiveDate, PathBuf)>> {
+    let prefix = format!("{}_", ticker);
+    let suffix = format!(".{}{}", format.extension(), compression.suffix());
+    let mut latest: Option<(NaiveDate, PathBuf)> = None;
+    let Ok(years) = fs::read_dir(root) else {
+        return Ok(None);
+    };
+    for year in years {
+        let year = year?.path();
+        if !year.is_dir() {
+            continue;
+        }
+        for month in fs::read_dir(&year)? {
+            let month = month?.path();
+            if !month.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(&month)? {
+                let path = file?.path();
+                let Some(date) = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| n.strip_prefix(&prefix))
+                    .and_then(|n| n.strip_suffix(&suffix))
+                    .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                else {
+                    continue;
+                };
+                if latest.as_ref().is_none_or(|(d, _)| date > *d) {
+                    latest = Some((date, path));
+                }
+            }
+        }
+    }
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mdd-reader-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_last_timestamp_csv_with_and_without_header() {
+        let dir = scratch("csv");
+        let with = dir.join("with.csv");
+        fs::write(
+            &with,
+            "ticker,timestamp,open,high,low,close,volume\nA,2024-02-01 14:30:00,1,1,1,1,\nA,2024-02-01 14:31:00,1,1,1,1,\n",
+        )
+        .unwrap();
+        let without = dir.join("without.csv");
+        fs::write(&without, "A,2024-02-01 14:30:00,1,1,1,1,\n").unwrap();
+
+        assert_eq!(
+            last_timestamp(
+                with.to_str().unwrap(),
+                OutputFormat::Csv,
+                &TimestampFormat::default()
+            )
+            .unwrap(),
+            Some(1706797860000)
+        );
+        assert_eq!(
+            last_timestamp(
+                without.to_str().unwrap(),
+                OutputFormat::Csv,
+                &TimestampFormat::default()
+            )
+            .unwrap(),
+            Some(1706797800000)
+        );
+        assert_eq!(
+            last_timestamp(
+                dir.join("missing.csv").to_str().unwrap(),
+                OutputFormat::Csv,
+                &TimestampFormat::default()
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_last_timestamp_in_another_format() {
+        let dir = scratch("formats");
+        let ts = TimestampFormat::default();
+        // Written with --timestamp-format epoch-s
+        let epoch = dir.join("epoch.csv");
+        fs::write(
+            &epoch,
+            "ticker,timestamp,open,high,low,close,volume\nA,1706797800,1,1,1,1,\n",
+        )
+        .unwrap();
+        assert_eq!(
+            last_timestamp(epoch.to_str().unwrap(), OutputFormat::Csv, &ts).unwrap(),
+            Some(1706797800000)
+        );
+        // Written with a custom pattern, which cannot be guessed
+        let custom = dir.join("custom.ndjson");
+        fs::write(&custom, "{\"timestamp\":\"01/02/2024 09:30\",\"open\":1}\n").unwrap();
+        let err = last_timestamp(custom.to_str().unwrap(), OutputFormat::Ndjson, &ts).unwrap_err();
+        assert!(
+            err.to_string().starts_with("None of the 1 timestamp(s)"),
+            "{}",
+            err
+        );
+        // A header alone holds no bars
+        let header = dir.join("header.csv");
+        fs::write(&header, "ticker,timestamp,open,high,low,close,volume\n").unwrap();
+        assert_eq!(
+            last_timestamp(header.to_str().unwrap(), OutputFormat::Csv, &ts).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_last_timestamp_json() {
+        let dir = scratch("json");
+        let path = dir.join("a.json");
+        fs::write(
+            &path,
+            r#"[{"timestamp":"2024-02-01 14:30:00","open":1},{"timestamp":"2024-02-01 14:31:00","open":1}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            last_timestamp(
+                path.to_str().unwrap(),
+                OutputFormat::Json,
+                &TimestampFormat::default()
+            )
+            .unwrap(),
+            Some(1706797860000)
+        );
+    }
+
+    #[test]
+    fn test_latest_day_file() {
+        let dir = scratch("days");
+        for rel in [
+            "2024/01/AAPL_2024-01-31.csv",
+            "2024/02/AAPL_2024-02-01.csv",
+            "2024/02/MSFT_2024-02-02.csv",
+        ] {
+            let p = dir.join(rel);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            fs::write(p, "").unwrap();
+        }
+        let (date, path) = latest_day_file(
+            dir.to_str().unwrap(),
+            "AAPL",
+            OutputFormat::Csv,
+            Compression::None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 2, 1).unwrap());
+        assert!(path.ends_with("2024/02/AAPL_2024-02-01.csv"));
+    }
+
+    #[test]
+    fn test_last_timestamp_ndjson_ignores_cut_off_line() {
+        let dir = scratch("ndjson");
+        let path = dir.join("a.ndjson");
+        fs::write(
+            &path,
+            "{\"timestamp\":\"2024-02-01 14:30:00\"}\n\n{\"timestamp\":\"2024-02-01 14:31:00\"}\n{\"timest",
+        )
+        .unwrap();
+        assert_eq!(
+            last_timestamp(
+                path.to_str().unwrap(),
+                OutputFormat::Ndjson,
+                &TimestampFormat::default()
+            )
+            .unwrap(),
+            Some(1706797860000)
+        );
+        assert_eq!(infer_format("out/a.jsonl"), OutputFormat::Ndjson);
+    }
+
+    #[test]
+    fn test_read_bars() {
+        let dir = scratch("bars");
+        let ts = TimestampFormat::default();
+        let csv = dir.join("a.csv");
+        fs::write(
+            &csv,
+            "ticker,timestamp,open,high,low,close,volume\n\
+             AAPL,2024-02-01 14:30:00,1,2,0.5,1.5,100\n\
+             I:SPX,2024-02-01 14:31:00,1.5,2,1,2,\n",
+        )
+        .unwrap();
+        let bars = read_bars(csv.to_str().unwrap(), OutputFormat::Csv, &ts).unwrap();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(
+            (bars[0].t, bars[0].l, bars[0].v),
+            (1706797800000, 0.5, Some(100.0))
+        );
+        assert_eq!((bars[1].c, bars[1].v), (2.0, None));
+
+        let ndjson = dir.join("a.ndjson");
+        fs::write(
+            &ndjson,
+            "{\"timestamp\":\"2024-02-01 14:30:00\",\"open\":1,\"high\":2,\"low\":0.5,\"close\":1.5,\"volume\":100,\"vw\":1.2,\"n\":7}\n",
+        )
+        .unwrap();
+        let bars = read_bars(ndjson.to_str().unwrap(), OutputFormat::Ndjson, &ts).unwrap();
+        assert_eq!((bars[0].vw, bars[0].n), (Some(1.2), Some(7)));
+
+        fs::write(
+            &csv,
+            "AAPL,not a time,1,2,0.5,1.5,100\nAAPL,also not,1,1,1,1,1\n",
+        )
+        .unwrap();
+        let err = read_bars(csv.to_str().unwrap(), OutputFormat::Csv, &ts).unwrap_err();
+        assert!(err.to_string().contains("a.csv:2"), "{}", err);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
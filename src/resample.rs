@@ -0,0 +1,436 @@
+//! Local aggregation of bars into coarser intervals.
+//!
+//! A [`Resampler`] folds an ascending stream of bars into buckets of a fixed
+//! length, so that e.g. 1-minute bars can be written as 15-minute or daily
+//! bars when the provider or plan does not offer them directly.
+
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use chrono::{NaiveTime, Offset as _, TimeZone, Timelike};
+use chrono_tz::Tz;
+
+use crate::sink::Sink;
+use crate::{Agg, Granularity};
+
+const SECOND: i64 = 1_000;
+const DAY: i64 = 86_400_000;
+const WEEK: i64 = 7 * DAY;
+/// 1970-01-05, the first Monday after the epoch, where weekly buckets start
+const FIRST_MONDAY: i64 = 4 * DAY;
+
+/// Length of a resampled bar, e.g. `30s`, `15m`, `1h`, `1d` or `1w`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval(i64);
+
+impl Interval {
+    pub fn millis(self) -> i64 {
+        self.0
+    }
+
+    /// The same length as a multiple of the largest whole timespan, e.g. 15
+    /// minutes for `15m` or 2 days for `48h`; stored with resampled bars
+    pub fn granularity(self) -> (Granularity, u32) {
+        let (granularity, unit) = [
+            (Granularity::Week, WEEK),
+            (Granularity::Day, DAY),
+            (Granularity::Hour, 3_600 * SECOND),
+            (Granularity::Minute, 60 * SECOND),
+        ]
+        .into_iter()
+        .find(|(_, unit)| self.0 % unit == 0)
+        .unwrap_or((Granularity::Second, SECOND));
+        (
+            granularity,
+            u32::try_from(self.0 / unit).unwrap_or(u32::MAX),
+        )
+    }
+}
+
+/// Parse `N` followed by `s`, `m`/`min`, `h`, `d` or `w` into milliseconds
+fn parse_millis(s: &str) -> Result<i64> {
+    let s = s.trim().to_ascii_lowercase();
+    let err = || {
+        anyhow!(
+            "Invalid interval '{}': expected N followed by s, m, h, d or w (e.g. 15m)",
+            s
+        )
+    };
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(err)?;
+    let n: i64 = s[..split].parse().map_err(|_| err())?;
+    let unit = match &s[split..] {
+        "s" | "sec" => SECOND,
+        "m" | "min" => 60 * SECOND,
+        "h" => 3_600 * SECOND,
+        "d" => DAY,
+        "w" => WEEK,
+        _ => return Err(err()),
+    };
+    n.checked_mul(unit).ok_or_else(err)
+}
+
+impl FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match parse_millis(s)? {
+            0 => Err(anyhow!("Interval '{}' must not be zero", s.trim())),
+            ms => Ok(Interval(ms)),
+        }
+    }
+}
+
+/// Offset of the bucket grid, e.g. `30m` for hourly bars starting at :30
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Offset(i64);
+
+impl Offset {
+    pub fn millis(self) -> i64 {
+        self.0
+    }
+}
+
+impl FromStr for Offset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Offset(parse_millis(s)?))
+    }
+}
+
+/// Aggregates bars into buckets of `interval`.
+///
+/// Buckets are aligned to the epoch (weekly ones to Monday) shifted by the
+/// offset, in local time of the time zone (UTC by default), so daily buckets
+/// start at local midnight. With session boundaries, intraday buckets instead
+/// restart at each boundary time of day, so no bucket spans e.g. the open or
+/// the close; the bucket before a boundary may then be shorter than `interval`.
+///
+/// Each output bar is stamped with the start of its bucket and has the first
+/// open, highest high, lowest low, last close, summed volume and trade count,
+/// and the volume-weighted average of `vw`.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    interval: i64,
+    offset: i64,
+    tz: Tz,
+    /// Session boundaries in ms after midnight, ascending
+    boundaries: Vec<i64>,
+    current: Option<Bucket>,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    bar: Agg,
+    /// Sum of `vw * v` and of `v` over bars that have both
+    vw_value: f64,
+    vw_volume: f64,
+}
+
+impl Bucket {
+    fn new(start: i64, bar: &Agg) -> Self {
+        let mut bucket = Bucket {
+            bar: Agg {
+                t: start,
+                v: None,
+                vw: None,
+                n: None,
+                ..bar.clone()
+            },
+            vw_value: 0.0,
+            vw_volume: 0.0,
+        };
+        bucket.add_volume(bar);
+        bucket
+    }
+
+    fn add(&mut self, bar: &Agg) {
+        self.bar.h = self.bar.h.max(bar.h);
+        self.bar.l = self.bar.l.min(bar.l);
+        self.bar.c = bar.c;
+        self.add_volume(bar);
+    }
+
+    fn add_volume(&mut self, bar: &Agg) {
+        if let Some(v) = bar.v {
+            self.bar.v = Some(self.bar.v.unwrap_or(0.0) + v);
+        }
+        if let Some(n) = bar.n {
+            self.bar.n = Some(self.bar.n.unwrap_or(0) + n);
+        }
+        if let (Some(vw), Some(v)) = (bar.vw, bar.v) {
+            self.vw_value += vw * v;
+            self.vw_volume += v;
+        }
+    }
+
+    fn close(mut self) -> Agg {
+        if self.vw_volume > 0.0 {
+            self.bar.vw = Some(self.vw_value / self.vw_volume);
+        }
+        self.bar
+    }
+}
+
+impl Resampler {
+    pub fn new(interval: Interval) -> Self {
+        Self {
+            interval: interval.millis(),
+            offset: 0,
+            tz: Tz::UTC,
+            boundaries: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Shift the bucket grid by `offset`; set it before [`Resampler::boundaries`],
+    /// which reject it
+    pub fn offset(mut self, offset: Offset) -> Self {
+        self.offset = offset.millis();
+        self
+    }
+
+    /// Align buckets and session boundaries to local time in `tz`
+    pub fn timezone(mut self, tz: Tz) -> Self {
+        self.tz = tz;
+        self
+    }
+
+    /// Close intraday buckets at these local times of day and restart the
+    /// grid there; the boundaries place the grid, so there must be no offset
+    pub fn boundaries(mut self, boundaries: &[NaiveTime]) -> Result<Self> {
+        if !boundaries.is_empty() && self.interval >= DAY {
+            return Err(anyhow!(
+                "Session boundaries only apply to intervals shorter than a day"
+            ));
+        }
+        if !boundaries.is_empty() && self.offset != 0 {
+            return Err(anyhow!(
+                "Session boundaries cannot be combined with a grid offset"
+            ));
+        }
+        self.boundaries = boundaries
+            .iter()
+            .map(|b| b.num_seconds_from_midnight() as i64 * SECOND)
+            .collect();
+        self.boundaries.sort_unstable();
+        self.boundaries.dedup();
+        Ok(self)
+    }
+
+    /// Start of the bucket holding timestamp `t`
+    pub fn bucket_start(&self, t: i64) -> i64 {
+        let shift = self
+            .tz
+            .timestamp_millis_opt(t)
+            .single()
+            .map(|dt| dt.offset().fix().local_minus_utc() as i64 * SECOND)
+            .unwrap_or(0);
+        self.local_bucket_start(t + shift) - shift
+    }
+
+    /// Bucket start for a timestamp shifted to local time
+    fn local_bucket_start(&self, t: i64) -> i64 {
+        if let Some(&last) = self.boundaries.last() {
+            let midnight = t.div_euclid(DAY) * DAY;
+            let since = t - midnight;
+            let session = match self.boundaries.iter().rev().find(|&&b| b <= since) {
+                Some(b) => midnight + b,
+                None => midnight - DAY + last,
+            };
+            return session + (t - session).div_euclid(self.interval) * self.interval;
+        }
+        let origin = if self.interval % WEEK == 0 {
+            FIRST_MONDAY
+        } else {
+            0
+        } + self.offset;
+        origin + (t - origin).div_euclid(self.interval) * self.interval
+    }
+
+    /// Add `bars` in ascending order, returning the buckets they completed
+    pub fn push(&mut self, bars: &[Agg]) -> Vec<Agg> {
+        let mut done = Vec::new();
+        for bar in bars {
+            let start = self.bucket_start(bar.t);
+            match &mut self.current {
+                Some(bucket) if bucket.bar.t == start => bucket.add(bar),
+                current => {
+                    if let Some(bucket) = current.replace(Bucket::new(start, bar)) {
+                        done.push(bucket.close());
+                    }
+                }
+            }
+        }
+        done
+    }
+
+    /// The bucket still being filled, if any
+    pub fn finish(&mut self) -> Option<Agg> {
+        self.current.take().map(Bucket::close)
+    }
+}
+
+/// Sink adapter that resamples bars before handing them to `inner`.
+///
+/// A bucket is only written once a later bar (or [`Sink::finish`]) shows it
+/// is complete, so pages of the inner sink hold whole buckets.
+pub struct ResampleSink {
+    inner: Box<dyn Sink>,
+    resampler: Resampler,
+}
+
+impl ResampleSink {
+    pub fn new(inner: Box<dyn Sink>, resampler: Resampler) -> Self {
+        Self { inner, resampler }
+    }
+}
+
+impl Sink for ResampleSink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        let done = self.resampler.push(bars);
+        if done.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_batch(&done)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if let Some(last) = self.resampler.finish() {
+            self.inner.write_batch(&[last])?;
+        }
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-01 00:00:00 UTC
+    const D0: i64 = 1706745600000;
+    const MIN: i64 = 60_000;
+
+    fn bar(t: i64, o: f64, h: f64, l: f64, c: f64, v: f64, vw: f64) -> Agg {
+        Agg {
+            t,
+            o,
+            h,
+            l,
+            c,
+            v: Some(v),
+            vw: Some(vw),
+            n: Some(1),
+        }
+    }
+
+    fn hm(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_interval_and_offset() {
+        assert_eq!("15m".parse::<Interval>().unwrap().millis(), 15 * MIN);
+        assert_eq!("1h".parse::<Interval>().unwrap().millis(), 60 * MIN);
+        assert_eq!("1d".parse::<Interval>().unwrap().millis(), DAY);
+        assert_eq!("30s".parse::<Interval>().unwrap().millis(), 30_000);
+        assert!("0m".parse::<Interval>().is_err());
+        assert!("15".parse::<Interval>().is_err());
+        assert!("m".parse::<Interval>().is_err());
+        assert_eq!("0m".parse::<Offset>().unwrap().millis(), 0);
+        let granularity = |s: &str| s.parse::<Interval>().unwrap().granularity();
+        assert_eq!(granularity("15m"), (Granularity::Minute, 15));
+        assert_eq!(granularity("90s"), (Granularity::Second, 90));
+        assert_eq!(granularity("48h"), (Granularity::Day, 2));
+        assert_eq!(granularity("14d"), (Granularity::Week, 2));
+    }
+
+    #[test]
+    fn test_aggregates_ohlcv_per_bucket() {
+        let mut r = Resampler::new("15m".parse().unwrap());
+        let done = r.push(&[
+            bar(D0 + 14 * MIN, 9.0, 9.5, 8.5, 9.0, 50.0, 9.0),
+            bar(D0 + 15 * MIN, 10.0, 12.0, 9.0, 11.0, 100.0, 10.0),
+            bar(D0 + 16 * MIN, 11.0, 11.5, 8.0, 10.5, 300.0, 11.0),
+        ]);
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[0].t, D0);
+        let last = r.push(&[bar(D0 + 29 * MIN, 10.5, 13.0, 10.0, 12.5, 100.0, 12.0)]);
+        assert!(last.is_empty());
+
+        let b = r.finish().unwrap();
+        assert_eq!(b.t, D0 + 15 * MIN);
+        assert_eq!((b.o, b.h, b.l, b.c), (10.0, 13.0, 8.0, 12.5));
+        assert_eq!(b.v, Some(500.0));
+        assert_eq!(b.n, Some(3));
+        // (10*100 + 11*300 + 12*100) / 500
+        assert_eq!(b.vw, Some(11.0));
+        assert!(r.finish().is_none());
+    }
+
+    #[test]
+    fn test_offset_and_weekly_alignment() {
+        let r = Resampler::new("1h".parse().unwrap()).offset("30m".parse().unwrap());
+        assert_eq!(
+            r.bucket_start(D0 + 9 * 60 * MIN),
+            D0 + 8 * 60 * MIN + 30 * MIN
+        );
+        // 2024-02-01 is a Thursday; its week starts on Monday 2024-01-29
+        let r = Resampler::new("1w".parse().unwrap());
+        assert_eq!(r.bucket_start(D0 + 5 * MIN), D0 - 3 * DAY);
+    }
+
+    #[test]
+    fn test_session_boundaries_restart_the_grid() {
+        let r = Resampler::new("1h".parse().unwrap())
+            .boundaries(&[hm(14, 30), hm(21, 0)])
+            .unwrap();
+        // Regular session buckets start at 14:30
+        assert_eq!(
+            r.bucket_start(D0 + 15 * 60 * MIN),
+            D0 + 14 * 60 * MIN + 30 * MIN
+        );
+        // The last one is cut short at the 21:00 close
+        assert_eq!(
+            r.bucket_start(D0 + 20 * 60 * MIN + 45 * MIN),
+            D0 + 20 * 60 * MIN + 30 * MIN
+        );
+        assert_eq!(
+            r.bucket_start(D0 + 21 * 60 * MIN + 5 * MIN),
+            D0 + 21 * 60 * MIN
+        );
+        // Before the first boundary of the day, the grid of the previous day's
+        // last session continues from 21:00
+        assert_eq!(
+            r.bucket_start(D0 + 10 * 60 * MIN + 20 * MIN),
+            D0 + 10 * 60 * MIN
+        );
+        assert!(
+            Resampler::new("1d".parse().unwrap())
+                .boundaries(&[hm(14, 30)])
+                .is_err()
+        );
+        assert!(
+            Resampler::new("1h".parse().unwrap())
+                .offset("30m".parse().unwrap())
+                .boundaries(&[hm(14, 30)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_timezone_aligns_to_local_midnight_and_sessions() {
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        // 2024-02-02 02:00 UTC is 21:00 on Feb 1 in New York (UTC-5)
+        let r = Resampler::new("1d".parse().unwrap()).timezone(new_york);
+        assert_eq!(r.bucket_start(D0 + 26 * 60 * MIN), D0 + 5 * 60 * MIN);
+        let r = Resampler::new("1h".parse().unwrap())
+            .timezone(new_york)
+            .boundaries(&[hm(9, 30)])
+            .unwrap();
+        assert_eq!(
+            r.bucket_start(D0 + 15 * 60 * MIN),
+            D0 + 14 * 60 * MIN + 30 * MIN
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! US equity trading sessions (NYSE/Nasdaq), used to filter intraday bars.
+//!
+//! Session hours are defined in exchange time, America/New_York, so they
+//! follow daylight saving time. On early-close days the regular session ends
+//! at 13:00 and the after-hours session at 17:00, following the NYSE
+//! [`Calendar`].
+
+use chrono::{NaiveDate, TimeZone, Timelike};
+use chrono_tz::America::New_York;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::Calendar;
+
+/// Which part of the trading day to keep
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Session {
+    /// Every bar, as returned by the provider
+    #[default]
+    All,
+    /// 09:30 to 16:00 (13:00 on early-close days)
+    #[value(alias = "rth")]
+    Regular,
+    /// Pre-market, regular and after-hours, 04:00 to 20:00
+    #[value(alias = "eth")]
+    Extended,
+    /// 04:00 to 09:30
+    Premarket,
+    /// 16:00 to 20:00 (13:00 to 17:00 on early-close days)
+    Afterhours,
+}
+
+/// Exchange time zone the session hours are given in
+pub const EXCHANGE_TZ: Tz = New_York;
+
+const PREMARKET_OPEN: i32 = 4 * 60;
+const REGULAR_OPEN: i32 = 9 * 60 + 30;
+const REGULAR_CLOSE: i32 = 16 * 60;
+const AFTERHOURS_CLOSE: i32 = 20 * 60;
+/// After-hours trading runs four hours past an early close
+const EARLY_AFTERHOURS_CLOSE: i32 = 17 * 60;
+
+impl Session {
+    /// Whether a bar starting at `t` (ms since the epoch) falls in this session
+    pub fn contains(self, t: i64) -> bool {
+        if self == Session::All {
+            return true;
+        }
+        let Some(local) = EXCHANGE_TZ.timestamp_millis_opt(t).single() else {
+            return false;
+        };
+        let minute = (local.time().num_seconds_from_midnight() / 60) as i32;
+        let (start, end) = self.bounds(local.date_naive());
+        (start..end).contains(&minute)
+    }
+
+    /// Start and end of the session on `date`, in minutes from midnight
+    /// exchange time
+    pub(crate) fn bounds(self, date: NaiveDate) -> (i32, i32) {
+        let (close, afterhours_close) = match Calendar::Nyse.early_close(date) {
+            Some(early) => (early, EARLY_AFTERHOURS_CLOSE),
+            None => (REGULAR_CLOSE, AFTERHOURS_CLOSE),
+        };
+        match self {
+            Session::All => (0, 24 * 60),
+            Session::Regular => (REGULAR_OPEN, close),
+            Session::Extended => (PREMARKET_OPEN, afterhours_close),
+            Session::Premarket => (PREMARKET_OPEN, REGULAR_OPEN),
+            Session::Afterhours => (close, afterhours_close),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Milliseconds of `h:m` New York time on `y-mo-d`
+    fn ny(y: i32, mo: u32, d: u32, h: u32, m: u32) -> i64 {
+        EXCHANGE_TZ
+            .with_ymd_and_hms(y, mo, d, h, m, 0)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn test_sessions_split_the_day() {
+        let cases = [
+            (ny(2024, 2, 1, 3, 59), [true, false, false, false, false]),
+            (ny(2024, 2, 1, 4, 0), [true, false, true, true, false]),
+            (ny(2024, 2, 1, 9, 30), [true, true, true, false, false]),
+            (ny(2024, 2, 1, 15, 59), [true, true, true, false, false]),
+            (ny(2024, 2, 1, 16, 0), [true, false, true, false, true]),
+            (ny(2024, 2, 1, 20, 0), [true, false, false, false, false]),
+        ];
+        let sessions = [
+            Session::All,
+            Session::Regular,
+            Session::Extended,
+            Session::Premarket,
+            Session::Afterhours,
+        ];
+        for (t, expected) in cases {
+            for (session, want) in sessions.iter().zip(expected) {
+                assert_eq!(session.contains(t), want, "{:?} at {}", session, t);
+            }
+        }
+    }
+
+    #[test]
+    fn test_regular_open_follows_daylight_saving_time() {
+        // 14:30 UTC before the March 10, 2024 switch, 13:30 UTC after it
+        let before = 1709908200000; // 2024-03-08 14:30 UTC
+        let after = 1710163800000; // 2024-03-11 13:30 UTC
+        assert!(Session::Regular.contains(before));
+        assert!(Session::Regular.contains(after));
+        assert!(!Session::Regular.contains(after - 60_000));
+    }
+
+    #[test]
+    fn test_early_closes() {
+        // Day after Thanksgiving, July 3 and Christmas Eve 2024
+        for (mo, d) in [(11, 29), (7, 3), (12, 24)] {
+            assert!(Session::Regular.contains(ny(2024, mo, d, 12, 59)));
+            assert!(!Session::Regular.contains(ny(2024, mo, d, 13, 0)));
+            assert!(Session::Afterhours.contains(ny(2024, mo, d, 16, 59)));
+            assert!(!Session::Afterhours.contains(ny(2024, mo, d, 17, 0)));
+        }
+        // July 3, 2026 is a Friday holiday, not an early close
+        assert!(Session::Regular.contains(ny(2026, 7, 3, 14, 0)));
+    }
+}
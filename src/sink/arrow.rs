@@ -0,0 +1,173 @@
+//! Arrow IPC (Feather v2) output, plus the record batch layout shared with
+//! the Parquet sink.
+
+use std::fs::File;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow_array::types::Int32Type;
+use arrow_array::{
+    ArrayRef, DictionaryArray, Float64Array, Int32Array, Int64Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+
+use super::{Sink, SinkOptions, create_file};
+use crate::Agg;
+
+/// Arrow schema of the bars: ticker, UTC timestamp, OHLCV, `vw` and `n`
+pub fn bar_schema() -> SchemaRef {
+    let dict = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    Arc::new(Schema::new(vec![
+        Field::new("ticker", dict, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("open", DataType::Float64, false),
+        Field::new("high", DataType::Float64, false),
+        Field::new("low", DataType::Float64, false),
+        Field::new("close", DataType::Float64, false),
+        Field::new("volume", DataType::Float64, true),
+        Field::new("vw", DataType::Float64, true),
+        Field::new("n", DataType::Int64, true),
+    ]))
+}
+
+/// Convert one page of bars into a record batch of [`bar_schema`]
+pub fn bar_batch(schema: &SchemaRef, ticker: &str, bars: &[Agg], prec: i32) -> Result<RecordBatch> {
+    let pow = 10f64.powi(prec);
+    let round_to = |x: f64| (x * pow).round() / pow;
+    let prices = |f: fn(&Agg) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from_iter_values(
+            bars.iter().map(|b| round_to(f(b))),
+        ))
+    };
+    let tickers = DictionaryArray::<Int32Type>::new(
+        Int32Array::from(vec![0; bars.len()]),
+        Arc::new(StringArray::from(vec![ticker])),
+    );
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(tickers),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(bars.iter().map(|b| b.t))
+                .with_timezone("UTC"),
+        ),
+        prices(|b| b.o),
+        prices(|b| b.h),
+        prices(|b| b.l),
+        prices(|b| b.c),
+        Arc::new(Float64Array::from_iter(
+            bars.iter().map(|b| b.v.map(round_to)),
+        )),
+        Arc::new(Float64Array::from_iter(
+            bars.iter().map(|b| b.vw.map(round_to)),
+        )),
+        Arc::new(Int64Array::from_iter(bars.iter().map(|b| b.n))),
+    ];
+    RecordBatch::try_new(Arc::clone(schema), columns).context("Cannot build record batch")
+}
+
+/// Single Arrow IPC file (Feather v2) with one record batch per page.
+///
+/// The file can be memory-mapped by pyarrow, pandas (`read_feather`) and
+/// polars (`read_ipc`) without parsing. Like Parquet, it is only valid once
+/// the footer has been written by [`Sink::finish`].
+pub struct ArrowSink {
+    writer: FileWriter<File>,
+    schema: SchemaRef,
+    ticker: String,
+    prec: i32,
+}
+
+impl ArrowSink {
+    pub fn create(path: &str, opts: &SinkOptions) -> Result<Self> {
+        let schema = bar_schema();
+        let writer = FileWriter::try_new(create_file(path)?, &schema)
+            .with_context(|| format!("Cannot write {}", path))?;
+        Ok(Self {
+            writer,
+            schema,
+            ticker: opts.ticker.clone(),
+            prec: opts.max_decimals as i32,
+        })
+    }
+}
+
+impl Sink for ArrowSink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        if bars.is_empty() {
+            return Ok(());
+        }
+        let batch = bar_batch(&self.schema, &self.ticker, bars, self.prec)?;
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Granularity, ProviderKind};
+    use arrow_array::Array;
+    use arrow_ipc::reader::FileReader;
+
+    #[test]
+    fn test_arrow_file_has_a_batch_per_page() {
+        let dir = std::env::temp_dir().join(format!("mdd-arrow-{}", std::process::id()));
+        let path = dir.join("aapl.arrow");
+        let opts = SinkOptions {
+            ticker: "AAPL".into(),
+            no_header: false,
+            max_decimals: 2,
+            provider: ProviderKind::Polygon,
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            compression: Default::default(),
+            timestamps: Default::default(),
+        };
+        let bar = |t| Agg {
+            t,
+            o: 1.0,
+            h: 2.0,
+            l: 0.5,
+            c: 1.5,
+            v: None,
+            vw: Some(1.25),
+            n: None,
+        };
+        let mut sink: Box<dyn Sink> =
+            Box::new(ArrowSink::create(path.to_str().unwrap(), &opts).unwrap());
+        sink.write_batch(&[bar(1_000), bar(2_000)]).unwrap();
+        sink.write_batch(&[]).unwrap();
+        sink.write_batch(&[bar(3_000)]).unwrap();
+        sink.finish().unwrap();
+
+        let reader = FileReader::try_new(File::open(&path).unwrap(), None).unwrap();
+        assert_eq!(reader.schema(), bar_schema());
+        assert_eq!(reader.num_batches(), 2);
+        let batches: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        let ts = batches[1]
+            .column(1)
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .unwrap();
+        assert_eq!(ts.value(0), 3_000);
+        let tickers = batches[1]
+            .column(0)
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap();
+        assert_eq!(tickers.values().len(), 1);
+        assert!(batches[0].column(6).is_null(0));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
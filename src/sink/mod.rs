@@ -0,0 +1,803 @@
+//! Output sinks for downloaded bars.
+//!
+//! Sinks receive bars page by page and flush after every page, so a long
+//! download never has to be held in memory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDate};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::compress::{CompressedWriter, Compression, open_reader};
+use crate::timestamp::TimestampFormat;
+use crate::{Agg, Granularity, OutputFormat, ProviderKind};
+
+mod arrow;
+mod parquet;
+pub mod sqlite;
+
+pub use arrow::{ArrowSink, bar_batch, bar_schema};
+pub use parquet::ParquetSink;
+pub use sqlite::SqliteSink;
+
+/// CSV header shared by the single-file and per-day writers
+pub const CSV_HEADER: [&str; 7] = [
+    "ticker",
+    "timestamp",
+    "open",
+    "high",
+    "low",
+    "close",
+    "volume",
+];
+
+/// Root directory of the per-day files written by [`SplitByDaySink`]
+pub const SPLIT_ROOT: &str = "output";
+
+/// Options common to every sink.
+#[derive(Debug, Clone)]
+pub struct SinkOptions {
+    pub ticker: String,
+    /// Omit header row in CSV output
+    pub no_header: bool,
+    /// Maximum number of decimal places for OHLCV values
+    pub max_decimals: u8,
+    /// Source of the bars, stored alongside them by the SQLite sink
+    pub provider: ProviderKind,
+    pub granularity: Granularity,
+    pub multiplier: u32,
+    /// Compression of CSV, JSON and NDJSON files, including per-day files
+    pub compression: Compression,
+    /// Time zone and style of text timestamps; the time zone also decides
+    /// which day file a bar goes to
+    pub timestamps: TimestampFormat,
+}
+
+pub trait Sink {
+    /// Write one page of bars
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()>;
+
+    /// Flush and close the output
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Open a single-file sink for `format` at `path`, creating parent directories.
+pub fn open_sink(format: OutputFormat, path: &str, opts: &SinkOptions) -> Result<Box<dyn Sink>> {
+    Ok(match format {
+        OutputFormat::Csv => Box::new(CsvSink::create(path, opts)?),
+        OutputFormat::Json => Box::new(JsonSink::create(path, opts)?),
+        OutputFormat::Ndjson => Box::new(NdjsonSink::create(path, opts)?),
+        OutputFormat::Parquet => Box::new(ParquetSink::create(path, opts)?),
+        OutputFormat::Arrow => Box::new(ArrowSink::create(path, opts)?),
+        // Existing rows are kept and upserted into
+        OutputFormat::Sqlite => Box::new(SqliteSink::open(path, opts)?),
+    })
+}
+
+/// Reopen an existing output at `path` and continue writing after its last bar.
+///
+/// Used to resume interrupted downloads and to append incremental updates; a
+/// missing file is created as with [`open_sink`]. Fails for formats that
+/// cannot be extended in place (see [`OutputFormat::appendable`]).
+pub fn open_sink_append(
+    format: OutputFormat,
+    path: &str,
+    opts: &SinkOptions,
+) -> Result<Box<dyn Sink>> {
+    Ok(match format {
+        OutputFormat::Csv => Box::new(CsvSink::append(path, opts)?),
+        OutputFormat::Json => Box::new(JsonSink::append(path, opts)?),
+        OutputFormat::Ndjson => Box::new(NdjsonSink::append(path, opts)?),
+        OutputFormat::Sqlite => Box::new(SqliteSink::open(path, opts)?),
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            return Err(anyhow::anyhow!(
+                "Cannot append to {:?} file {}",
+                format,
+                path
+            ));
+        }
+    })
+}
+
+/// Cut `path` back to `len` bytes, discarding anything written after a checkpoint
+pub fn truncate_output(path: &str, len: u64) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Cannot open {}", path))?;
+    file.set_len(len)
+        .with_context(|| format!("Cannot truncate {}", path))
+}
+
+fn open_append(path: &str) -> Result<File> {
+    if let Some(parent) = Path::new(path).parent()
+        && !parent.as_os_str().is_empty()
+    {
+        create_dir_all(parent)
+            .with_context(|| format!("Cannot create directory {}", parent.display()))?;
+    }
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Cannot open {}", path))
+}
+
+fn create_file(path: &str) -> Result<File> {
+    // Ensure parent directory exists if path includes directories
+    if let Some(parent) = Path::new(path).parent()
+        && !parent.as_os_str().is_empty()
+    {
+        create_dir_all(parent)
+            .with_context(|| format!("Cannot create directory {}", parent.display()))?;
+    }
+    File::create(path).with_context(|| format!("Cannot create {}", path))
+}
+
+fn csv_record(ticker: &str, r: &Agg, prec: usize, ts: &TimestampFormat) -> [String; 7] {
+    let v = match r.v {
+        Some(val) => format!("{:.1$}", val, prec),
+        None => String::new(),
+    };
+    [
+        ticker.to_string(),
+        ts.format(r.t),
+        format!("{:.1$}", r.o, prec),
+        format!("{:.1$}", r.h, prec),
+        format!("{:.1$}", r.l, prec),
+        format!("{:.1$}", r.c, prec),
+        v,
+    ]
+}
+
+/// JSON object for one bar, as written by the JSON and NDJSON sinks
+fn json_object(r: &Agg, prec: i32, ts: &TimestampFormat) -> serde_json::Value {
+    let pow = 10f64.powi(prec);
+    let round_to = |x: f64| (x * pow).round() / pow;
+    serde_json::json!({
+        "timestamp": ts.json(r.t),
+        "open": round_to(r.o),
+        "high": round_to(r.h),
+        "low": round_to(r.l),
+        "close": round_to(r.c),
+        "volume": r.v.map(round_to),
+        "vw": r.vw.map(round_to),
+        "n": r.n,
+    })
+}
+
+/// Single CSV file with an optional header row.
+pub struct CsvSink {
+    writer: csv::Writer<CompressedWriter>,
+    ticker: String,
+    prec: usize,
+    timestamps: TimestampFormat,
+}
+
+impl CsvSink {
+    pub fn create(path: &str, opts: &SinkOptions) -> Result<Self> {
+        let file = CompressedWriter::new(create_file(path)?, opts.compression);
+        let mut writer = csv::Writer::from_writer(file);
+        if !opts.no_header {
+            writer.write_record(CSV_HEADER)?;
+        }
+        Ok(Self {
+            writer,
+            ticker: opts.ticker.clone(),
+            prec: opts.max_decimals as usize,
+            timestamps: opts.timestamps.clone(),
+        })
+    }
+
+    /// Append to an existing CSV, writing the header only if the file is empty
+    pub fn append(path: &str, opts: &SinkOptions) -> Result<Self> {
+        let file = open_append(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut writer = csv::Writer::from_writer(CompressedWriter::new(file, opts.compression));
+        if is_new && !opts.no_header {
+            writer.write_record(CSV_HEADER)?;
+        }
+        Ok(Self {
+            writer,
+            ticker: opts.ticker.clone(),
+            prec: opts.max_decimals as usize,
+            timestamps: opts.timestamps.clone(),
+        })
+    }
+}
+
+impl Sink for CsvSink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        for r in bars {
+            self.writer
+                .write_record(csv_record(&self.ticker, r, self.prec, &self.timestamps))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Single JSON array of bar objects.
+pub struct JsonSink {
+    file: CompressedWriter,
+    prec: i32,
+    timestamps: TimestampFormat,
+    wrote_any: bool,
+}
+
+impl JsonSink {
+    pub fn create(path: &str, opts: &SinkOptions) -> Result<Self> {
+        let mut file = CompressedWriter::new(create_file(path)?, opts.compression);
+        // Write opening bracket for an array
+        write!(file, "[")?;
+        Ok(Self {
+            file,
+            prec: opts.max_decimals as i32,
+            timestamps: opts.timestamps.clone(),
+            wrote_any: false,
+        })
+    }
+
+    /// Reopen a JSON array, complete or cut short, and continue it.
+    ///
+    /// Compressed arrays cannot be reopened, since the closing bracket would
+    /// have to be removed from inside the compressed stream.
+    pub fn append(path: &str, opts: &SinkOptions) -> Result<Self> {
+        if opts.compression != Compression::None {
+            return Err(anyhow::anyhow!(
+                "Cannot append to compressed JSON array {}; use NDJSON instead",
+                path
+            ));
+        }
+        let mut file = open_append(path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            write!(file, "[")?;
+            return Ok(Self {
+                file: CompressedWriter::new(file, opts.compression),
+                prec: opts.max_decimals as i32,
+                timestamps: opts.timestamps.clone(),
+                wrote_any: false,
+            });
+        }
+
+        // Inspect the tail: drop a closing bracket and note whether the array is empty
+        let tail_len = len.min(4096);
+        file.seek(SeekFrom::Start(len - tail_len))?;
+        let mut tail = Vec::with_capacity(tail_len as usize);
+        (&file).take(tail_len).read_to_end(&mut tail)?;
+        let mut end = tail.len();
+        while end > 0 && tail[end - 1].is_ascii_whitespace() {
+            end -= 1;
+        }
+        if end > 0 && tail[end - 1] == b']' {
+            end -= 1;
+            while end > 0 && tail[end - 1].is_ascii_whitespace() {
+                end -= 1;
+            }
+        }
+        let wrote_any = match end.checked_sub(1).map(|i| tail[i]) {
+            Some(b'[') => false,
+            Some(_) => true,
+            None if len > tail_len => true,
+            None => {
+                return Err(anyhow::anyhow!("{} is not a JSON array", path));
+            }
+        };
+        file.set_len(len - tail_len + end as u64)?;
+        Ok(Self {
+            file: CompressedWriter::new(file, opts.compression),
+            prec: opts.max_decimals as i32,
+            timestamps: opts.timestamps.clone(),
+            wrote_any,
+        })
+    }
+}
+
+impl Sink for JsonSink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        for r in bars {
+            if self.wrote_any {
+                write!(self.file, ",")?;
+            }
+            write!(self.file, "{}", json_object(r, self.prec, &self.timestamps))?;
+            self.wrote_any = true;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        // Close JSON array
+        write!(self.file, "]")?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Newline-delimited JSON: one bar object per line.
+///
+/// Every complete line is valid on its own, so an interrupted download leaves
+/// a usable file and appending needs no fix-ups beyond dropping a partial
+/// last line.
+pub struct NdjsonSink {
+    file: CompressedWriter,
+    prec: i32,
+    timestamps: TimestampFormat,
+}
+
+impl NdjsonSink {
+    pub fn create(path: &str, opts: &SinkOptions) -> Result<Self> {
+        Ok(Self {
+            file: CompressedWriter::new(create_file(path)?, opts.compression),
+            prec: opts.max_decimals as i32,
+            timestamps: opts.timestamps.clone(),
+        })
+    }
+
+    /// Continue an existing file after its last complete line.
+    ///
+    /// A compressed file is extended with new gzip members or zstd frames; a
+    /// member cut short by a crash cannot be repaired in place, which is what
+    /// the `--resume` checkpoint truncation is for.
+    pub fn append(path: &str, opts: &SinkOptions) -> Result<Self> {
+        let mut file = open_append(path)?;
+        if opts.compression == Compression::None {
+            let len = file.metadata()?.len();
+            // Walk back to the last newline; anything after it is a cut-off line
+            let mut end = len;
+            let mut buf = [0u8; 4096];
+            while end > 0 {
+                let start = end.saturating_sub(buf.len() as u64);
+                let chunk = &mut buf[..(end - start) as usize];
+                file.seek(SeekFrom::Start(start))?;
+                file.read_exact(chunk)?;
+                if let Some(i) = chunk.iter().rposition(|b| *b == b'\n') {
+                    end = start + i as u64 + 1;
+                    break;
+                }
+                end = start;
+            }
+            if end < len {
+                file.set_len(end)
+                    .with_context(|| format!("Cannot truncate {}", path))?;
+            }
+        }
+        Ok(Self {
+            file: CompressedWriter::new(file, opts.compression),
+            prec: opts.max_decimals as i32,
+            timestamps: opts.timestamps.clone(),
+        })
+    }
+}
+
+impl Sink for NdjsonSink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        for r in bars {
+            writeln!(self.file, "{}", json_object(r, self.prec, &self.timestamps))?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// What `--split-by-day` does with a day file that existed before this run.
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum SplitMode {
+    /// Replace the file with the downloaded bars
+    #[default]
+    Overwrite,
+    /// Leave the file untouched and drop the downloaded bars for that day
+    SkipExisting,
+    /// Combine existing and downloaded rows by timestamp, downloaded rows winning
+    Merge,
+}
+
+/// Per-day files under `output/YYYY/MM/TICKER_YYYY-MM-DD.csv` (or `.ndjson`,
+/// plus `.gz`/`.zst` when compressed).
+///
+/// Each day file is rewritten as a whole (via a temporary file and rename),
+/// so rerunning the same range never duplicates rows. Days already written
+/// earlier in the same run are always merged.
+pub struct SplitByDaySink {
+    opts: SinkOptions,
+    mode: SplitMode,
+    format: OutputFormat,
+    root: PathBuf,
+    /// Days handled so far in this run and whether they were skipped
+    seen: HashMap<NaiveDate, bool>,
+}
+
+impl SplitByDaySink {
+    pub fn new(opts: &SinkOptions, mode: SplitMode) -> Self {
+        Self {
+            opts: opts.clone(),
+            mode,
+            format: OutputFormat::Csv,
+            root: PathBuf::from(SPLIT_ROOT),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Write under `root` instead of [`SPLIT_ROOT`]
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Write day files in `format`, CSV or NDJSON (see [`OutputFormat::splittable`])
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    fn day_path(&self, date: NaiveDate) -> PathBuf {
+        self.root
+            .join(date.year().to_string())
+            .join(format!("{:02}", date.month()))
+            .join(format!(
+                "{}_{}.{}{}",
+                self.opts.ticker,
+                date,
+                self.format.extension(),
+                self.opts.compression.suffix()
+            ))
+    }
+
+    fn write_day(&mut self, date: NaiveDate, bars: &[&Agg]) -> Result<()> {
+        let path = self.day_path(date);
+        let merge = match self.seen.get(&date) {
+            Some(true) => return Ok(()),
+            Some(false) => true,
+            None if path.exists() => match self.mode {
+                SplitMode::Overwrite => false,
+                SplitMode::SkipExisting => {
+                    self.seen.insert(date, true);
+                    return Ok(());
+                }
+                SplitMode::Merge => true,
+            },
+            None => false,
+        };
+        self.seen.insert(date, false);
+
+        let dir = path.parent().unwrap();
+        create_dir_all(dir)
+            .with_context(|| format!("Cannot create directory {}", dir.display()))?;
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let file =
+            File::create(&tmp).with_context(|| format!("Cannot create {}", tmp.display()))?;
+        let file = CompressedWriter::new(file, self.opts.compression);
+        match self.format {
+            OutputFormat::Ndjson => self.write_ndjson_day(&path, file, merge, bars)?,
+            _ => self.write_csv_day(&path, file, merge, bars)?,
+        }
+        std::fs::rename(&tmp, &path).with_context(|| format!("Cannot write {}", path.display()))
+    }
+
+    fn write_csv_day(
+        &self,
+        path: &Path,
+        file: CompressedWriter,
+        merge: bool,
+        bars: &[&Agg],
+    ) -> Result<()> {
+        let ts_col = CSV_HEADER.iter().position(|c| *c == "timestamp").unwrap();
+        let mut rows: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+        if merge {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(open_reader(&path.to_string_lossy())?);
+            for record in reader.records() {
+                let record =
+                    record.with_context(|| format!("Invalid CSV in {}", path.display()))?;
+                // Skips the header row, which has no parseable timestamp
+                if let Some(ts) = record
+                    .get(ts_col)
+                    .and_then(|s| self.opts.timestamps.parse(s))
+                {
+                    rows.insert(ts, record.iter().map(str::to_owned).collect());
+                }
+            }
+        }
+        let prec = self.opts.max_decimals as usize;
+        for r in bars {
+            rows.insert(
+                r.t,
+                csv_record(&self.opts.ticker, r, prec, &self.opts.timestamps).to_vec(),
+            );
+        }
+
+        let mut writer = csv::Writer::from_writer(file);
+        if !self.opts.no_header {
+            writer.write_record(CSV_HEADER)?;
+        }
+        for row in rows.values() {
+            writer.write_record(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_ndjson_day(
+        &self,
+        path: &Path,
+        mut file: CompressedWriter,
+        merge: bool,
+        bars: &[&Agg],
+    ) -> Result<()> {
+        let mut rows: BTreeMap<i64, String> = BTreeMap::new();
+        if merge {
+            let mut text = String::new();
+            open_reader(&path.to_string_lossy())?
+                .read_to_string(&mut text)
+                .with_context(|| format!("Cannot read {}", path.display()))?;
+            for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                let row: serde_json::Value = serde_json::from_str(line)
+                    .with_context(|| format!("Invalid NDJSON in {}", path.display()))?;
+                if let Some(ts) = self.opts.timestamps.parse_json(&row["timestamp"]) {
+                    rows.insert(ts, line.to_owned());
+                }
+            }
+        }
+        let prec = self.opts.max_decimals as i32;
+        for r in bars {
+            rows.insert(r.t, json_object(r, prec, &self.opts.timestamps).to_string());
+        }
+        for line in rows.values() {
+            writeln!(file, "{}", line)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl Sink for SplitByDaySink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        let mut days: BTreeMap<NaiveDate, Vec<&Agg>> = BTreeMap::new();
+        for r in bars {
+            if let Some(date) = self.opts.timestamps.date(r.t) {
+                days.entry(date).or_default().push(r);
+            }
+        }
+        for (date, bars) in days {
+            self.write_day(date, &bars)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let skipped = self.seen.values().filter(|skipped| **skipped).count();
+        if skipped > 0 {
+            eprintln!("Skipped {} existing day file(s)", skipped);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(t: i64) -> Agg {
+        Agg {
+            t,
+            o: 1.0,
+            h: 1.0,
+            l: 1.0,
+            c: 1.0,
+            v: None,
+            vw: None,
+            n: None,
+        }
+    }
+
+    fn scratch(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("mdd-sink-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn opts() -> SinkOptions {
+        SinkOptions {
+            ticker: "AAPL".into(),
+            no_header: false,
+            max_decimals: 2,
+            provider: ProviderKind::Polygon,
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            compression: Compression::None,
+            timestamps: TimestampFormat::default(),
+        }
+    }
+
+    #[test]
+    fn test_json_append_continues_closed_and_cut_short_arrays() {
+        let path = scratch("append.json");
+        let mut sink = JsonSink::create(&path, &opts()).unwrap();
+        sink.write_batch(&[bar(0)]).unwrap();
+        Box::new(sink).finish().unwrap();
+
+        // Closed array
+        let mut sink = JsonSink::append(&path, &opts()).unwrap();
+        sink.write_batch(&[bar(60_000)]).unwrap();
+        // Simulate a crash: no closing bracket
+        drop(sink);
+        let mut sink = JsonSink::append(&path, &opts()).unwrap();
+        sink.write_batch(&[bar(120_000)]).unwrap();
+        Box::new(sink).finish().unwrap();
+
+        let rows: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2]["timestamp"], "1970-01-01 00:02:00");
+    }
+
+    #[test]
+    fn test_ndjson_append_drops_cut_off_line() {
+        let path = scratch("append.ndjson");
+        let mut sink = NdjsonSink::create(&path, &opts()).unwrap();
+        sink.write_batch(&[bar(0), bar(60_000)]).unwrap();
+        Box::new(sink).finish().unwrap();
+        // Simulate a crash in the middle of a line
+        let mut file = open_append(&path).unwrap();
+        write!(file, "{{\"timestamp\":\"1970-01").unwrap();
+        drop(file);
+
+        let mut sink = NdjsonSink::append(&path, &opts()).unwrap();
+        sink.write_batch(&[bar(120_000)]).unwrap();
+        Box::new(sink).finish().unwrap();
+
+        let data = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<serde_json::Value> = data
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2]["timestamp"], "1970-01-01 00:02:00");
+    }
+
+    #[test]
+    fn test_csv_append_skips_header_on_existing_file() {
+        let path = scratch("append.csv");
+        let mut sink = CsvSink::create(&path, &opts()).unwrap();
+        sink.write_batch(&[bar(0)]).unwrap();
+        Box::new(sink).finish().unwrap();
+        let mut sink = CsvSink::append(&path, &opts()).unwrap();
+        sink.write_batch(&[bar(60_000)]).unwrap();
+        Box::new(sink).finish().unwrap();
+
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(data.lines().count(), 3);
+        assert_eq!(data.matches("ticker,").count(), 1);
+    }
+
+    #[test]
+    fn test_csv_record_rounds_and_blanks_missing_volume() {
+        let bar = Agg {
+            t: 0,
+            o: 1.23456,
+            h: 2.0,
+            l: 1.0,
+            c: 1.5,
+            v: None,
+            vw: None,
+            n: None,
+        };
+        let rec = csv_record("I:NDX", &bar, 2, &TimestampFormat::default());
+        assert_eq!(
+            rec,
+            [
+                "I:NDX",
+                "1970-01-01 00:00:00",
+                "1.23",
+                "2.00",
+                "1.00",
+                "1.50",
+                ""
+            ]
+        );
+    }
+
+    fn read_day(root: &Path, name: &str) -> Vec<String> {
+        std::fs::read_to_string(root.join("2024/02").join(name))
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn split_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("mdd-split-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    // 2024-02-01 14:30:00 UTC
+    const T0: i64 = 1706797800000;
+
+    fn rerun(root: &Path, mode: SplitMode, bars: &[Agg]) {
+        let mut sink = SplitByDaySink::new(&opts(), mode).with_root(root);
+        sink.write_batch(bars).unwrap();
+        Box::new(sink).finish().unwrap();
+    }
+
+    #[test]
+    fn test_split_overwrite_is_idempotent() {
+        let root = split_root("overwrite");
+        let bars = [bar(T0), bar(T0 + 60_000), bar(T0 + 86_400_000)];
+        rerun(&root, SplitMode::Overwrite, &bars);
+        rerun(&root, SplitMode::Overwrite, &bars);
+        assert_eq!(read_day(&root, "AAPL_2024-02-01.csv").len(), 3);
+        assert_eq!(read_day(&root, "AAPL_2024-02-02.csv").len(), 2);
+    }
+
+    #[test]
+    fn test_split_same_day_across_batches_is_kept() {
+        let root = split_root("batches");
+        let mut sink = SplitByDaySink::new(&opts(), SplitMode::Overwrite).with_root(&root);
+        sink.write_batch(&[bar(T0)]).unwrap();
+        sink.write_batch(&[bar(T0 + 60_000)]).unwrap();
+        Box::new(sink).finish().unwrap();
+        assert_eq!(read_day(&root, "AAPL_2024-02-01.csv").len(), 3);
+    }
+
+    #[test]
+    fn test_split_skip_existing_and_merge() {
+        let root = split_root("modes");
+        rerun(&root, SplitMode::Overwrite, &[bar(T0)]);
+
+        rerun(&root, SplitMode::SkipExisting, &[bar(T0 + 60_000)]);
+        assert_eq!(read_day(&root, "AAPL_2024-02-01.csv").len(), 2);
+
+        rerun(&root, SplitMode::Merge, &[bar(T0 + 60_000), bar(T0)]);
+        let lines = read_day(&root, "AAPL_2024-02-01.csv");
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("14:30:00"));
+        assert!(lines[2].contains("14:31:00"));
+    }
+
+    #[test]
+    fn test_split_by_day_ndjson_merge() {
+        let root = split_root("ndjson");
+        let write = |bars: &[Agg]| {
+            let mut sink = SplitByDaySink::new(&opts(), SplitMode::Merge)
+                .with_root(&root)
+                .with_format(OutputFormat::Ndjson);
+            sink.write_batch(bars).unwrap();
+            Box::new(sink).finish().unwrap();
+        };
+        write(&[bar(T0), bar(T0 + 60_000)]);
+        write(&[bar(T0 + 60_000), bar(T0 + 120_000)]);
+
+        let lines = read_day(&root, "AAPL_2024-02-01.ndjson");
+        assert_eq!(lines.len(), 3);
+        let last: serde_json::Value = serde_json::from_str(&lines[2]).unwrap();
+        assert_eq!(last["timestamp"], "2024-02-01 14:32:00");
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,132 @@
+//! Parquet output with one row group per page.
+
+use std::fs::File;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow_schema::SchemaRef;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use super::arrow::{bar_batch, bar_schema};
+use super::{Sink, SinkOptions, create_file};
+use crate::Agg;
+
+/// Single Parquet file, Snappy-compressed.
+///
+/// Every page becomes its own row group, so only one page is buffered at a
+/// time. The footer is written by [`Sink::finish`]; a file cut short before
+/// that is unreadable, which is why Parquet downloads cannot be resumed.
+pub struct ParquetSink {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    ticker: String,
+    prec: i32,
+}
+
+impl ParquetSink {
+    pub fn create(path: &str, opts: &SinkOptions) -> Result<Self> {
+        let schema = bar_schema();
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(create_file(path)?, Arc::clone(&schema), Some(props))
+            .with_context(|| format!("Cannot write {}", path))?;
+        Ok(Self {
+            writer,
+            schema,
+            ticker: opts.ticker.clone(),
+            prec: opts.max_decimals as i32,
+        })
+    }
+}
+
+impl Sink for ParquetSink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        if bars.is_empty() {
+            return Ok(());
+        }
+        let batch = bar_batch(&self.schema, &self.ticker, bars, self.prec)?;
+        self.writer.write(&batch)?;
+        // Close the row group so the page is not held in memory
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Granularity, ProviderKind};
+    use arrow_array::{Array, Float64Array, TimestampMillisecondArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn bar(t: i64, v: Option<f64>) -> Agg {
+        Agg {
+            t,
+            o: 1.234,
+            h: 2.0,
+            l: 0.5,
+            c: 1.5,
+            v,
+            vw: None,
+            n: Some(7),
+        }
+    }
+
+    #[test]
+    fn test_parquet_row_group_per_page_and_typed_columns() {
+        let dir = std::env::temp_dir().join(format!("mdd-parquet-{}", std::process::id()));
+        let path = dir.join("aapl.parquet");
+        let opts = SinkOptions {
+            ticker: "AAPL".into(),
+            no_header: false,
+            max_decimals: 2,
+            provider: ProviderKind::Polygon,
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            compression: Default::default(),
+            timestamps: Default::default(),
+        };
+        let mut sink: Box<dyn Sink> =
+            Box::new(ParquetSink::create(path.to_str().unwrap(), &opts).unwrap());
+        sink.write_batch(&[bar(1_000, Some(10.0)), bar(2_000, None)])
+            .unwrap();
+        sink.write_batch(&[bar(3_000, Some(5.0))]).unwrap();
+        sink.finish().unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        assert_eq!(builder.schema().as_ref(), bar_schema().as_ref());
+        let batches: Vec<_> = builder.build().unwrap().collect::<Result<_, _>>().unwrap();
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 3);
+
+        let first = &batches[0];
+        let ts = first
+            .column(1)
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .unwrap();
+        assert_eq!(ts.value(1), 2_000);
+        let open = first
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(open.value(0), 1.23);
+        let volume = first
+            .column(6)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert!(volume.is_null(1));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,266 @@
+//! SQLite output that consolidates downloads into one `bars` table.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use rusqlite::{Connection, params};
+
+use super::{Sink, SinkOptions};
+use crate::Agg;
+use crate::timestamp::TimestampFormat;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS bars (
+    ticker TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    granularity TEXT NOT NULL,
+    ts INTEGER NOT NULL,
+    open REAL NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    close REAL NOT NULL,
+    volume REAL,
+    vw REAL,
+    n INTEGER,
+    PRIMARY KEY (ticker, provider, granularity, ts)
+) WITHOUT ROWID";
+
+const UPSERT: &str = "INSERT INTO bars
+    (ticker, provider, granularity, ts, open, high, low, close, volume, vw, n)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+    ON CONFLICT (ticker, provider, granularity, ts) DO UPDATE SET
+    open = excluded.open, high = excluded.high, low = excluded.low,
+    close = excluded.close, volume = excluded.volume, vw = excluded.vw,
+    n = excluded.n";
+
+/// Name of a CLI value as stored in the `provider` and `granularity` columns
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_owned())
+        .unwrap_or_default()
+}
+
+/// Open (or create) the database at `path` with the `bars` table in place
+pub fn open_database(path: &str) -> Result<Connection> {
+    if let Some(parent) = Path::new(path).parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Cannot create directory {}", parent.display()))?;
+    }
+    let conn = Connection::open(path).with_context(|| format!("Cannot open {}", path))?;
+    // Several tickers of a batch may write to the same file
+    conn.busy_timeout(Duration::from_secs(30))?;
+    conn.execute_batch(CREATE_TABLE)
+        .with_context(|| format!("Cannot create bars table in {}", path))?;
+    Ok(conn)
+}
+
+/// Value of the `granularity` column: the timespan name, prefixed with the
+/// multiplier unless it is 1 (e.g. `minute`, `5minute`)
+fn interval_name(opts: &SinkOptions) -> String {
+    match opts.multiplier {
+        1 => opts.granularity.name().to_owned(),
+        m => format!("{}{}", m, opts.granularity.name()),
+    }
+}
+
+/// Latest `ts` stored for one series, or `None` when the database is missing
+/// or has no rows for it yet
+pub fn last_timestamp(path: &str, opts: &SinkOptions) -> Result<Option<i64>> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+    let conn = open_database(path)?;
+    conn.query_row(
+        "SELECT MAX(ts) FROM bars WHERE ticker = ?1 AND provider = ?2 AND granularity = ?3",
+        params![opts.ticker, value_name(opts.provider), interval_name(opts)],
+        |row| row.get(0),
+    )
+    .with_context(|| format!("Cannot query {}", path))
+}
+
+/// Rows of the `bars` table keyed by `(ticker, provider, granularity, ts)`.
+///
+/// Existing rows are updated in place, so repeated or overlapping downloads
+/// never duplicate bars. Each page is committed in its own transaction, and a
+/// page holding a NaN or infinite price is rejected before any row is bound.
+pub struct SqliteSink {
+    conn: Connection,
+    ticker: String,
+    provider: String,
+    granularity: String,
+    prec: i32,
+    timestamps: TimestampFormat,
+}
+
+impl SqliteSink {
+    pub fn open(path: &str, opts: &SinkOptions) -> Result<Self> {
+        Ok(Self {
+            conn: open_database(path)?,
+            ticker: opts.ticker.clone(),
+            provider: value_name(opts.provider),
+            granularity: interval_name(opts),
+            prec: opts.max_decimals as i32,
+            timestamps: opts.timestamps.clone(),
+        })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write_batch(&mut self, bars: &[Agg]) -> Result<()> {
+        // SQLite binds NaN as NULL, which the NOT NULL columns then reject
+        // without saying which bar was at fault
+        if let Some(r) = bars.iter().find(|r| {
+            [r.o, r.h, r.l, r.c]
+                .into_iter()
+                .chain(r.v)
+                .chain(r.vw)
+                .any(|x| !x.is_finite())
+        }) {
+            return Err(anyhow!(
+                "Bar at {} of {} has a non-finite value and cannot be stored in SQLite; use --validate drop to skip such bars",
+                self.timestamps.format(r.t),
+                self.ticker
+            ));
+        }
+        let pow = 10f64.powi(self.prec);
+        let round_to = |x: f64| (x * pow).round() / pow;
+        let tx = self.conn.transaction()?;
+        {
+            let mut upsert = tx.prepare_cached(UPSERT)?;
+            for r in bars {
+                upsert.execute(params![
+                    self.ticker,
+                    self.provider,
+                    self.granularity,
+                    r.t,
+                    round_to(r.o),
+                    round_to(r.h),
+                    round_to(r.l),
+                    round_to(r.c),
+                    r.v.map(round_to),
+                    r.vw.map(round_to),
+                    r.n,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.conn.close().map_err(|(_, e)| e)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Granularity, ProviderKind};
+
+    fn bar(t: i64, c: f64) -> Agg {
+        Agg {
+            t,
+            o: 1.0,
+            h: 2.0,
+            l: 0.5,
+            c,
+            v: Some(100.0),
+            vw: None,
+            n: None,
+        }
+    }
+
+    #[test]
+    fn test_overlapping_writes_upsert() {
+        let dir = std::env::temp_dir().join(format!("mdd-sqlite-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("bars.db");
+        let path = path.to_str().unwrap();
+        let opts = SinkOptions {
+            ticker: "AAPL".into(),
+            no_header: false,
+            max_decimals: 2,
+            provider: ProviderKind::Polygon,
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            compression: Default::default(),
+            timestamps: Default::default(),
+        };
+
+        let mut sink = Box::new(SqliteSink::open(path, &opts).unwrap());
+        sink.write_batch(&[bar(1_000, 1.0), bar(2_000, 1.5)])
+            .unwrap();
+        sink.finish().unwrap();
+        let mut sink = Box::new(SqliteSink::open(path, &opts).unwrap());
+        sink.write_batch(&[bar(2_000, 1.75), bar(3_000, 2.0)])
+            .unwrap();
+        sink.finish().unwrap();
+        // Same timestamps for another ticker are a separate series
+        let other = SinkOptions {
+            ticker: "MSFT".into(),
+            ..opts.clone()
+        };
+        let mut sink = Box::new(SqliteSink::open(path, &other).unwrap());
+        sink.write_batch(&[bar(1_000, 9.0)]).unwrap();
+        sink.finish().unwrap();
+
+        let conn = Connection::open(path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM bars WHERE ticker = 'AAPL'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 3);
+        let close: f64 = conn
+            .query_row(
+                "SELECT close FROM bars WHERE ticker = 'AAPL' AND ts = 2000",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(close, 1.75);
+        assert_eq!(last_timestamp(path, &opts).unwrap(), Some(3_000));
+        assert_eq!(last_timestamp(path, &other).unwrap(), Some(1_000));
+        let five_minute = SinkOptions {
+            multiplier: 5,
+            ..opts.clone()
+        };
+        assert_eq!(last_timestamp(path, &five_minute).unwrap(), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_non_finite_bar_is_rejected_by_name() {
+        let dir = std::env::temp_dir().join(format!("mdd-sqlite-nan-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("bars.db");
+        let path = path.to_str().unwrap();
+        let opts = SinkOptions {
+            ticker: "AAPL".into(),
+            no_header: false,
+            max_decimals: 2,
+            provider: ProviderKind::Polygon,
+            granularity: Granularity::Minute,
+            multiplier: 1,
+            compression: Default::default(),
+            timestamps: Default::default(),
+        };
+
+        let mut sink = Box::new(SqliteSink::open(path, &opts).unwrap());
+        let err = sink
+            .write_batch(&[bar(1_000, 1.0), bar(60_000, f64::NAN)])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&opts.timestamps.format(60_000)), "{err}");
+        assert!(err.contains("--validate drop"), "{err}");
+        sink.finish().unwrap();
+        // Nothing of the rejected page was committed
+        assert_eq!(last_timestamp(path, &opts).unwrap(), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
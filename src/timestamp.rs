@@ -0,0 +1,239 @@
+//! Rendering of bar timestamps in text outputs.
+//!
+//! Bars carry milliseconds since the epoch (UTC). A [`TimestampFormat`]
+//! renders them in a time zone and style, and parses them back when an
+//! existing output is read, e.g. by `update` or `--split-by-day` merging.
+
+use chrono::format::StrftimeItems;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Layout of rendered timestamps
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimestampStyle {
+    /// `2024-02-01 14:30:00`, followed by the UTC offset outside UTC
+    #[default]
+    Plain,
+    /// `2024-02-01T09:30:00-05:00`
+    Iso8601,
+    /// Milliseconds since the epoch
+    EpochMs,
+    /// Seconds since the epoch
+    EpochS,
+    /// A strftime pattern given with `--timestamp-pattern`
+    Custom,
+}
+
+const PLAIN: &str = "%Y-%m-%d %H:%M:%S";
+const PLAIN_WITH_OFFSET: &str = "%Y-%m-%d %H:%M:%S%:z";
+
+/// Time zone and style of the timestamps in an output
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampFormat {
+    pub style: TimestampStyle,
+    pub tz: Tz,
+    /// strftime pattern of [`TimestampStyle::Custom`]
+    pub pattern: Option<String>,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self {
+            style: TimestampStyle::Plain,
+            tz: Tz::UTC,
+            pattern: None,
+        }
+    }
+}
+
+impl TimestampFormat {
+    /// Format for `style` in `tz`; `pattern` is required by, and only allowed
+    /// with, [`TimestampStyle::Custom`]
+    pub fn new(style: TimestampStyle, tz: Tz, pattern: Option<String>) -> anyhow::Result<Self> {
+        match (style, &pattern) {
+            (TimestampStyle::Custom, None) => {
+                return Err(anyhow::anyhow!(
+                    "--timestamp-format custom needs --timestamp-pattern, e.g. \"%d/%m/%Y %H:%M\""
+                ));
+            }
+            (TimestampStyle::Custom, Some(p)) if StrftimeItems::new(p).parse().is_err() => {
+                return Err(anyhow::anyhow!("Invalid timestamp pattern '{}'", p));
+            }
+            (TimestampStyle::Custom, Some(_)) | (_, None) => {}
+            (_, Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "--timestamp-pattern requires --timestamp-format custom"
+                ));
+            }
+        }
+        Ok(Self { style, tz, pattern })
+    }
+
+    fn local(&self, ms: i64) -> Option<DateTime<Tz>> {
+        self.tz.timestamp_millis_opt(ms).single()
+    }
+
+    /// Render `ms` since the epoch; out-of-range values fall back to raw milliseconds
+    pub fn format(&self, ms: i64) -> String {
+        let Some(dt) = self.local(ms) else {
+            return ms.to_string();
+        };
+        match self.style {
+            TimestampStyle::Plain if self.tz == Tz::UTC => dt.format(PLAIN).to_string(),
+            TimestampStyle::Plain => dt.format(PLAIN_WITH_OFFSET).to_string(),
+            TimestampStyle::Iso8601 => dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            TimestampStyle::EpochMs => ms.to_string(),
+            TimestampStyle::EpochS => ms.div_euclid(1000).to_string(),
+            TimestampStyle::Custom => dt
+                .format(self.pattern.as_deref().unwrap_or(PLAIN))
+                .to_string(),
+        }
+    }
+
+    /// JSON value of `ms`: a number for the epoch styles, a string otherwise
+    pub fn json(&self, ms: i64) -> serde_json::Value {
+        match self.style {
+            TimestampStyle::EpochMs => ms.into(),
+            TimestampStyle::EpochS => ms.div_euclid(1000).into(),
+            _ => self.format(ms).into(),
+        }
+    }
+
+    /// Parse a timestamp written in this format, or by earlier versions of the
+    /// tool, back into milliseconds since the epoch. Epoch numbers are read in
+    /// the unit of an epoch style, and otherwise told apart by their size
+    pub fn parse(&self, s: &str) -> Option<i64> {
+        let s = s.trim();
+        if let (TimestampStyle::Custom, Some(pattern)) = (self.style, &self.pattern) {
+            if let Ok(dt) = DateTime::parse_from_str(s, pattern) {
+                return Some(dt.timestamp_millis());
+            }
+            let naive = NaiveDateTime::parse_from_str(s, pattern).ok().or_else(|| {
+                NaiveDate::parse_from_str(s, pattern)
+                    .ok()
+                    .and_then(|d| d.and_hms_opt(0, 0, 0))
+            });
+            if let Some(naive) = naive {
+                return self
+                    .tz
+                    .from_local_datetime(&naive)
+                    .earliest()
+                    .map(|dt| dt.timestamp_millis());
+            }
+        }
+        if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+            return Some(dt.timestamp_millis());
+        }
+        if let Ok(dt) = DateTime::parse_from_str(s, PLAIN_WITH_OFFSET) {
+            return Some(dt.timestamp_millis());
+        }
+        // Without an offset, plain timestamps are UTC
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, PLAIN) {
+            return Some(Utc.from_utc_datetime(&dt).timestamp_millis());
+        }
+        let n: i64 = s.parse().ok()?;
+        match self.style {
+            TimestampStyle::EpochS => n.checked_mul(1000),
+            TimestampStyle::EpochMs => Some(n),
+            // Seconds have at most 11 digits until the year 5138, milliseconds
+            // since 1973 at least 12
+            _ if n.unsigned_abs() < 100_000_000_000 => n.checked_mul(1000),
+            _ => Some(n),
+        }
+    }
+
+    /// Parse a JSON `timestamp` field, string or number
+    pub fn parse_json(&self, value: &serde_json::Value) -> Option<i64> {
+        match value {
+            serde_json::Value::String(s) => self.parse(s),
+            serde_json::Value::Number(n) => self.parse(&n.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Calendar date of `ms` in the time zone
+    pub fn date(&self, ms: i64) -> Option<NaiveDate> {
+        self.local(ms).map(|dt| dt.date_naive())
+    }
+}
+
+/// Parse an IANA time zone name such as `America/New_York`
+pub fn parse_tz(s: &str) -> anyhow::Result<Tz> {
+    s.trim().parse().map_err(|_| {
+        anyhow::anyhow!(
+            "Unknown time zone '{}': expected an IANA name such as America/New_York",
+            s
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-01 14:30:00 UTC
+    const T0: i64 = 1706797800000;
+
+    fn new_york(style: TimestampStyle) -> TimestampFormat {
+        TimestampFormat::new(style, parse_tz("America/New_York").unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn test_default_matches_legacy_format() {
+        let f = TimestampFormat::default();
+        assert_eq!(f.format(T0), "2024-02-01 14:30:00");
+        assert_eq!(f.format(T0), crate::fmt_ts(T0));
+        assert_eq!(f.parse("2024-02-01 14:30:00"), Some(T0));
+    }
+
+    #[test]
+    fn test_styles_in_exchange_time_roundtrip() {
+        let cases = [
+            (TimestampStyle::Plain, "2024-02-01 09:30:00-05:00"),
+            (TimestampStyle::Iso8601, "2024-02-01T09:30:00-05:00"),
+            (TimestampStyle::EpochMs, "1706797800000"),
+            (TimestampStyle::EpochS, "1706797800"),
+        ];
+        for (style, expected) in cases {
+            let f = new_york(style);
+            assert_eq!(f.format(T0), expected);
+            assert_eq!(f.parse(expected), Some(T0), "{:?}", style);
+        }
+        assert_eq!(new_york(TimestampStyle::EpochS).json(T0), 1706797800);
+        // Epoch numbers read with another style
+        let plain = TimestampFormat::default();
+        assert_eq!(plain.parse("1706797800"), Some(T0));
+        assert_eq!(plain.parse("1706797800000"), Some(T0));
+        let utc = TimestampFormat::new(TimestampStyle::Iso8601, Tz::UTC, None).unwrap();
+        assert_eq!(utc.format(T0), "2024-02-01T14:30:00Z");
+    }
+
+    #[test]
+    fn test_custom_pattern_is_local_time() {
+        let tz = parse_tz("America/New_York").unwrap();
+        let f = TimestampFormat::new(
+            TimestampStyle::Custom,
+            tz,
+            Some("%d/%m/%Y %H:%M".to_string()),
+        )
+        .unwrap();
+        assert_eq!(f.format(T0), "01/02/2024 09:30");
+        assert_eq!(f.parse("01/02/2024 09:30"), Some(T0));
+        assert!(TimestampFormat::new(TimestampStyle::Custom, tz, None).is_err());
+        assert!(TimestampFormat::new(TimestampStyle::Iso8601, tz, Some("%H".into())).is_err());
+        assert!(parse_tz("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn test_local_date() {
+        let f = new_york(TimestampStyle::Plain);
+        // 2024-02-02 02:00 UTC is still the evening of Feb 1 in New York
+        let late = T0 + 11 * 3_600_000 + 30 * 60_000;
+        assert_eq!(f.date(late), NaiveDate::from_ymd_opt(2024, 2, 1));
+    }
+}
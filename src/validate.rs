@@ -0,0 +1,236 @@
+//! Sanity checks of parsed bars before they reach a sink.
+//!
+//! Providers occasionally send bars that cannot be right, e.g. a high below
+//! the low or "NaN" in a numeric string field. A [`Validator`] counts them
+//! per [`Rule`] and keeps, drops or rejects them according to its
+//! [`ValidationMode`].
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+
+use crate::Agg;
+use crate::timestamp::TimestampFormat;
+
+/// What to do with a bar that breaks a rule
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+pub enum ValidationMode {
+    /// Write every bar unchecked
+    Off,
+    /// Write invalid bars too, and summarize them at the end
+    #[default]
+    Warn,
+    /// Leave invalid bars out of the output
+    Drop,
+    /// Stop the download at the first invalid bar
+    Fail,
+}
+
+/// Check applied to every bar
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Rule {
+    /// A price, volume or VWAP that is NaN or infinite
+    NotFinite,
+    HighBelowLow,
+    NegativeVolume,
+    CloseOutsideRange,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 4] = [
+        Rule::NotFinite,
+        Rule::HighBelowLow,
+        Rule::NegativeVolume,
+        Rule::CloseOutsideRange,
+    ];
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Rule::NotFinite => "non-finite value",
+            Rule::HighBelowLow => "high below low",
+            Rule::NegativeVolume => "negative volume",
+            Rule::CloseOutsideRange => "close outside [low, high]",
+        }
+    }
+
+    /// Whether `bar` breaks this rule
+    pub fn broken_by(self, bar: &Agg) -> bool {
+        match self {
+            Rule::NotFinite => [bar.o, bar.h, bar.l, bar.c]
+                .into_iter()
+                .chain(bar.v)
+                .chain(bar.vw)
+                .any(|x| !x.is_finite()),
+            Rule::HighBelowLow => bar.h < bar.l,
+            Rule::NegativeVolume => bar.v.is_some_and(|v| v < 0.0),
+            Rule::CloseOutsideRange => bar.h >= bar.l && (bar.c < bar.l || bar.c > bar.h),
+        }
+    }
+}
+
+/// Rules broken by `bar`, in [`Rule::ALL`] order
+pub fn check(bar: &Agg) -> Vec<Rule> {
+    Rule::ALL
+        .into_iter()
+        .filter(|rule| rule.broken_by(bar))
+        .collect()
+}
+
+/// Applies a [`ValidationMode`] to the pages of a download and keeps count
+#[derive(Debug, Clone, Default)]
+pub struct Validator {
+    mode: ValidationMode,
+    invalid: u64,
+    /// Bars breaking each rule and the timestamp of the first one
+    rules: BTreeMap<Rule, (u64, i64)>,
+}
+
+impl Validator {
+    pub fn new(mode: ValidationMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /// Check `bars`, removing the invalid ones in [`ValidationMode::Drop`];
+    /// in [`ValidationMode::Fail`] the first invalid bar is an error, with
+    /// its timestamp rendered by `ts`
+    pub fn apply(&mut self, bars: &mut Vec<Agg>, ts: &TimestampFormat) -> Result<()> {
+        if self.mode == ValidationMode::Off {
+            return Ok(());
+        }
+        let mut failure = None;
+        bars.retain(|bar| {
+            let broken = check(bar);
+            if broken.is_empty() {
+                return true;
+            }
+            self.invalid += 1;
+            for rule in &broken {
+                self.rules.entry(*rule).or_insert((0, bar.t)).0 += 1;
+            }
+            if failure.is_none() {
+                failure = Some((bar.t, broken));
+            }
+            self.mode != ValidationMode::Drop
+        });
+        match failure {
+            Some((t, broken)) if self.mode == ValidationMode::Fail => Err(anyhow!(
+                "Invalid bar at {}: {}; use --validate warn or drop to continue past it",
+                ts.format(t),
+                broken
+                    .iter()
+                    .map(|rule| rule.describe())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of bars that broke at least one rule
+    pub fn invalid(&self) -> u64 {
+        self.invalid
+    }
+
+    /// Per-rule count of invalid bars, or `None` when every bar passed
+    pub fn summary(&self, ts: &TimestampFormat) -> Option<String> {
+        if self.invalid == 0 {
+            return None;
+        }
+        let action = match self.mode {
+            ValidationMode::Drop => "dropped",
+            _ => "kept",
+        };
+        let mut out = format!("{} invalid bar(s), {}:\n", self.invalid, action);
+        for (rule, (count, first)) in &self.rules {
+            writeln!(
+                out,
+                "  {}: {} (first at {})",
+                rule.describe(),
+                count,
+                ts.format(*first)
+            )
+            .unwrap();
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(t: i64, o: f64, h: f64, l: f64, c: f64, v: f64) -> Agg {
+        Agg {
+            t,
+            o,
+            h,
+            l,
+            c,
+            v: Some(v),
+            vw: None,
+            n: None,
+        }
+    }
+
+    fn page() -> Vec<Agg> {
+        vec![
+            bar(0, 10.0, 11.0, 9.0, 10.5, 100.0),
+            bar(60_000, 10.0, 9.0, 11.0, 10.0, 100.0),
+            bar(120_000, 10.0, 11.0, 9.0, 12.0, -5.0),
+            bar(180_000, f64::NAN, 11.0, 9.0, 10.0, 100.0),
+        ]
+    }
+
+    #[test]
+    fn test_rules() {
+        let [good, inverted, outside, nan] = page().try_into().unwrap();
+        assert!(check(&good).is_empty());
+        assert_eq!(check(&inverted), vec![Rule::HighBelowLow]);
+        assert_eq!(
+            check(&outside),
+            vec![Rule::NegativeVolume, Rule::CloseOutsideRange]
+        );
+        assert_eq!(check(&nan), vec![Rule::NotFinite]);
+        // Indices have no volume to check
+        let index = Agg { v: None, ..good };
+        assert!(check(&index).is_empty());
+    }
+
+    #[test]
+    fn test_modes() {
+        let ts = TimestampFormat::default();
+        let mut bars = page();
+        let mut warn = Validator::new(ValidationMode::Warn);
+        warn.apply(&mut bars, &ts).unwrap();
+        assert_eq!(bars.len(), 4);
+        assert_eq!(warn.invalid(), 3);
+        let summary = warn.summary(&ts).unwrap();
+        assert!(
+            summary.starts_with("3 invalid bar(s), kept:\n"),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("  high below low: 1 (first at 1970-01-01 00:01:00)"));
+        assert!(summary.contains("  negative volume: 1 (first at 1970-01-01 00:02:00)"));
+
+        let mut drop = Validator::new(ValidationMode::Drop);
+        drop.apply(&mut bars, &ts).unwrap();
+        assert_eq!(bars, &page()[..1]);
+
+        let mut fail = Validator::new(ValidationMode::Fail);
+        let err = fail.apply(&mut page(), &ts).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Invalid bar at 1970-01-01 00:01:00: high below low")
+        );
+
+        let mut off = Validator::new(ValidationMode::Off);
+        off.apply(&mut page(), &ts).unwrap();
+        assert_eq!(off.summary(&ts), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! Minimal HTTP/1.1 stand-in for the provider APIs.
+//!
+//! Routes are matched on path plus required query fragments, in the order
+//! they were registered. A route with several responses serves them in turn
+//! and keeps repeating the last one, which is enough to script paging chains,
+//! error payloads and transient failures.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(body: impl Into<String>) -> Self {
+        Self::status(200, body)
+    }
+
+    pub fn status(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+struct Route {
+    path: String,
+    query: Vec<String>,
+    responses: Vec<Response>,
+}
+
+impl Route {
+    fn matches(&self, path: &str, query: &str) -> bool {
+        self.path == path && self.query.iter().all(|q| query.contains(q.as_str()))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<String>,
+}
+
+pub struct MockServer {
+    addr: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(Mutex::new(State::default()));
+        let shared = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || handle(stream, &shared));
+            }
+        });
+        Self { addr, state }
+    }
+
+    /// Base URL to pass as `--base-url`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Serve `responses` for GET `path` when the query contains every fragment in `query`
+    pub fn mock(&self, path: &str, query: &[&str], responses: Vec<Response>) {
+        assert!(!responses.is_empty(), "route needs at least one response");
+        self.state.lock().unwrap().routes.push(Route {
+            path: path.to_string(),
+            query: query.iter().map(|q| q.to_string()).collect(),
+            responses,
+        });
+    }
+
+    /// Request targets (path and query) received so far
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+fn handle(mut stream: TcpStream, state: &Mutex<State>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Drain headers; the clients under test only send GET requests without a body
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) if line == "\r\n" || line == "\n" => break,
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target.as_str(), ""));
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(target.clone());
+        match state.routes.iter_mut().find(|r| r.matches(path, query)) {
+            Some(route) if route.responses.len() > 1 => route.responses.remove(0),
+            Some(route) => route.responses[0].clone(),
+            None => Response::status(404, format!(r#"{{"error":"no route for {}"}}"#, target)),
+        }
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(response.body.as_bytes());
+    let _ = stream.flush();
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        403 => "Forbidden",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// Polygon aggregates body with one minute bar per timestamp and an optional `next_url`
+pub fn polygon_page(timestamps: &[i64], next_url: Option<&str>) -> String {
+    let results: Vec<_> = timestamps
+        .iter()
+        .map(|t| serde_json::json!({"t": t, "o": 10.0, "h": 11.0, "l": 9.5, "c": 10.5, "v": 100.0, "vw": 10.25, "n": 7}))
+        .collect();
+    let mut body = serde_json::json!({"status": "OK", "results": results});
+    if let Some(next) = next_url {
+        body["next_url"] = serde_json::json!(next);
+    }
+    body.to_string()
+}
+
+/// Twelve Data time_series body with one bar per datetime and an optional `next_page_token`
+pub fn twelvedata_page(datetimes: &[&str], next_page_token: Option<&str>) -> String {
+    let values: Vec<_> = datetimes
+        .iter()
+        .map(|d| serde_json::json!({"datetime": d, "open": "10.0", "high": "11.0", "low": "9.5", "close": "10.5", "volume": "100"}))
+        .collect();
+    let mut body = serde_json::json!({"status": "ok", "values": values});
+    if let Some(token) = next_page_token {
+        body["next_page_token"] = serde_json::json!(token);
+    }
+    body.to_string()
+}
+
+/// Fresh scratch directory for one test
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `download` against `server` from inside `dir`, with millisecond retry
+/// backoff and no rate limiting unless `args` sets one
+pub fn run(server: &MockServer, dir: &PathBuf, args: &[&str]) -> Output {
+    run_command(server, dir, "download", args)
+}
+
+/// Run `subcommand` against `server` from inside `dir`, like [`run`]
+pub fn run_command(server: &MockServer, dir: &PathBuf, subcommand: &str, args: &[&str]) -> Output {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin("market-data-downloader"));
+    cmd.current_dir(dir)
+        .env_remove("POLYGON_BASE_URL")
+        .env_remove("TWELVEDATA_BASE_URL")
+        .arg(subcommand)
+        .args(["--apikey", "TESTKEY", "--base-url", &server.url()])
+        .args(["--retry-base-delay-ms", "10"]);
+    if !args.contains(&"--rate-limit") {
+        cmd.args(["--rate-limit", "unlimited"]);
+    }
+    cmd.args(args).output().expect("failed to run binary")
+}
+
+/// Run an offline `subcommand` (one that makes no requests) from inside `dir`
+pub fn run_offline(dir: &PathBuf, subcommand: &str, args: &[&str]) -> Output {
+    Command::new(assert_cmd::cargo::cargo_bin("market-data-downloader"))
+        .current_dir(dir)
+        .arg(subcommand)
+        .args(args)
+        .output()
+        .expect("failed to run binary")
+}
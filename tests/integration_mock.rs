@@ -0,0 +1,2164 @@
+//! Offline end-to-end tests against a local stand-in for the provider APIs.
+
+mod common;
+
+use std::fs;
+use std::io::Read;
+
+use common::{
+    MockServer, Response, polygon_page, run, run_command, run_offline, scratch_dir, twelvedata_page,
+};
+
+// 2024-02-01 14:30:00 UTC
+const T0: i64 = 1706797800000;
+const MIN: i64 = 60_000;
+const POLYGON_PATH: &str = "/v2/aggs/ticker/AAPL/range/1/minute/2024-02-01/2024-02-01";
+
+fn stderr(out: &std::process::Output) -> String {
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+#[test]
+fn polygon_follows_next_url_chain_into_csv() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &["apiKey=TESTKEY"],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], Some(&next)))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &["apiKey=TESTKEY"],
+        vec![Response::json(polygon_page(&[T0 + 2 * MIN], None))],
+    );
+    let dir = scratch_dir("polygon_follows_next_url_chain_into_csv");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let lines: Vec<_> = data.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "ticker,timestamp,open,high,low,close,volume",
+            "AAPL,2024-02-01 14:30:00,10.00,11.00,9.50,10.50,100.00",
+            "AAPL,2024-02-01 14:31:00,10.00,11.00,9.50,10.50,100.00",
+            "AAPL,2024-02-01 14:32:00,10.00,11.00,9.50,10.50,100.00",
+        ]
+    );
+    // The second page is fetched with the API key re-attached
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].starts_with("/v2/aggs/cursor/page2?"));
+    assert!(requests[1].contains("apiKey=TESTKEY"));
+}
+
+#[test]
+fn polygon_json_output_is_a_valid_array_across_pages() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0], Some(&next)))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(&[T0 + MIN], None))],
+    );
+    let dir = scratch_dir("polygon_json_output_is_a_valid_array_across_pages");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--format",
+            "json",
+            "--out",
+            "aapl.json",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+
+    let data = fs::read_to_string(dir.join("aapl.json")).unwrap();
+    let rows: Vec<serde_json::Value> = serde_json::from_str(&data).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["timestamp"], "2024-02-01 14:30:00");
+    assert_eq!(rows[1]["timestamp"], "2024-02-01 14:31:00");
+    assert_eq!(rows[1]["vw"], 10.25);
+    assert_eq!(rows[1]["n"], 7);
+}
+
+#[test]
+fn polygon_parquet_output_has_a_row_group_per_page() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], Some(&next)))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(&[T0 + 2 * MIN], None))],
+    );
+    let dir = scratch_dir("polygon_parquet_output_has_a_row_group_per_page");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--format",
+            "parquet",
+            "--out",
+            "aapl.parquet",
+            // Nothing held back, so each page is written as it arrives
+            "--reorder-window",
+            "0",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(!dir.join("aapl.parquet.checkpoint.json").exists());
+
+    let reader =
+        SerializedFileReader::new(fs::File::open(dir.join("aapl.parquet")).unwrap()).unwrap();
+    let meta = reader.metadata();
+    assert_eq!(meta.num_row_groups(), 2);
+    assert_eq!(meta.file_metadata().num_rows(), 3);
+    let columns: Vec<_> = meta
+        .file_metadata()
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            "ticker",
+            "timestamp",
+            "open",
+            "high",
+            "low",
+            "close",
+            "volume",
+            "vw",
+            "n"
+        ]
+    );
+}
+
+#[test]
+fn polygon_arrow_output_has_a_batch_per_page() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], Some(&next)))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(&[T0 + 2 * MIN], None))],
+    );
+    let dir = scratch_dir("polygon_arrow_output_has_a_batch_per_page");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--format",
+            "arrow",
+            "--reorder-window",
+            "0",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+
+    let path = dir.join("output/AAPL_2024-02-01_2024-02-01.arrow");
+    let reader =
+        arrow_ipc::reader::FileReader::try_new(fs::File::open(path).unwrap(), None).unwrap();
+    assert_eq!(reader.num_batches(), 2);
+    let rows: usize = reader.map(|b| b.unwrap().num_rows()).sum();
+    assert_eq!(rows, 3);
+}
+
+#[test]
+fn polygon_empty_results_leave_no_file() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[], None))],
+    );
+    let dir = scratch_dir("polygon_empty_results_leave_no_file");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(stderr(&out).contains("No data returned"));
+    assert!(!dir.join("aapl.csv").exists());
+}
+
+#[test]
+fn polygon_split_by_day_writes_one_file_per_day() {
+    let server = MockServer::start();
+    let day2 = T0 + 24 * 60 * MIN;
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/1/minute/2024-02-01/2024-02-02",
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN, day2], None))],
+    );
+    let dir = scratch_dir("polygon_split_by_day_writes_one_file_per_day");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-02",
+            "--split-by-day",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+
+    let first = fs::read_to_string(dir.join("output/2024/02/AAPL_2024-02-01.csv")).unwrap();
+    let second = fs::read_to_string(dir.join("output/2024/02/AAPL_2024-02-02.csv")).unwrap();
+    assert_eq!(first.lines().count(), 3);
+    assert_eq!(second.lines().count(), 2);
+}
+
+#[test]
+fn split_by_day_resume_keeps_the_interrupted_day() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    // Feb 1 spans both pages; the second one fails once
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], Some(&next)))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![
+            Response::status(500, "boom"),
+            Response::json(polygon_page(&[T0 + 2 * MIN], None)),
+        ],
+    );
+    let dir = scratch_dir("split_by_day_resume_keeps_the_interrupted_day");
+    let args = [
+        "-t",
+        "AAPL",
+        "-f",
+        "2024-02-01",
+        "-T",
+        "2024-02-01",
+        "--split-by-day",
+        "--max-retries",
+        "0",
+        "--reorder-window",
+        "0",
+    ];
+
+    let out = run(&server, &dir, &args);
+    assert!(!out.status.success());
+    let day = dir.join("output/2024/02/AAPL_2024-02-01.csv");
+    assert_eq!(fs::read_to_string(&day).unwrap().lines().count(), 3);
+
+    let resumed: Vec<&str> = args.iter().copied().chain(["--resume"]).collect();
+    let out = run(&server, &dir, &resumed);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let data = fs::read_to_string(&day).unwrap();
+    let stamps: Vec<_> = data
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        stamps,
+        vec![
+            "2024-02-01 14:30:00",
+            "2024-02-01 14:31:00",
+            "2024-02-01 14:32:00"
+        ]
+    );
+}
+
+#[test]
+fn split_by_day_rerun_does_not_duplicate_rows() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], None))],
+    );
+    let dir = scratch_dir("split_by_day_rerun_does_not_duplicate_rows");
+    let day_file = dir.join("output/2024/02/AAPL_2024-02-01.csv");
+    let args = [
+        "-t",
+        "AAPL",
+        "-f",
+        "2024-02-01",
+        "-T",
+        "2024-02-01",
+        "--split-by-day",
+    ];
+
+    for _ in 0..2 {
+        let out = run(&server, &dir, &args);
+        assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+        assert_eq!(fs::read_to_string(&day_file).unwrap().lines().count(), 3);
+    }
+
+    // skip-existing leaves a hand-edited file alone
+    fs::write(&day_file, "sentinel\n").unwrap();
+    let skip: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(["--split-mode", "skip-existing"])
+        .collect();
+    let out = run(&server, &dir, &skip);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert_eq!(fs::read_to_string(&day_file).unwrap(), "sentinel\n");
+}
+
+#[test]
+fn update_split_by_day_merges_into_last_day() {
+    let server = MockServer::start();
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/1/minute/2024-02-01/2024-02-05",
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0, T0 + MIN, T0 + 2 * MIN],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("update_split_by_day_merges_into_last_day");
+    let day_file = dir.join("output/2024/02/AAPL_2024-02-01.csv");
+    fs::create_dir_all(day_file.parent().unwrap()).unwrap();
+    fs::write(
+        &day_file,
+        "ticker,timestamp,open,high,low,close,volume\n\
+         AAPL,2024-02-01 14:30:00,10.00,11.00,9.50,10.50,100.00\n",
+    )
+    .unwrap();
+
+    let out = run_command(
+        &server,
+        &dir,
+        "update",
+        &["-t", "AAPL", "--split-by-day", "-T", "2024-02-05"],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let lines: Vec<_> = fs::read_to_string(&day_file)
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[3].contains("14:32:00"));
+}
+
+#[test]
+fn polygon_403_reports_entitlement_hint() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::status(
+            403,
+            r#"{"status":"NOT_AUTHORIZED","message":"You are not entitled to this data."}"#,
+        )],
+    );
+    let dir = scratch_dir("polygon_403_reports_entitlement_hint");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(!out.status.success());
+    let err = stderr(&out);
+    assert!(err.contains("HTTP 403 Forbidden"), "stderr=\n{}", err);
+    assert!(err.contains("not entitled"), "stderr=\n{}", err);
+    assert!(!dir.join("aapl.csv").exists());
+}
+
+#[test]
+fn polygon_429_fails_after_retries_are_exhausted() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::status(
+            429,
+            r#"{"status":"ERROR","error":"rate limited"}"#,
+        )],
+    );
+    let dir = scratch_dir("polygon_429_fails_after_retries_are_exhausted");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+            "--max-retries",
+            "2",
+        ],
+    );
+    assert!(!out.status.success());
+    let err = stderr(&out);
+    assert!(err.contains("429"), "stderr=\n{}", err);
+    assert!(err.contains("after 3 attempt(s)"), "stderr=\n{}", err);
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[test]
+fn polygon_429_with_retry_after_then_success() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![
+            Response::status(429, r#"{"status":"ERROR"}"#).header("Retry-After", "0"),
+            Response::json(polygon_page(&[T0], None)),
+        ],
+    );
+    let dir = scratch_dir("polygon_429_with_retry_after_then_success");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+            "-v",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(
+        stderr(&out).contains("attempt 2/4"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    assert_eq!(data.lines().count(), 2);
+}
+
+#[test]
+fn polygon_transient_502_mid_download_keeps_earlier_pages() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0], Some(&next)))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![
+            Response::status(502, "bad gateway"),
+            Response::status(503, "unavailable"),
+            Response::json(polygon_page(&[T0 + MIN], None)),
+        ],
+    );
+    let dir = scratch_dir("polygon_transient_502_mid_download_keeps_earlier_pages");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    assert_eq!(data.lines().count(), 3);
+    assert_eq!(server.requests().len(), 4);
+}
+
+#[test]
+fn polygon_404_is_not_retried() {
+    let server = MockServer::start();
+    let dir = scratch_dir("polygon_404_is_not_retried");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("404"), "stderr=\n{}", stderr(&out));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn rate_limit_paces_pages_and_honors_quota_headers() {
+    let server = MockServer::start();
+    let next2 = format!("{}/v2/aggs/cursor/page2", server.url());
+    let next3 = format!("{}/v2/aggs/cursor/page3", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0], Some(&next2)))],
+    );
+    // The provider reports an exhausted quota that resets in one second
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![
+            Response::json(polygon_page(&[T0 + MIN], Some(&next3)))
+                .header("X-RateLimit-Remaining", "0")
+                .header("X-RateLimit-Reset", "1"),
+        ],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page3",
+        &[],
+        vec![Response::json(polygon_page(&[T0 + 2 * MIN], None))],
+    );
+    let dir = scratch_dir("rate_limit_paces_pages_and_honors_quota_headers");
+
+    let started = std::time::Instant::now();
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+            "--rate-limit",
+            "100/s",
+            "-v",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(started.elapsed() >= std::time::Duration::from_millis(900));
+    assert!(
+        stderr(&out).contains("rate limit (100/s)"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    assert_eq!(data.lines().count(), 4);
+}
+
+fn interrupted_then_resumed(format: &str, out_file: &str) -> (std::path::PathBuf, MockServer) {
+    let server = MockServer::start();
+    let next2 = format!("{}/v2/aggs/cursor/page2", server.url());
+    let next3 = format!("{}/v2/aggs/cursor/page3", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], Some(&next2)))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(&[T0 + 2 * MIN], Some(&next3)))],
+    );
+    // Page 3 fails on the first run and succeeds on the resumed one
+    server.mock(
+        "/v2/aggs/cursor/page3",
+        &[],
+        vec![
+            Response::status(500, "boom"),
+            Response::json(polygon_page(&[T0 + 3 * MIN], None)),
+        ],
+    );
+    let dir = scratch_dir(&format!("resume_{}", format));
+    let args = [
+        "-t",
+        "AAPL",
+        "-f",
+        "2024-02-01",
+        "-T",
+        "2024-02-01",
+        "--format",
+        format,
+        "--out",
+        out_file,
+        "--max-retries",
+        "0",
+    ];
+
+    let out = run(&server, &dir, &args);
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("--resume"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    let checkpoint = dir.join(format!("{}.checkpoint.json", out_file));
+    let saved = fs::read_to_string(&checkpoint).unwrap();
+    assert!(saved.contains("page3"));
+    assert!(!saved.contains("TESTKEY"), "API key leaked into checkpoint");
+
+    let resumed: Vec<&str> = args.iter().copied().chain(["--resume"]).collect();
+    let out = run(&server, &dir, &resumed);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(!checkpoint.exists());
+    // Pages 1 and 2 are not fetched again
+    let requests = server.requests();
+    assert_eq!(requests.len(), 4);
+    assert!(requests[3].starts_with("/v2/aggs/cursor/page3?"));
+    (dir, server)
+}
+
+#[test]
+fn resume_appends_csv_without_duplicates() {
+    let (dir, _server) = interrupted_then_resumed("csv", "aapl.csv");
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let lines: Vec<_> = data.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "ticker,timestamp,open,high,low,close,volume");
+    assert!(lines[4].starts_with("AAPL,2024-02-01 14:33:00"));
+}
+
+#[test]
+fn resume_completes_json_array() {
+    let (dir, _server) = interrupted_then_resumed("json", "aapl.json");
+    let data = fs::read_to_string(dir.join("aapl.json")).unwrap();
+    let rows: Vec<serde_json::Value> = serde_json::from_str(&data).unwrap();
+    let ts: Vec<_> = rows
+        .iter()
+        .map(|r| r["timestamp"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        ts,
+        vec![
+            "2024-02-01 14:30:00",
+            "2024-02-01 14:31:00",
+            "2024-02-01 14:32:00",
+            "2024-02-01 14:33:00"
+        ]
+    );
+}
+
+const DAY: i64 = 86_400_000;
+
+fn polygon_path(from: &str, to: &str) -> String {
+    format!("/v2/aggs/ticker/AAPL/range/1/minute/{}/{}", from, to)
+}
+
+#[test]
+fn auto_chunk_splits_minute_range_by_month_and_drops_boundary_duplicates() {
+    let server = MockServer::start();
+    // The January window also reports the first bar of February
+    server.mock(
+        &polygon_path("2024-01-15", "2024-01-31"),
+        &[],
+        vec![Response::json(polygon_page(&[T0 - DAY, T0], None))],
+    );
+    server.mock(
+        &polygon_path("2024-02-01", "2024-02-10"),
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], None))],
+    );
+    let dir = scratch_dir("auto_chunk_splits_minute_range_by_month");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-01-15",
+            "-T",
+            "2024-02-10",
+            "--calendar",
+            "none",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert_eq!(server.requests().len(), 2);
+
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let ts: Vec<_> = data
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(1).unwrap().to_string())
+        .collect();
+    assert_eq!(
+        ts,
+        vec![
+            "2024-01-31 14:30:00",
+            "2024-02-01 14:30:00",
+            "2024-02-01 14:31:00"
+        ]
+    );
+}
+
+#[test]
+fn concurrent_chunks_are_written_in_date_order() {
+    let server = MockServer::start();
+    for (day, offset) in [("2024-02-01", 0), ("2024-02-02", 1), ("2024-02-03", 2)] {
+        server.mock(
+            &polygon_path(day, day),
+            &[],
+            vec![Response::json(polygon_page(&[T0 + offset * DAY], None))],
+        );
+    }
+    let dir = scratch_dir("concurrent_chunks_are_written_in_date_order");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-03",
+            "--chunk",
+            "day",
+            "--chunk-concurrency",
+            "3",
+            "--calendar",
+            "none",
+            "--out",
+            "aapl.json",
+            "--format",
+            "json",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let data = fs::read_to_string(dir.join("aapl.json")).unwrap();
+    let rows: Vec<serde_json::Value> = serde_json::from_str(&data).unwrap();
+    let ts: Vec<_> = rows
+        .iter()
+        .map(|r| r["timestamp"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        ts,
+        vec![
+            "2024-02-01 14:30:00",
+            "2024-02-02 14:30:00",
+            "2024-02-03 14:30:00"
+        ]
+    );
+}
+
+#[test]
+fn resume_skips_completed_chunks() {
+    let server = MockServer::start();
+    server.mock(
+        &polygon_path("2024-02-01", "2024-02-01"),
+        &[],
+        vec![Response::json(polygon_page(&[T0], None))],
+    );
+    server.mock(
+        &polygon_path("2024-02-02", "2024-02-02"),
+        &[],
+        vec![
+            Response::status(500, "boom"),
+            Response::json(polygon_page(&[T0 + DAY], None)),
+        ],
+    );
+    let dir = scratch_dir("resume_skips_completed_chunks");
+    let args = [
+        "-t",
+        "AAPL",
+        "-f",
+        "2024-02-01",
+        "-T",
+        "2024-02-02",
+        "--chunk",
+        "day",
+        "--out",
+        "aapl.csv",
+        "--max-retries",
+        "0",
+    ];
+
+    let out = run(&server, &dir, &args);
+    assert!(!out.status.success());
+    let saved = fs::read_to_string(dir.join("aapl.csv.checkpoint.json")).unwrap();
+    assert!(saved.contains("\"next_from\": \"2024-02-02\""), "{}", saved);
+
+    let resumed: Vec<&str> = args.iter().copied().chain(["--resume"]).collect();
+    let out = run(&server, &dir, &resumed);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests[2].contains("/2024-02-02/2024-02-02?"));
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    assert_eq!(data.lines().count(), 3);
+}
+
+#[test]
+fn batch_downloads_each_ticker_and_summarizes_failures() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], None))],
+    );
+    server.mock(
+        "/v2/aggs/ticker/MSFT/range/1/minute/2024-02-01/2024-02-01",
+        &[],
+        vec![Response::json(polygon_page(&[T0], None))],
+    );
+    // No route for NOPE: the mock answers 404
+    let dir = scratch_dir("batch_downloads_each_ticker");
+    fs::write(dir.join("tickers.txt"), "MSFT\n# unknown\nNOPE\n").unwrap();
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "--tickers-file",
+            "tickers.txt",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "{ticker}.csv",
+            "--concurrency",
+            "2",
+        ],
+    );
+    assert!(!out.status.success());
+    let err = stderr(&out);
+    assert!(
+        err.contains("Downloaded 2 of 3 ticker(s)"),
+        "stderr=\n{}",
+        err
+    );
+    assert!(
+        err.contains("1 of 3 ticker(s) failed: NOPE"),
+        "stderr=\n{}",
+        err
+    );
+
+    let aapl = fs::read_to_string(dir.join("AAPL.csv")).unwrap();
+    assert_eq!(aapl.lines().count(), 3);
+    let msft = fs::read_to_string(dir.join("MSFT.csv")).unwrap();
+    assert!(msft.lines().nth(1).unwrap().starts_with("MSFT,"));
+    assert!(!dir.join("NOPE.csv").exists());
+}
+
+#[test]
+fn batch_requires_ticker_placeholder_in_out() {
+    let server = MockServer::start();
+    let dir = scratch_dir("batch_requires_ticker_placeholder_in_out");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL,MSFT",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "all.csv",
+        ],
+    );
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("{ticker}"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn resume_continues_ndjson_lines() {
+    let (dir, _server) = interrupted_then_resumed("ndjson", "aapl.ndjson");
+    let data = fs::read_to_string(dir.join("aapl.ndjson")).unwrap();
+    let ts: Vec<String> = data
+        .lines()
+        .map(|l| {
+            let row: serde_json::Value = serde_json::from_str(l).unwrap();
+            row["timestamp"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(
+        ts,
+        vec![
+            "2024-02-01 14:30:00",
+            "2024-02-01 14:31:00",
+            "2024-02-01 14:32:00",
+            "2024-02-01 14:33:00"
+        ]
+    );
+}
+
+#[test]
+fn resume_appends_to_gzipped_csv() {
+    let (dir, _server) = interrupted_then_resumed("csv", "aapl.csv.gz");
+    let mut data = String::new();
+    flate2::read::MultiGzDecoder::new(fs::File::open(dir.join("aapl.csv.gz")).unwrap())
+        .read_to_string(&mut data)
+        .unwrap();
+    let lines: Vec<_> = data.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "ticker,timestamp,open,high,low,close,volume");
+    assert!(lines[4].starts_with("AAPL,2024-02-01 14:33:00"));
+}
+
+#[test]
+fn resample_aggregates_bars_across_pages() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0, T0 + MIN, T0 + 14 * MIN],
+            Some(&next),
+        ))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0 + 15 * MIN, T0 + 16 * MIN],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("resample_aggregates_bars_across_pages");
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--resample",
+            "15m",
+            "--format",
+            "ndjson",
+            "--out",
+            "aapl.ndjson",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    // Resampled output has no checkpoint to resume from
+    assert!(!dir.join("aapl.ndjson.checkpoint.json").exists());
+
+    let data = fs::read_to_string(dir.join("aapl.ndjson")).unwrap();
+    let rows: Vec<serde_json::Value> = data
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["timestamp"], "2024-02-01 14:30:00");
+    assert_eq!(rows[0]["volume"], 300.0);
+    assert_eq!(rows[0]["n"], 21);
+    assert_eq!(rows[0]["vw"], 10.25);
+    assert_eq!(rows[1]["timestamp"], "2024-02-01 14:45:00");
+    assert_eq!(rows[1]["volume"], 200.0);
+}
+
+#[test]
+fn sqlite_batch_and_rerun_upsert_into_one_table() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![
+            Response::json(polygon_page(&[T0, T0 + MIN], None)),
+            Response::json(polygon_page(&[T0 + MIN, T0 + 2 * MIN], None)),
+        ],
+    );
+    server.mock(
+        "/v2/aggs/ticker/MSFT/range/1/minute/2024-02-01/2024-02-01",
+        &[],
+        vec![Response::json(polygon_page(&[T0], None))],
+    );
+    let dir = scratch_dir("sqlite_batch_and_rerun_upsert_into_one_table");
+    let args = [
+        "-t",
+        "AAPL,MSFT",
+        "-f",
+        "2024-02-01",
+        "-T",
+        "2024-02-01",
+        "--format",
+        "sqlite",
+        "--out",
+        "bars.db",
+    ];
+
+    // The second run sees an overlapping AAPL page
+    for _ in 0..2 {
+        let out = run(&server, &dir, &args);
+        assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    }
+
+    let conn = rusqlite::Connection::open(dir.join("bars.db")).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT ticker, provider, granularity, COUNT(*), MAX(ts) FROM bars GROUP BY ticker ORDER BY ticker")
+        .unwrap();
+    let rows: Vec<(String, String, String, i64, i64)> = stmt
+        .query_map([], |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+        })
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (
+                "AAPL".into(),
+                "polygon".into(),
+                "minute".into(),
+                3,
+                T0 + 2 * MIN
+            ),
+            ("MSFT".into(), "polygon".into(), "minute".into(), 1, T0),
+        ]
+    );
+}
+
+#[test]
+fn sqlite_keeps_resampled_bars_apart_from_downloaded_ones() {
+    let server = MockServer::start();
+    let times: Vec<i64> = (0..30).map(|i| T0 + i * MIN).collect();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&times, None))],
+    );
+    let dir = scratch_dir("sqlite_keeps_resampled_bars_apart_from_downloaded_ones");
+    let args = [
+        "-t",
+        "AAPL",
+        "-f",
+        "2024-02-01",
+        "-T",
+        "2024-02-01",
+        "--format",
+        "sqlite",
+        "--out",
+        "bars.db",
+    ];
+    let out = run(&server, &dir, &args);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let resampled: Vec<&str> = args.iter().copied().chain(["--resample", "15m"]).collect();
+    let out = run(&server, &dir, &resampled);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+
+    let conn = rusqlite::Connection::open(dir.join("bars.db")).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT granularity, COUNT(*), SUM(volume) FROM bars GROUP BY granularity ORDER BY granularity")
+        .unwrap();
+    let rows: Vec<(String, i64, f64)> = stmt
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    // The 1-minute rows at :30 and :45 are left as they were
+    assert_eq!(
+        rows,
+        vec![
+            ("15minute".into(), 2, 3000.0),
+            ("minute".into(), 30, 3000.0)
+        ]
+    );
+}
+
+#[test]
+fn update_appends_only_newer_bars_to_csv() {
+    let server = MockServer::start();
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/1/minute/2024-02-01/2024-02-05",
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0, T0 + MIN, T0 + 2 * MIN, T0 + 3 * MIN],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("update_appends_only_newer_bars_to_csv");
+    fs::write(
+        dir.join("aapl.csv"),
+        "ticker,timestamp,open,high,low,close,volume\n\
+         AAPL,2024-02-01 14:30:00,10.00,11.00,9.50,10.50,100.00\n\
+         AAPL,2024-02-01 14:31:00,10.00,11.00,9.50,10.50,100.00\n",
+    )
+    .unwrap();
+
+    let out = run_command(
+        &server,
+        &dir,
+        "update",
+        &["-t", "AAPL", "--out", "aapl.csv", "-T", "2024-02-05"],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(
+        stderr(&out).contains("Appended 2 bar(s)"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let stamps: Vec<_> = data
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        stamps,
+        vec![
+            "2024-02-01 14:30:00",
+            "2024-02-01 14:31:00",
+            "2024-02-01 14:32:00",
+            "2024-02-01 14:33:00"
+        ]
+    );
+}
+
+#[test]
+fn update_rejects_timestamps_it_cannot_read() {
+    let server = MockServer::start();
+    let dir = scratch_dir("update_rejects_timestamps_it_cannot_read");
+    // Written with --timestamp-format custom --timestamp-pattern "%d/%m/%Y %H:%M"
+    fs::write(
+        dir.join("aapl.csv"),
+        "ticker,timestamp,open,high,low,close,volume\n\
+         AAPL,01/02/2024 14:30,10.00,11.00,9.50,10.50,100.00\n",
+    )
+    .unwrap();
+
+    let out = run_command(
+        &server,
+        &dir,
+        "update",
+        &["-t", "AAPL", "--out", "aapl.csv", "-T", "2024-02-05"],
+    );
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("None of the 1 timestamp(s) in aapl.csv could be read"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    // Nothing is downloaded again from the start
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn update_extends_json_array() {
+    let server = MockServer::start();
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/1/minute/2024-02-01/2024-02-05",
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], None))],
+    );
+    let dir = scratch_dir("update_extends_json_array");
+    fs::write(
+        dir.join("aapl.json"),
+        r#"[{"timestamp":"2024-02-01 14:30:00","open":10.0,"high":11.0,"low":9.5,"close":10.5,"volume":100.0,"vw":null,"n":null}]"#,
+    )
+    .unwrap();
+
+    let out = run_command(
+        &server,
+        &dir,
+        "update",
+        &["-t", "AAPL", "--out", "aapl.json", "-T", "2024-02-05"],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let rows: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(dir.join("aapl.json")).unwrap()).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1]["timestamp"], "2024-02-01 14:31:00");
+}
+
+#[test]
+fn update_extends_zstd_ndjson() {
+    let server = MockServer::start();
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/1/minute/2024-02-01/2024-02-05",
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], None))],
+    );
+    let dir = scratch_dir("update_extends_zstd_ndjson");
+    let existing = r#"{"timestamp":"2024-02-01 14:30:00","open":10.0,"high":11.0,"low":9.5,"close":10.5,"volume":100.0,"vw":null,"n":null}"#;
+    fs::write(
+        dir.join("aapl.ndjson.zst"),
+        zstd::encode_all(format!("{}\n", existing).as_bytes(), 0).unwrap(),
+    )
+    .unwrap();
+
+    let out = run_command(
+        &server,
+        &dir,
+        "update",
+        &["-t", "AAPL", "--out", "aapl.ndjson.zst", "-T", "2024-02-05"],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(
+        stderr(&out).contains("Appended 1 bar(s)"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    let data = zstd::decode_all(fs::File::open(dir.join("aapl.ndjson.zst")).unwrap()).unwrap();
+    let lines: Vec<_> = std::str::from_utf8(&data).unwrap().lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains("2024-02-01 14:31:00"));
+}
+
+#[test]
+fn compressed_json_cannot_be_updated() {
+    let server = MockServer::start();
+    let dir = scratch_dir("compressed_json_cannot_be_updated");
+    let out = run_command(
+        &server,
+        &dir,
+        "update",
+        &["-t", "AAPL", "--out", "aapl.json.gz", "-T", "2024-02-05"],
+    );
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("compressed Json"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn timestamps_in_exchange_time_are_read_back_by_update() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + MIN], None))],
+    );
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/1/minute/2024-02-01/2024-02-05",
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0, T0 + MIN, T0 + 2 * MIN],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("timestamps_in_exchange_time_are_read_back_by_update");
+    let style = [
+        "--tz",
+        "America/New_York",
+        "--timestamp-format",
+        "iso8601",
+        "--out",
+        "aapl.csv",
+    ];
+    let download: Vec<&str> = ["-t", "AAPL", "-f", "2024-02-01", "-T", "2024-02-01"]
+        .into_iter()
+        .chain(style)
+        .collect();
+    let out = run(&server, &dir, &download);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+
+    let update: Vec<&str> = ["-t", "AAPL", "-T", "2024-02-05"]
+        .into_iter()
+        .chain(style)
+        .collect();
+    let out = run_command(&server, &dir, "update", &update);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(
+        stderr(&out).contains("Appended 1 bar(s)"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let stamps: Vec<_> = data
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        stamps,
+        vec![
+            "2024-02-01T09:30:00-05:00",
+            "2024-02-01T09:31:00-05:00",
+            "2024-02-01T09:32:00-05:00"
+        ]
+    );
+}
+
+#[test]
+fn epoch_timestamps_are_json_numbers() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&[T0], None))],
+    );
+    let dir = scratch_dir("epoch_timestamps_are_json_numbers");
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--format",
+            "json",
+            "--timestamp-format",
+            "epoch-s",
+            "--out",
+            "aapl.json",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let rows: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(dir.join("aapl.json")).unwrap()).unwrap();
+    assert_eq!(rows[0]["timestamp"], T0 / 1000);
+}
+
+#[test]
+fn update_without_existing_data_needs_from() {
+    let server = MockServer::start();
+    let dir = scratch_dir("update_without_existing_data_needs_from");
+
+    let out = run_command(
+        &server,
+        &dir,
+        "update",
+        &["-t", "AAPL", "--out", "aapl.csv"],
+    );
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("pass --from"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn twelvedata_follows_page_tokens_into_csv() {
+    let server = MockServer::start();
+    server.mock(
+        "/time_series",
+        &["page_token=tok2"],
+        vec![Response::json(twelvedata_page(&["2025-01-03"], None))],
+    );
+    server.mock(
+        "/time_series",
+        &["symbol=AAPL", "interval=1day", "apikey=TESTKEY"],
+        vec![Response::json(twelvedata_page(
+            &["2025-01-02"],
+            Some("tok2"),
+        ))],
+    );
+    let dir = scratch_dir("twelvedata_follows_page_tokens_into_csv");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2025-01-02",
+            "-T",
+            "2025-01-03",
+            "--granularity",
+            "day",
+            "--provider",
+            "twelvedata",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let lines: Vec<_> = data.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "ticker,timestamp,open,high,low,close,volume",
+            "AAPL,2025-01-02 00:00:00,10.00,11.00,9.50,10.50,100.00",
+            "AAPL,2025-01-03 00:00:00,10.00,11.00,9.50,10.50,100.00",
+        ]
+    );
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn polygon_multiplier_and_timespan_are_in_the_path() {
+    let server = MockServer::start();
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/5/minute/2024-02-01/2024-02-01",
+        &[],
+        vec![Response::json(polygon_page(&[T0, T0 + 5 * MIN], None))],
+    );
+    let dir = scratch_dir("polygon_multiplier_and_timespan_are_in_the_path");
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--multiplier",
+            "5",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    assert_eq!(data.lines().count(), 3);
+    assert!(data.contains("2024-02-01 14:35:00"));
+}
+
+#[test]
+fn twelvedata_maps_hour_multiplier_and_rejects_unsupported_intervals() {
+    let server = MockServer::start();
+    server.mock(
+        "/time_series",
+        &["interval=4h"],
+        vec![Response::json(twelvedata_page(
+            &["2025-01-02 08:00:00"],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("twelvedata_maps_hour_multiplier");
+    let args = |granularity: &'static str, multiplier: &'static str| {
+        [
+            "-t",
+            "AAPL",
+            "-f",
+            "2025-01-02",
+            "-T",
+            "2025-01-02",
+            "--provider",
+            "twelvedata",
+            "--granularity",
+            granularity,
+            "--multiplier",
+            multiplier,
+            "--out",
+            "aapl.csv",
+        ]
+    };
+    let out = run(&server, &dir, &args("hour", "4"));
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert_eq!(server.requests().len(), 1);
+
+    for (granularity, multiplier, message) in [
+        ("minute", "7", "supported intervals are 1min, 5min"),
+        ("quarter", "1", "no quarter bars"),
+    ] {
+        let out = run(&server, &dir, &args(granularity, multiplier));
+        assert!(!out.status.success());
+        assert!(stderr(&out).contains(message), "stderr=\n{}", stderr(&out));
+    }
+    // Unsupported intervals fail before any request is made
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn session_filter_keeps_regular_hours_only() {
+    let server = MockServer::start();
+    let hour = 60 * MIN;
+    // 07:30, 09:30, 15:59 and 16:30 in New York
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0 - 2 * hour, T0, T0 + 6 * hour + 29 * MIN, T0 + 7 * hour],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("session_filter_keeps_regular_hours_only");
+    let args = |session: &'static str, granularity: &'static str| {
+        [
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--session",
+            session,
+            "--granularity",
+            granularity,
+            "--out",
+            "aapl.csv",
+        ]
+    };
+    let out = run(&server, &dir, &args("regular", "minute"));
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let stamps: Vec<_> = data
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(1).unwrap())
+        .collect();
+    assert_eq!(stamps, vec!["2024-02-01 14:30:00", "2024-02-01 20:59:00"]);
+
+    let out = run(&server, &dir, &args("regular", "day"));
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("applies to intraday bars"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn twelvedata_error_payload_is_surfaced() {
+    let server = MockServer::start();
+    server.mock(
+        "/time_series",
+        &[],
+        vec![Response::json(
+            r#"{"code":400,"message":"**symbol** not found: NOPE","status":"error"}"#,
+        )],
+    );
+    let dir = scratch_dir("twelvedata_error_payload_is_surfaced");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "NOPE",
+            "-f",
+            "2025-01-02",
+            "-T",
+            "2025-01-02",
+            "--provider",
+            "twelvedata",
+            "--out",
+            "nope.csv",
+        ],
+    );
+    assert!(!out.status.success());
+    let err = stderr(&out);
+    assert!(err.contains("Twelve Data API error"), "stderr=\n{}", err);
+    assert!(err.contains("symbol** not found"), "stderr=\n{}", err);
+    assert!(!dir.join("nope.csv").exists());
+}
+
+#[test]
+fn calendar_skips_weekends_and_holidays_and_reports_expected_bars() {
+    let server = MockServer::start();
+    // Christmas week 2024: the 25th is a holiday, the 24th an early close
+    for (day, offset) in [
+        ("2024-12-23", 326),
+        ("2024-12-24", 327),
+        ("2024-12-26", 329),
+        ("2024-12-27", 330),
+    ] {
+        server.mock(
+            &polygon_path(day, day),
+            &[],
+            vec![Response::json(polygon_page(&[T0 + offset * DAY], None))],
+        );
+    }
+    let dir = scratch_dir("calendar_skips_weekends_and_holidays");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-12-21",
+            "-T",
+            "2024-12-29",
+            "--chunk",
+            "day",
+            "--session",
+            "regular",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert_eq!(server.requests().len(), 4);
+    // 3 full days of 390 minutes and the 13:00 close of Christmas Eve
+    assert!(
+        stderr(&out)
+            .contains("Received 4 of up to 1380 minute bar(s) expected over 4 NYSE trading day(s)"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn download_over_a_holiday_explains_missing_data() {
+    let server = MockServer::start();
+    let dir = scratch_dir("download_over_a_holiday_explains_missing_data");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-03-29",
+            "-T",
+            "2024-03-31",
+            "--out",
+            "aapl.csv",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert!(server.requests().is_empty());
+    assert!(
+        stderr(&out).contains("(no NYSE trading days: Good Friday, weekend)"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    assert!(!dir.join("aapl.csv").exists());
+}
+
+#[test]
+fn completeness_report_lists_gaps_and_repeated_bars() {
+    let server = MockServer::start();
+    // Regular session of Feb 1 without 09:35-09:37 and with 10:00 sent twice
+    let mut times: Vec<i64> = (0..390).map(|i| T0 + i * MIN).collect();
+    times.retain(|t| !(T0 + 5 * MIN..T0 + 8 * MIN).contains(t));
+    times.insert(30, T0 + 30 * MIN);
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(&times, None))],
+    );
+    let dir = scratch_dir("completeness_report_lists_gaps_and_repeated_bars");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--session",
+            "regular",
+            "--out",
+            "aapl.csv",
+            "--report",
+            "report.json",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let err = stderr(&out);
+    assert!(
+        err.contains("Received 388 of up to 390 minute bar(s) expected over 1 NYSE trading day(s)"),
+        "stderr=\n{}",
+        err
+    );
+    assert!(err.contains("2024-02-01 14:35:00 to 2024-02-01 14:38:00 (3 bar(s))"));
+    assert!(err.contains("1 repeated timestamp(s)"));
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("report.json")).unwrap()).unwrap();
+    assert_eq!(report["ticker"], "AAPL");
+    assert_eq!(report["calendar"], "nyse");
+    assert_eq!(report["missing"], 3);
+    assert_eq!(report["days"][0]["date"], "2024-02-01");
+    assert_eq!(report["days"][0]["received"], 388);
+    assert_eq!(report["gaps"][0]["start"], T0 + 5 * MIN);
+    assert_eq!(report["gaps"][0]["bars"], 3);
+    assert_eq!(report["duplicates"][0], T0 + 30 * MIN);
+    assert_eq!(report["out_of_order"].as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn completeness_report_lists_repeats_across_pages() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    // Page 2 starts again with the last bar of page 1 and brings 14:31 late
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0, T0 + 2 * MIN, T0 + 3 * MIN],
+            Some(&next),
+        ))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0 + 3 * MIN, T0 + MIN, T0 + 4 * MIN],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("completeness_report_lists_repeats_across_pages");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+            "--report",
+            "report.json",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let err = stderr(&out);
+    assert!(err.contains("1 repeated timestamp(s)"), "stderr=\n{}", err);
+    assert!(err.contains("1 out-of-order bar(s)"), "stderr=\n{}", err);
+    // The output itself holds each bar once, in order
+    assert_eq!(
+        fs::read_to_string(dir.join("aapl.csv"))
+            .unwrap()
+            .lines()
+            .count(),
+        6
+    );
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("report.json")).unwrap()).unwrap();
+    assert_eq!(report["received"], 6);
+    assert_eq!(report["duplicates"], serde_json::json!([T0 + 3 * MIN]));
+    assert_eq!(report["out_of_order"][0]["t"], T0 + MIN);
+    assert_eq!(report["out_of_order"][0]["after"], T0 + 3 * MIN);
+}
+
+#[test]
+fn validate_drops_or_rejects_impossible_bars() {
+    let mut body: serde_json::Value =
+        serde_json::from_str(&polygon_page(&[T0, T0 + MIN, T0 + 2 * MIN], None)).unwrap();
+    body["results"][1]["h"] = 9.0.into();
+    body["results"][2]["v"] = (-1.0).into();
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![
+            Response::json(body.to_string()),
+            Response::json(body.to_string()),
+        ],
+    );
+    let dir = scratch_dir("validate_drops_or_rejects_impossible_bars");
+    let args = |mode| {
+        [
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+            "--validate",
+            mode,
+        ]
+    };
+
+    let out = run(&server, &dir, &args("drop"));
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let err = stderr(&out);
+    assert!(
+        err.contains("AAPL: 2 invalid bar(s), dropped:"),
+        "stderr=\n{}",
+        err
+    );
+    assert!(err.contains("  high below low: 1 (first at 2024-02-01 14:31:00)"));
+    assert!(err.contains("  negative volume: 1 (first at 2024-02-01 14:32:00)"));
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    assert_eq!(data.lines().count(), 2);
+
+    fs::remove_file(dir.join("aapl.csv")).unwrap();
+    let out = run(&server, &dir, &args("fail"));
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("Invalid bar at 2024-02-01 14:31:00: high below low"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    assert!(!dir.join("aapl.csv").exists());
+}
+
+#[test]
+fn late_bars_are_put_in_order_across_pages_and_resumes() {
+    let server = MockServer::start();
+    let next2 = format!("{}/v2/aggs/cursor/page2", server.url());
+    let next3 = format!("{}/v2/aggs/cursor/page3", server.url());
+    // Page 2 repeats 14:32 and brings 14:31 after it; page 3 fails once
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0, T0 + 2 * MIN, T0 + 3 * MIN],
+            Some(&next2),
+        ))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0 + 2 * MIN, T0 + MIN],
+            Some(&next3),
+        ))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page3",
+        &[],
+        vec![
+            Response::status(500, "boom"),
+            Response::json(polygon_page(&[T0 + 4 * MIN], None)),
+        ],
+    );
+    let dir = scratch_dir("late_bars_are_put_in_order_across_pages_and_resumes");
+    let args = [
+        "-t",
+        "AAPL",
+        "-f",
+        "2024-02-01",
+        "-T",
+        "2024-02-01",
+        "--format",
+        "ndjson",
+        "--out",
+        "aapl.ndjson",
+        "--max-retries",
+        "0",
+    ];
+
+    let out = run(&server, &dir, &args);
+    assert!(!out.status.success());
+    // Held-back bars are saved with the checkpoint instead of being written
+    let saved = fs::read_to_string(dir.join("aapl.ndjson.checkpoint.json")).unwrap();
+    assert!(saved.contains("\"pending\""), "{}", saved);
+
+    let resumed: Vec<&str> = args.iter().copied().chain(["--resume"]).collect();
+    let out = run(&server, &dir, &resumed);
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let data = fs::read_to_string(dir.join("aapl.ndjson")).unwrap();
+    let ts: Vec<String> = data
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["timestamp"].to_string())
+        .collect();
+    assert_eq!(
+        ts,
+        vec![
+            "\"2024-02-01 14:30:00\"",
+            "\"2024-02-01 14:31:00\"",
+            "\"2024-02-01 14:32:00\"",
+            "\"2024-02-01 14:33:00\"",
+            "\"2024-02-01 14:34:00\""
+        ]
+    );
+}
+
+#[test]
+fn without_a_reorder_window_late_bars_are_dropped() {
+    let server = MockServer::start();
+    let next = format!("{}/v2/aggs/cursor/page2", server.url());
+    server.mock(
+        POLYGON_PATH,
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0 + 2 * MIN, T0],
+            Some(&next),
+        ))],
+    );
+    server.mock(
+        "/v2/aggs/cursor/page2",
+        &[],
+        vec![Response::json(polygon_page(
+            &[T0 + MIN, T0 + 3 * MIN],
+            None,
+        ))],
+    );
+    let dir = scratch_dir("without_a_reorder_window_late_bars_are_dropped");
+
+    let out = run(
+        &server,
+        &dir,
+        &[
+            "-t",
+            "AAPL",
+            "-f",
+            "2024-02-01",
+            "-T",
+            "2024-02-01",
+            "--out",
+            "aapl.csv",
+            "--reorder-window",
+            "0",
+        ],
+    );
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let data = fs::read_to_string(dir.join("aapl.csv")).unwrap();
+    let ts: Vec<_> = data
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(1).unwrap().to_string())
+        .collect();
+    // A page is still sorted on its own, but 14:31 comes after 14:32 was written
+    assert_eq!(
+        ts,
+        vec![
+            "2024-02-01 14:30:00",
+            "2024-02-01 14:32:00",
+            "2024-02-01 14:33:00"
+        ]
+    );
+    // The late bars are still reported, including the dropped one
+    let err = stderr(&out);
+    assert!(err.contains("2 out-of-order bar(s):"), "stderr=\n{}", err);
+    assert!(err.contains("2024-02-01 14:31:00 after 2024-02-01 14:32:00"));
+}
+
+#[test]
+fn adjustment_is_passed_to_each_provider() {
+    let server = MockServer::start();
+    server.mock(
+        POLYGON_PATH,
+        &["adjusted=false"],
+        vec![Response::json(polygon_page(&[T0], None))],
+    );
+    server.mock(
+        "/time_series",
+        &["symbol=AAPL", "adjust=all"],
+        vec![Response::json(twelvedata_page(&["2025-01-02"], None))],
+    );
+    let dir = scratch_dir("adjustment_is_passed_to_each_provider");
+    let args = |provider: &'static str, from: &'static str, adjustment: &'static str| {
+        [
+            "-t",
+            "AAPL",
+            "-f",
+            from,
+            "-T",
+            from,
+            "--granularity",
+            if provider == "polygon" {
+                "minute"
+            } else {
+                "day"
+            },
+            "--provider",
+            provider,
+            "--adjustment",
+            adjustment,
+            "--out",
+            "aapl.csv",
+        ]
+    };
+
+    let out = run(&server, &dir, &args("polygon", "2024-02-01", "raw"));
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let out = run(&server, &dir, &args("twelvedata", "2025-01-02", "all"));
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert_eq!(server.requests().len(), 2);
+
+    // Polygon cannot adjust for dividends; it fails before any request
+    let out = run(&server, &dir, &args("polygon", "2024-02-01", "all"));
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("Polygon adjusts for splits only"),
+        "stderr=\n{}",
+        stderr(&out)
+    );
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn adjust_command_derives_adjusted_bars_from_raw_ones() {
+    let dir = scratch_dir("adjust_command_derives_adjusted_bars_from_raw_ones");
+    fs::write(
+        dir.join("raw.csv"),
+        "ticker,timestamp,open,high,low,close,volume\n\
+         AAPL,2024-06-04 00:00:00,200,200,200,200,100\n\
+         AAPL,2024-06-03 00:00:00,198,198,198,198,100\n\
+         AAPL,2024-06-05 00:00:00,100,100,100,100,100\n\
+         AAPL,2024-06-06 00:00:00,99,99,99,99,100\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("actions.csv"),
+        "ticker,date,action,value\n\
+         AAPL,2024-06-05,split,2:1\n\
+         AAPL,2024-06-06,dividend,1\n\
+         MSFT,2024-06-04,split,10\n",
+    )
+    .unwrap();
+    let adjust = |adjustment: &'static str, out: &'static str| {
+        run_offline(
+            &dir,
+            "adjust",
+            &[
+                "raw.csv",
+                "--actions",
+                "actions.csv",
+                "-t",
+                "AAPL",
+                "--calendar",
+                "none",
+                "--adjustment",
+                adjustment,
+                "--out",
+                out,
+            ],
+        )
+    };
+    let closes = |file: &str| -> Vec<String> {
+        fs::read_to_string(dir.join(file))
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|l| l.split(',').skip(5).collect::<Vec<_>>().join(","))
+            .collect()
+    };
+
+    let out = adjust("splits", "splits.csv");
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    assert_eq!(
+        closes("splits.csv"),
+        vec![
+            "99.00,200.00",
+            "100.00,200.00",
+            "100.00,100.00",
+            "99.00,100.00"
+        ]
+    );
+
+    // The dividend scales earlier prices by 1 - 1/100
+    let out = adjust("all", "all.ndjson");
+    assert!(out.status.success(), "stderr=\n{}", stderr(&out));
+    let lines: Vec<serde_json::Value> = fs::read_to_string(dir.join("all.ndjson"))
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let closes: Vec<_> = lines.iter().map(|v| v["close"].as_f64().unwrap()).collect();
+    assert_eq!(closes, vec![98.01, 99.0, 99.0, 99.0]);
+    assert_eq!(lines[0]["volume"].as_f64(), Some(200.0));
+}